@@ -0,0 +1,21 @@
+// Generates chip8.h from the exported functions into OUT_DIR, and only replaces include/chip8.h
+// when CHIP8_UPDATE_HEADER is set, so that builds never write to the source tree on their own
+fn main() {
+    let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+    let out_dir = std::env::var("OUT_DIR").unwrap();
+    println!("cargo:rerun-if-changed=src/lib.rs");
+    println!("cargo:rerun-if-changed=cbindgen.toml");
+    println!("cargo:rerun-if-env-changed=CHIP8_UPDATE_HEADER");
+
+    let config = cbindgen::Config::from_file(format!("{}/cbindgen.toml", crate_dir)).expect("Failed reading cbindgen.toml");
+    match cbindgen::generate_with_config(&crate_dir, config) {
+        Ok(bindings) => {
+            bindings.write_to_file(format!("{}/chip8.h", out_dir));
+            if std::env::var_os("CHIP8_UPDATE_HEADER").is_some() {
+                bindings.write_to_file(format!("{}/include/chip8.h", crate_dir));
+            }
+        },
+        // The crate itself still builds without the header
+        Err(e) => println!("cargo:warning=Failed generating chip8.h : {}", e)
+    }
+}
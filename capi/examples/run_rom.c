@@ -0,0 +1,79 @@
+// Runs a rom for a number of frames and prints the screen, using the C API:
+// cc capi/examples/run_rom.c -Icapi/include -Ltarget/release -lchip8_capi -o run_rom
+#include <stdio.h>
+#include <stdlib.h>
+#include <string.h>
+
+#include "chip8.h"
+
+static int fail(Chip8 *chip8, const char *call) {
+    fprintf(stderr, "%s failed : %s\n", call, chip8_last_error(chip8));
+    chip8_free(chip8);
+    return 1;
+}
+
+int main(int argc, char **argv) {
+    if (argc < 2) {
+        fprintf(stderr, "Usage : %s <rom> [frames]\n", argv[0]);
+        return 1;
+    }
+    int frames = argc > 2 ? atoi(argv[2]) : 300;
+
+    FILE *rom_file = fopen(argv[1], "rb");
+    if (rom_file == NULL) {
+        perror(argv[1]);
+        return 1;
+    }
+    static uint8_t rom[0x10000];
+    size_t rom_size = fread(rom, 1, sizeof(rom), rom_file);
+    fclose(rom_file);
+
+    Chip8 *chip8 = chip8_new(NULL);
+    if (chip8_load_rom(chip8, rom, rom_size) != CHIP8_STATUS_OK) {
+        return fail(chip8, "chip8_load_rom");
+    }
+
+    // Key 5 is held for the first second
+    chip8_set_keys(chip8, 1 << 0x5);
+    for (int frame = 0; frame < frames; frame++) {
+        if (frame == 60) {
+            chip8_set_keys(chip8, 0);
+        }
+        if (chip8_run_frame(chip8) != CHIP8_STATUS_OK) {
+            return fail(chip8, "chip8_run_frame");
+        }
+    }
+
+    // A state saved and loaded again is the same
+    size_t state_size = chip8_save_state_size(chip8);
+    uint8_t *state = malloc(state_size);
+    uint8_t *reloaded_state = malloc(state_size);
+    if (chip8_save_state(chip8, state, state_size, NULL) != CHIP8_STATUS_OK) {
+        return fail(chip8, "chip8_save_state");
+    }
+    chip8_run_frame(chip8);
+    if (chip8_load_state(chip8, state, state_size) != CHIP8_STATUS_OK) {
+        return fail(chip8, "chip8_load_state");
+    }
+    chip8_save_state(chip8, reloaded_state, state_size, NULL);
+    printf("chip8 %s, save state of %zu bytes %s\n", chip8_version(), state_size,
+        memcmp(state, reloaded_state, state_size) == 0 ? "reloaded" : "differs after reloading");
+    free(state);
+    free(reloaded_state);
+
+    size_t width, height;
+    const uint8_t *pixels = chip8_get_framebuffer(chip8, &width, &height);
+    for (size_t y = 0; y < height; y++) {
+        for (size_t x = 0; x < width; x++) {
+            putchar(pixels[y * width + x] ? '#' : ' ');
+        }
+        putchar('\n');
+    }
+
+    Chip8Registers registers;
+    chip8_get_registers(chip8, &registers);
+    printf("PC %#06x I %#06x V0 %#04x\n", registers.program_counter, registers.index_register, registers.v[0]);
+
+    chip8_free(chip8);
+    return 0;
+}
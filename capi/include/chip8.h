@@ -0,0 +1,156 @@
+/* C API of the chip8 emulator, link with libchip8_capi */
+
+#ifndef CHIP8_H
+#define CHIP8_H
+
+/* Generated by cbindgen from capi/src/lib.rs, don't edit it by hand */
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+typedef enum Chip8Status {
+  CHIP8_STATUS_OK = 0,
+  CHIP8_STATUS_INVALID_ARGUMENT = 1,
+  CHIP8_STATUS_NO_ROM = 2,
+  CHIP8_STATUS_INVALID_ROM = 3,
+  CHIP8_STATUS_EXECUTION_ERROR = 4,
+  CHIP8_STATUS_INVALID_STATE = 5,
+  CHIP8_STATUS_BUFFER_TOO_SMALL = 6,
+} Chip8Status;
+
+typedef struct Chip8 Chip8;
+
+typedef struct Chip8Registers {
+  uint16_t program_counter;
+  uint16_t index_register;
+  uint8_t v[16];
+  uint8_t delay_timer;
+  uint8_t sound_timer;
+  uint8_t stack_depth;
+} Chip8Registers;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// The version of the library, as a static string
+const char *chip8_version(void);
+
+// Creates an emulator with the quirks of a profile (chip8, modern, schip or xochip), NULL uses the default profile.
+// Returns NULL when the profile is unknown, the emulator is freed with chip8_free.
+//
+// # Safety
+//
+// `quirks_profile` must be NULL or a nul-terminated string, valid for the duration of the call.
+struct Chip8 *chip8_new(const char *quirks_profile);
+
+// # Safety
+//
+// `chip8` must be NULL or an emulator created by `chip8_new` that no other thread uses, it can't be used after this call.
+void chip8_free(struct Chip8 *chip8);
+
+// The message of the last failed call, valid until the next call on the same emulator
+//
+// # Safety
+//
+// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+const char *chip8_last_error(const struct Chip8 *chip8);
+
+// Loads a rom and restarts the machine, the rom is copied
+//
+// # Safety
+//
+// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+// `rom` must be NULL or valid for reads of `size` bytes for the duration of the call.
+enum Chip8Status chip8_load_rom(struct Chip8 *chip8,
+                                const uint8_t *rom,
+                                size_t size);
+
+// Restarts the loaded rom
+//
+// # Safety
+//
+// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+enum Chip8Status chip8_reset(struct Chip8 *chip8);
+
+// # Safety
+//
+// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+enum Chip8Status chip8_set_instructions_per_frame(struct Chip8 *chip8,
+                                                  uint32_t instructions_per_frame);
+
+// Sets the pressed keys, bit N of keys is chip8 key N, they stay pressed until the next call
+//
+// # Safety
+//
+// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+enum Chip8Status chip8_set_keys(struct Chip8 *chip8,
+                                uint16_t keys);
+
+// Runs the instructions of one frame (1/60 of a second) and ticks the timers
+//
+// # Safety
+//
+// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+enum Chip8Status chip8_run_frame(struct Chip8 *chip8);
+
+// The screen as width * height bytes in row major order, 1 for a lit pixel and 0 otherwise.
+// The width and height change when SUPER-CHIP roms switch resolution, the pointer is valid until the next call on the emulator.
+// Returns NULL when no rom is loaded.
+//
+// # Safety
+//
+// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+// `width` and `height` must each be NULL or point to a writable `size_t`.
+const uint8_t *chip8_get_framebuffer(struct Chip8 *chip8,
+                                     size_t *width,
+                                     size_t *height);
+
+// Whether the buzzer should be playing
+//
+// # Safety
+//
+// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+bool chip8_is_sound_active(const struct Chip8 *chip8);
+
+// # Safety
+//
+// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+// `registers` must be NULL or point to a writable `Chip8Registers`.
+enum Chip8Status chip8_get_registers(struct Chip8 *chip8,
+                                     struct Chip8Registers *registers);
+
+// The size of the buffer needed by chip8_save_state, it doesn't change while the same rom is loaded
+//
+// # Safety
+//
+// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+size_t chip8_save_state_size(const struct Chip8 *chip8);
+
+// Writes the state of the machine into buffer, and its size into written when it isn't NULL
+//
+// # Safety
+//
+// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+// `buffer` must be NULL or valid for writes of `size` bytes, and `written` NULL or point to a writable `size_t`.
+enum Chip8Status chip8_save_state(struct Chip8 *chip8,
+                                  uint8_t *buffer,
+                                  size_t size,
+                                  size_t *written);
+
+// Loads a state saved by chip8_save_state, the machine is left unchanged when it fails
+//
+// # Safety
+//
+// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+// `state` must be NULL or valid for reads of `size` bytes for the duration of the call.
+enum Chip8Status chip8_load_state(struct Chip8 *chip8,
+                                  const uint8_t *state,
+                                  size_t size);
+
+#ifdef __cplusplus
+} // extern "C"
+#endif // __cplusplus
+
+#endif /* CHIP8_H */
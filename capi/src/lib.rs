@@ -0,0 +1,383 @@
+use chip8::config::Config;
+use chip8::cpu::CPU;
+use chip8::errors::Chip8Error;
+use chip8::keypad::Keypad;
+use chip8::memory::Memory;
+use chip8::quirks::Quirks;
+use chip8::state::MachineState;
+
+use std::ffi::{CStr, CString};
+use std::os::raw::c_char;
+use std::ptr;
+use std::slice;
+
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Chip8Status {
+    Ok = 0,
+    InvalidArgument = 1,
+    // Called before a rom was loaded
+    NoRom = 2,
+    InvalidRom = 3,
+    // An instruction failed, the machine stays stopped until it is reset or a state is loaded
+    ExecutionError = 4,
+    InvalidState = 5,
+    BufferTooSmall = 6,
+}
+
+#[repr(C)]
+pub struct Chip8Registers {
+    pub program_counter: u16,
+    pub index_register: u16,
+    pub v: [u8; 16],
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub stack_depth: u8,
+}
+
+// An emulator instance, only used through pointers returned by chip8_new
+pub struct Chip8 {
+    quirks: Quirks,
+    instructions_per_frame: u32,
+    rom_content: Vec<u8>,
+    cpu: Option<CPU>,
+    keypad: Keypad,
+    stopped: bool,
+    // One byte per pixel, handed out by chip8_get_framebuffer
+    framebuffer: Vec<u8>,
+    last_error: CString,
+}
+
+impl Chip8 {
+    fn fail(&mut self, status: Chip8Status, message: String) -> Chip8Status {
+        self.last_error = CString::new(message.replace('\0', " ")).unwrap_or_default();
+        return status;
+    }
+
+    fn load_rom(&mut self, rom_content: Vec<u8>) -> Chip8Status {
+        match Memory::new_from_rom(rom_content.clone(), self.quirks.memory_size, self.quirks.address_policy) {
+            Ok(memory) => {
+                self.cpu = Some(CPU::new(memory, self.quirks));
+                self.rom_content = rom_content;
+                self.stopped = false;
+                return Chip8Status::Ok;
+            },
+            Err(e) => return self.fail(Chip8Status::InvalidRom, e.to_string())
+        }
+    }
+
+    fn run_frame(&mut self) -> Chip8Status {
+        if self.stopped {
+            return self.fail(Chip8Status::ExecutionError, String::from("The machine is stopped after an error, reset it or load a state"));
+        }
+        let cpu = match self.cpu.as_mut() {
+            Some(cpu) => cpu,
+            None => return self.fail(Chip8Status::NoRom, String::from("No rom is loaded"))
+        };
+
+        let mut executed = 0;
+        let mut result: Result<(), Chip8Error> = Ok(());
+        while executed < self.instructions_per_frame {
+            match cpu.step(&self.keypad, self.instructions_per_frame - executed) {
+                Ok(count) => executed += count,
+                Err(e) => {
+                    result = Err(e);
+                    break;
+                }
+            }
+        }
+        cpu.tick_timers();
+
+        if let Err(e) = result {
+            self.stopped = true;
+            return self.fail(Chip8Status::ExecutionError, e.to_string());
+        }
+        return Chip8Status::Ok;
+    }
+}
+
+/// The version of the library, as a static string
+#[no_mangle]
+pub extern "C" fn chip8_version() -> *const c_char {
+    return concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char;
+}
+
+/// Creates an emulator with the quirks of a profile (chip8, modern, schip or xochip), NULL uses the default profile.
+/// Returns NULL when the profile is unknown, the emulator is freed with chip8_free.
+///
+/// # Safety
+///
+/// `quirks_profile` must be NULL or a nul-terminated string, valid for the duration of the call.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_new(quirks_profile: *const c_char) -> *mut Chip8 {
+    let default_config = Config::default();
+    let quirks = match quirks_profile.is_null() {
+        true => default_config.quirks(),
+        false => match CStr::from_ptr(quirks_profile).to_str() {
+            Ok(profile) => Quirks::from_profile(profile),
+            Err(_) => return ptr::null_mut()
+        }
+    };
+
+    match quirks {
+        Ok(quirks) => {
+            let chip8 = Chip8{quirks: quirks, instructions_per_frame: default_config.cpu.instructions_per_frame, rom_content: Vec::new(),
+                cpu: None, keypad: Keypad::new(), stopped: false, framebuffer: Vec::new(), last_error: CString::default()};
+            return Box::into_raw(Box::new(chip8));
+        },
+        Err(_) => return ptr::null_mut()
+    }
+}
+
+/// # Safety
+///
+/// `chip8` must be NULL or an emulator created by `chip8_new` that no other thread uses, it can't be used after this call.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_free(chip8: *mut Chip8) {
+    if !chip8.is_null() {
+        drop(Box::from_raw(chip8));
+    }
+}
+
+/// The message of the last failed call, valid until the next call on the same emulator
+///
+/// # Safety
+///
+/// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+#[no_mangle]
+// cbindgen can't parse c"" literals yet
+#[allow(clippy::manual_c_str_literals)]
+pub unsafe extern "C" fn chip8_last_error(chip8: *const Chip8) -> *const c_char {
+    match chip8.as_ref() {
+        Some(chip8) => return chip8.last_error.as_ptr(),
+        None => return b"Emulator is NULL\0".as_ptr() as *const c_char
+    }
+}
+
+/// Loads a rom and restarts the machine, the rom is copied
+///
+/// # Safety
+///
+/// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+/// `rom` must be NULL or valid for reads of `size` bytes for the duration of the call.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom(chip8: *mut Chip8, rom: *const u8, size: usize) -> Chip8Status {
+    let chip8 = match chip8.as_mut() {
+        Some(chip8) => chip8,
+        None => return Chip8Status::InvalidArgument
+    };
+    if rom.is_null() {
+        return chip8.fail(Chip8Status::InvalidArgument, String::from("Rom is NULL"));
+    }
+
+    return chip8.load_rom(slice::from_raw_parts(rom, size).to_vec());
+}
+
+/// Restarts the loaded rom
+///
+/// # Safety
+///
+/// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_reset(chip8: *mut Chip8) -> Chip8Status {
+    let chip8 = match chip8.as_mut() {
+        Some(chip8) => chip8,
+        None => return Chip8Status::InvalidArgument
+    };
+    if chip8.cpu.is_none() {
+        return chip8.fail(Chip8Status::NoRom, String::from("No rom is loaded"));
+    }
+
+    let rom_content = chip8.rom_content.clone();
+    return chip8.load_rom(rom_content);
+}
+
+/// # Safety
+///
+/// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_instructions_per_frame(chip8: *mut Chip8, instructions_per_frame: u32) -> Chip8Status {
+    let chip8 = match chip8.as_mut() {
+        Some(chip8) => chip8,
+        None => return Chip8Status::InvalidArgument
+    };
+    if instructions_per_frame == 0 {
+        return chip8.fail(Chip8Status::InvalidArgument, String::from("Instructions per frame must be positive"));
+    }
+
+    chip8.instructions_per_frame = instructions_per_frame;
+    return Chip8Status::Ok;
+}
+
+/// Sets the pressed keys, bit N of keys is chip8 key N, they stay pressed until the next call
+///
+/// # Safety
+///
+/// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_keys(chip8: *mut Chip8, keys: u16) -> Chip8Status {
+    let chip8 = match chip8.as_mut() {
+        Some(chip8) => chip8,
+        None => return Chip8Status::InvalidArgument
+    };
+
+    for key in 0..16u8 {
+        chip8.keypad.set_pressed(key, keys & (1 << key) != 0);
+    }
+    return Chip8Status::Ok;
+}
+
+/// Runs the instructions of one frame (1/60 of a second) and ticks the timers
+///
+/// # Safety
+///
+/// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_run_frame(chip8: *mut Chip8) -> Chip8Status {
+    match chip8.as_mut() {
+        Some(chip8) => return chip8.run_frame(),
+        None => return Chip8Status::InvalidArgument
+    }
+}
+
+/// The screen as width * height bytes in row major order, 1 for a lit pixel and 0 otherwise.
+/// The width and height change when SUPER-CHIP roms switch resolution, the pointer is valid until the next call on the emulator.
+/// Returns NULL when no rom is loaded.
+///
+/// # Safety
+///
+/// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+/// `width` and `height` must each be NULL or point to a writable `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_get_framebuffer(chip8: *mut Chip8, width: *mut usize, height: *mut usize) -> *const u8 {
+    let chip8 = match chip8.as_mut() {
+        Some(chip8) => chip8,
+        None => return ptr::null()
+    };
+    let framebuffer = match chip8.cpu.as_ref() {
+        Some(cpu) => cpu.framebuffer(),
+        None => return ptr::null()
+    };
+
+    if !width.is_null() {
+        *width = framebuffer.width();
+    }
+    if !height.is_null() {
+        *height = framebuffer.height();
+    }
+    chip8.framebuffer.clear();
+    chip8.framebuffer.extend(framebuffer.pixels().iter().map(|pixel| *pixel as u8));
+    return chip8.framebuffer.as_ptr();
+}
+
+/// Whether the buzzer should be playing
+///
+/// # Safety
+///
+/// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_is_sound_active(chip8: *const Chip8) -> bool {
+    match chip8.as_ref().and_then(|chip8| chip8.cpu.as_ref()) {
+        Some(cpu) => return cpu.is_sound_active(),
+        None => return false
+    }
+}
+
+/// # Safety
+///
+/// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+/// `registers` must be NULL or point to a writable `Chip8Registers`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_get_registers(chip8: *mut Chip8, registers: *mut Chip8Registers) -> Chip8Status {
+    let chip8 = match chip8.as_mut() {
+        Some(chip8) => chip8,
+        None => return Chip8Status::InvalidArgument
+    };
+    let cpu = match (chip8.cpu.as_ref(), registers.is_null()) {
+        (_, true) => return chip8.fail(Chip8Status::InvalidArgument, String::from("Registers are NULL")),
+        (None, false) => return chip8.fail(Chip8Status::NoRom, String::from("No rom is loaded")),
+        (Some(cpu), false) => cpu
+    };
+
+    let (delay_timer, sound_timer) = cpu.timers();
+    let mut v = [0u8; 16];
+    for (register, value) in v.iter_mut().enumerate() {
+        *value = cpu.v(register);
+    }
+    *registers = Chip8Registers{program_counter: cpu.program_counter(), index_register: cpu.index_register(), v: v,
+        delay_timer: delay_timer, sound_timer: sound_timer, stack_depth: cpu.stack().frames().len() as u8};
+    return Chip8Status::Ok;
+}
+
+/// The size of the buffer needed by chip8_save_state, it doesn't change while the same rom is loaded
+///
+/// # Safety
+///
+/// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_save_state_size(chip8: *const Chip8) -> usize {
+    match chip8.as_ref().and_then(|chip8| chip8.cpu.as_ref()) {
+        Some(cpu) => return cpu.save_state().to_bytes().len(),
+        None => return 0
+    }
+}
+
+/// Writes the state of the machine into buffer, and its size into written when it isn't NULL
+///
+/// # Safety
+///
+/// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+/// `buffer` must be NULL or valid for writes of `size` bytes, and `written` NULL or point to a writable `size_t`.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_save_state(chip8: *mut Chip8, buffer: *mut u8, size: usize, written: *mut usize) -> Chip8Status {
+    let chip8 = match chip8.as_mut() {
+        Some(chip8) => chip8,
+        None => return Chip8Status::InvalidArgument
+    };
+    let state = match chip8.cpu.as_ref() {
+        Some(cpu) => cpu.save_state().to_bytes(),
+        None => return chip8.fail(Chip8Status::NoRom, String::from("No rom is loaded"))
+    };
+    if buffer.is_null() {
+        return chip8.fail(Chip8Status::InvalidArgument, String::from("Buffer is NULL"));
+    }
+    if state.len() > size {
+        return chip8.fail(Chip8Status::BufferTooSmall, format!("Save state needs {} bytes, the buffer has {}", state.len(), size));
+    }
+
+    slice::from_raw_parts_mut(buffer, state.len()).copy_from_slice(&state);
+    if !written.is_null() {
+        *written = state.len();
+    }
+    return Chip8Status::Ok;
+}
+
+/// Loads a state saved by chip8_save_state, the machine is left unchanged when it fails
+///
+/// # Safety
+///
+/// `chip8` must be NULL or an emulator created by `chip8_new` and not freed yet, that no other thread uses during the call.
+/// `state` must be NULL or valid for reads of `size` bytes for the duration of the call.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_state(chip8: *mut Chip8, state: *const u8, size: usize) -> Chip8Status {
+    let chip8 = match chip8.as_mut() {
+        Some(chip8) => chip8,
+        None => return Chip8Status::InvalidArgument
+    };
+    if state.is_null() {
+        return chip8.fail(Chip8Status::InvalidArgument, String::from("State is NULL"));
+    }
+    let cpu = match chip8.cpu.as_mut() {
+        Some(cpu) => cpu,
+        None => return chip8.fail(Chip8Status::NoRom, String::from("No rom is loaded"))
+    };
+
+    let bytes = slice::from_raw_parts(state, size);
+    match MachineState::from_bytes(bytes).and_then(|state| cpu.load_state(&state)) {
+        Ok(()) => {
+            chip8.stopped = false;
+            return Chip8Status::Ok;
+        },
+        Err(e) => return chip8.fail(Chip8Status::InvalidState, e.to_string())
+    }
+}
@@ -0,0 +1,175 @@
+// A minimal libretro frontend, runs a rom through the core without RetroArch:
+// cargo run -p chip8_libretro --example harness -- rom.ch8 [frames] [screenshot.ppm]
+use chip8_libretro::ffi::*;
+use chip8_libretro::*;
+
+use std::cell::RefCell;
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::process;
+use std::ptr;
+use std::slice;
+
+struct HarnessState {
+    frame: Vec<u32>,
+    frame_size: (usize, usize),
+    audio_frames: usize,
+    loud_audio_frames: usize,
+    options: Vec<String>,
+    // The retropad button held down, for the frames in pressed_frames
+    pressed_button: c_uint,
+    pressed_frames: std::ops::Range<usize>,
+    current_frame: usize,
+}
+
+thread_local! {
+    static STATE: RefCell<HarnessState> = const { RefCell::new(HarnessState{frame: Vec::new(), frame_size: (0, 0), audio_frames: 0, loud_audio_frames: 0,
+        options: Vec::new(), pressed_button: RETRO_DEVICE_ID_JOYPAD_A, pressed_frames: 0..0, current_frame: 0}) };
+}
+
+unsafe extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match cmd {
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT => return *(data as *const c_uint) == RETRO_PIXEL_FORMAT_XRGB8888,
+        RETRO_ENVIRONMENT_SET_VARIABLES => {
+            let mut variable = data as *const RetroVariable;
+            while !(*variable).key.is_null() {
+                let option = format!("{} = {}", CStr::from_ptr((*variable).key).to_string_lossy(), CStr::from_ptr((*variable).value).to_string_lossy());
+                STATE.with(|state| state.borrow_mut().options.push(option));
+                variable = variable.add(1);
+            }
+            return true;
+        },
+        // Every option keeps its default
+        RETRO_ENVIRONMENT_GET_VARIABLE => return false,
+        RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE => {
+            *(data as *mut bool) = false;
+            return true;
+        },
+        RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS | RETRO_ENVIRONMENT_SET_GEOMETRY => return true,
+        _ => return false
+    }
+}
+
+unsafe extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+    let pixels = slice::from_raw_parts(data as *const u32, pitch / 4 * height as usize);
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.frame = pixels.to_vec();
+        state.frame_size = (width as usize, height as usize);
+    });
+}
+
+unsafe extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+    let samples = slice::from_raw_parts(data, frames * 2);
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        state.audio_frames += 1;
+        if samples.iter().any(|sample| *sample != 0) {
+            state.loud_audio_frames += 1;
+        }
+    });
+    return frames;
+}
+
+unsafe extern "C" fn audio_sample(_left: i16, _right: i16) {}
+
+unsafe extern "C" fn input_poll() {}
+
+unsafe extern "C" fn input_state(port: c_uint, device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+    return STATE.with(|state| {
+        let state = state.borrow();
+        let is_pressed = port == 0 && device == RETRO_DEVICE_JOYPAD && id == state.pressed_button && state.pressed_frames.contains(&state.current_frame);
+        return is_pressed as i16;
+    });
+}
+
+fn run_frames(frames: usize) {
+    for _ in 0..frames {
+        retro_run();
+        STATE.with(|state| state.borrow_mut().current_frame += 1);
+    }
+}
+
+fn serialize() -> Vec<u8> {
+    let mut state = vec![0u8; retro_serialize_size()];
+    if !unsafe { retro_serialize(state.as_mut_ptr() as *mut c_void, state.len()) } {
+        eprintln!("retro_serialize failed");
+        process::exit(1);
+    }
+    return state;
+}
+
+fn write_screenshot(path: &str) {
+    STATE.with(|state| {
+        let state = state.borrow();
+        let (width, height) = state.frame_size;
+        let mut ppm = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+        for pixel in &state.frame {
+            ppm.extend_from_slice(&[(pixel >> 16) as u8, (pixel >> 8) as u8, *pixel as u8]);
+        }
+        std::fs::write(path, ppm).unwrap_or_else(|e| eprintln!("Failed writing {} : {}", path, e));
+    });
+}
+
+fn main() {
+    let args: Vec<String> = std::env::args().collect();
+    if args.len() < 2 {
+        eprintln!("Usage : {} <rom> [frames] [screenshot.ppm]", args[0]);
+        process::exit(1);
+    }
+    let rom_content = std::fs::read(&args[1]).unwrap_or_else(|e| {
+        eprintln!("Failed reading {} : {}", args[1], e);
+        process::exit(1);
+    });
+    let frames = args.get(2).and_then(|frames| frames.parse::<usize>().ok()).unwrap_or(600);
+
+    let mut system_info = RetroSystemInfo{library_name: ptr::null(), library_version: ptr::null(), valid_extensions: ptr::null(),
+        need_fullpath: false, block_extract: false};
+    unsafe { retro_get_system_info(&mut system_info) };
+    println!("Core {} {} (api version {})", unsafe { CStr::from_ptr(system_info.library_name) }.to_string_lossy(),
+        unsafe { CStr::from_ptr(system_info.library_version) }.to_string_lossy(), retro_api_version());
+
+    retro_set_environment(environment);
+    retro_set_video_refresh(video_refresh);
+    retro_set_audio_sample(audio_sample);
+    retro_set_audio_sample_batch(audio_sample_batch);
+    retro_set_input_poll(input_poll);
+    retro_set_input_state(input_state);
+    retro_init();
+    STATE.with(|state| state.borrow().options.iter().for_each(|option| println!("Option {}", option)));
+
+    let game = RetroGameInfo{path: ptr::null::<c_char>(), data: rom_content.as_ptr() as *const c_void, size: rom_content.len(), meta: ptr::null()};
+    if !unsafe { retro_load_game(&game) } {
+        eprintln!("retro_load_game failed");
+        process::exit(1);
+    }
+
+    // The A button is held for a while in the middle of the run
+    STATE.with(|state| state.borrow_mut().pressed_frames = frames / 4..frames / 4 + 10);
+    run_frames(frames / 2);
+
+    // A state saved and loaded again is the same, and keeps its size
+    let saved_state = serialize();
+    run_frames(frames / 2);
+    if !unsafe { retro_unserialize(saved_state.as_ptr() as *const c_void, saved_state.len()) } || serialize() != saved_state {
+        eprintln!("Save state round trip failed");
+        process::exit(1);
+    }
+    println!("Save state of {} bytes loaded back", saved_state.len());
+    run_frames(frames - frames / 2);
+
+    STATE.with(|state| {
+        let state = state.borrow();
+        let lit_pixels = state.frame.iter().filter(|pixel| **pixel != state.frame[0]).count();
+        println!("Ran {} frames, last frame is {}x{} with {} pixels in the foreground color", state.current_frame, state.frame_size.0,
+            state.frame_size.1, lit_pixels);
+        println!("{} audio frames, {} with the buzzer on", state.audio_frames, state.loud_audio_frames);
+    });
+
+    if let Some(screenshot_path) = args.get(3) {
+        write_screenshot(screenshot_path);
+    }
+
+    retro_unload_game();
+    retro_deinit();
+}
@@ -0,0 +1,543 @@
+#[macro_use] extern crate log;
+
+pub mod ffi;
+
+use chip8::cheats::{Cheat, CheatList};
+use chip8::config::Config;
+use chip8::consts;
+use chip8::cpu::CPU;
+use chip8::errors::Chip8Error;
+use chip8::keypad::Keypad;
+use chip8::memory::Memory;
+use chip8::palette::Palette;
+use chip8::quirks::Quirks;
+use chip8::romdb::{self, RomDatabase};
+use chip8::state::MachineState;
+
+use ffi::*;
+
+use std::cell::{Cell, RefCell};
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::ptr;
+use std::slice;
+
+const SAMPLE_RATE: u32 = 44100;
+const FRAMES_PER_SECOND: u32 = 60;
+const BUZZER_FREQUENCY: u32 = 440;
+const BUZZER_AMPLITUDE: i16 = i16::MAX / 4;
+
+const OPTION_QUIRKS: &CStr = c"chip8_quirks";
+const OPTION_SPEED: &CStr = c"chip8_instructions_per_frame";
+const OPTION_PALETTE: &CStr = c"chip8_palette";
+
+// The retropad buttons of the chip8 keys, the d-pad and face buttons follow the default controller layout
+const JOYPAD_KEYS: [(c_uint, u8, &CStr); 16] = [
+    (RETRO_DEVICE_ID_JOYPAD_UP, 0x5, c"Key 5 (Up)"),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, 0x7, c"Key 7 (Left)"),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, 0x8, c"Key 8 (Down)"),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, 0x9, c"Key 9 (Right)"),
+    (RETRO_DEVICE_ID_JOYPAD_A, 0x6, c"Key 6"),
+    (RETRO_DEVICE_ID_JOYPAD_B, 0x4, c"Key 4"),
+    (RETRO_DEVICE_ID_JOYPAD_START, 0xF, c"Key F"),
+    (RETRO_DEVICE_ID_JOYPAD_SELECT, 0x0, c"Key 0"),
+    (RETRO_DEVICE_ID_JOYPAD_X, 0x1, c"Key 1"),
+    (RETRO_DEVICE_ID_JOYPAD_Y, 0x2, c"Key 2"),
+    (RETRO_DEVICE_ID_JOYPAD_L, 0x3, c"Key 3"),
+    (RETRO_DEVICE_ID_JOYPAD_R, 0xC, c"Key C"),
+    (RETRO_DEVICE_ID_JOYPAD_L2, 0xA, c"Key A"),
+    (RETRO_DEVICE_ID_JOYPAD_R2, 0xB, c"Key B"),
+    (RETRO_DEVICE_ID_JOYPAD_L3, 0xD, c"Key D"),
+    (RETRO_DEVICE_ID_JOYPAD_R3, 0xE, c"Key E"),
+];
+
+#[derive(Clone, Copy, Default)]
+struct Callbacks {
+    environment: Option<RetroEnvironmentFn>,
+    video_refresh: Option<RetroVideoRefreshFn>,
+    audio_sample: Option<RetroAudioSampleFn>,
+    audio_sample_batch: Option<RetroAudioSampleBatchFn>,
+    input_poll: Option<RetroInputPollFn>,
+    input_state: Option<RetroInputStateFn>,
+}
+
+struct Core {
+    rom_content: Vec<u8>,
+    // Defaults with the settings of the rom database, used by the options set to auto
+    rom_config: Config,
+    cpu: CPU,
+    quirks: Quirks,
+    instructions_per_frame: u32,
+    palette: Palette,
+    // Keyboard keys of the chip8 keys, as libretro key codes
+    keyboard_keys: Vec<(c_uint, u8)>,
+    frame: Vec<u32>,
+    display_size: (usize, usize),
+    audio_samples: Vec<i16>,
+    audio_position: u32,
+    // Set when the rom stops with an error, the last frame keeps being shown
+    stopped: bool,
+    // Set by the frontend, kept across resets
+    cheats: CheatList,
+}
+
+thread_local! {
+    static CALLBACKS: Cell<Callbacks> = Cell::new(Callbacks::default());
+    static CORE: RefCell<Option<Core>> = const { RefCell::new(None) };
+}
+
+fn callbacks() -> Callbacks {
+    return CALLBACKS.with(|callbacks| callbacks.get());
+}
+
+fn set_callbacks(update: impl FnOnce(&mut Callbacks)) {
+    CALLBACKS.with(|callbacks| {
+        let mut updated = callbacks.get();
+        update(&mut updated);
+        callbacks.set(updated);
+    });
+}
+
+fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match callbacks().environment {
+        Some(environment) => unsafe { environment(cmd, data) },
+        None => false
+    }
+}
+
+// The value of a core option, None when the frontend doesn't know it
+fn option_value(key: &CStr) -> Option<String> {
+    let mut variable = RetroVariable{key: key.as_ptr(), value: ptr::null()};
+    if !environment(RETRO_ENVIRONMENT_GET_VARIABLE, &mut variable as *mut RetroVariable as *mut c_void) || variable.value.is_null() {
+        return None;
+    }
+
+    return unsafe { CStr::from_ptr(variable.value) }.to_str().ok().map(String::from);
+}
+
+// The options set to auto, or unknown to the frontend, keep the settings of the rom database
+fn quirks_option(rom_config: &Config) -> Result<Quirks, String> {
+    match option_value(OPTION_QUIRKS) {
+        Some(profile) if profile != "auto" => Quirks::from_profile(&profile),
+        _ => rom_config.quirks()
+    }
+}
+
+fn speed_option(rom_config: &Config) -> u32 {
+    match option_value(OPTION_SPEED).and_then(|speed| speed.parse::<u32>().ok()) {
+        Some(instructions_per_frame) => instructions_per_frame,
+        None => rom_config.cpu.instructions_per_frame
+    }
+}
+
+fn palette_option(rom_config: &Config) -> Result<Palette, String> {
+    match option_value(OPTION_PALETTE) {
+        Some(palette_name) if palette_name != "auto" => Palette::from_name(&palette_name).ok_or_else(|| format!("Unknown palette \"{}\"", palette_name)),
+        _ => Palette::from_config(&rom_config.palette)
+    }
+}
+
+fn rgb_to_xrgb((red, green, blue): (u8, u8, u8)) -> u32 {
+    return ((red as u32) << 16) | ((green as u32) << 8) | blue as u32;
+}
+
+fn new_cpu(rom_content: &[u8], quirks: Quirks) -> Result<CPU, Chip8Error> {
+    let memory = Memory::new_from_rom(rom_content.to_vec(), quirks.memory_size, quirks.address_policy)?;
+    return Ok(CPU::new(memory, quirks));
+}
+
+impl Core {
+    fn new(rom_content: Vec<u8>) -> Result<Core, String> {
+        let mut rom_layers = Vec::new();
+        let rom_sha1 = romdb::rom_sha1(&rom_content);
+        if let Some(matched_rom) = RomDatabase::load()?.lookup(&rom_sha1) {
+            info!("LIBRETRO | Rom {} matched \"{}\" in the rom database", rom_sha1, matched_rom.program.title);
+            rom_layers.push(matched_rom.config_layer());
+        }
+        let rom_config = Config::with_layers(&rom_layers)?;
+
+        let quirks = quirks_option(&rom_config)?;
+        let cpu = new_cpu(&rom_content, quirks).map_err(|e| e.to_string())?;
+        let keyboard_keys = consts::get_chip_to_real_key_map().iter()
+            .filter_map(|(chip_key, real_key)| real_key.chars().next().map(|key_char| (key_char.to_ascii_lowercase() as c_uint, *chip_key)))
+            .collect();
+
+        return Ok(Core{instructions_per_frame: speed_option(&rom_config), palette: palette_option(&rom_config)?, rom_content: rom_content,
+            rom_config: rom_config, cpu: cpu, quirks: quirks, keyboard_keys: keyboard_keys, frame: Vec::new(), display_size: (0, 0),
+            audio_samples: Vec::new(), audio_position: 0, stopped: false, cheats: CheatList::new()});
+    }
+
+    // The speed and palette apply right away, the quirks apply on the next reset
+    fn update_options(&mut self) {
+        self.instructions_per_frame = speed_option(&self.rom_config).max(1);
+        match palette_option(&self.rom_config) {
+            Ok(palette) => self.palette = palette,
+            Err(e) => warn!("LIBRETRO | {}", e)
+        }
+    }
+
+    fn reset(&mut self) {
+        let quirks = match quirks_option(&self.rom_config) {
+            Ok(quirks) => quirks,
+            Err(e) => {
+                warn!("LIBRETRO | {}, keeping the current quirks", e);
+                self.quirks
+            }
+        };
+
+        match new_cpu(&self.rom_content, quirks) {
+            Ok(cpu) => {
+                self.quirks = quirks;
+                self.cpu = cpu;
+                self.stopped = false;
+            },
+            Err(e) => error!("LIBRETRO | Failed resetting : {}", e)
+        }
+    }
+
+    fn keypad_state(&self, input_state: RetroInputStateFn) -> Keypad {
+        let mut keypad = Keypad::new();
+        for (button, chip_key, _) in JOYPAD_KEYS.iter() {
+            if unsafe { input_state(0, RETRO_DEVICE_JOYPAD, 0, *button) } != 0 {
+                keypad.set_pressed(*chip_key, true);
+            }
+        }
+        for (key_code, chip_key) in &self.keyboard_keys {
+            if unsafe { input_state(0, RETRO_DEVICE_KEYBOARD, 0, *key_code) } != 0 {
+                keypad.set_pressed(*chip_key, true);
+            }
+        }
+
+        return keypad;
+    }
+
+    fn run_frame(&mut self, keypad: &Keypad) {
+        if self.stopped {
+            return;
+        }
+
+        self.cheats.apply(&mut self.cpu);
+
+        let mut executed = 0;
+        while executed < self.instructions_per_frame {
+            match self.cpu.step(keypad, self.instructions_per_frame - executed) {
+                Ok(count) => executed += count,
+                Err(e) => {
+                    error!("LIBRETRO | Stopping due to {}", e);
+                    self.stopped = true;
+                    return;
+                }
+            }
+        }
+
+        self.cpu.tick_timers();
+    }
+
+    fn video_frame(&mut self) {
+        let framebuffer = self.cpu.framebuffer();
+        let size = (framebuffer.width(), framebuffer.height());
+        if size != self.display_size {
+            // SUPER-CHIP roms switch between the 64x32 and 128x64 modes
+            let mut geometry = RetroGameGeometry{base_width: size.0 as c_uint, base_height: size.1 as c_uint,
+                max_width: consts::HIRES_DISPLAY_WIDTH as c_uint, max_height: consts::HIRES_DISPLAY_HEIGHT as c_uint, aspect_ratio: 2.0};
+            environment(RETRO_ENVIRONMENT_SET_GEOMETRY, &mut geometry as *mut RetroGameGeometry as *mut c_void);
+            self.display_size = size;
+        }
+
+        let (background, foreground) = (rgb_to_xrgb(self.palette.background), rgb_to_xrgb(self.palette.foreground));
+        self.frame.clear();
+        self.frame.extend(framebuffer.pixels().iter().map(|pixel| if *pixel { foreground } else { background }));
+
+        if let Some(video_refresh) = callbacks().video_refresh {
+            unsafe { video_refresh(self.frame.as_ptr() as *const c_void, size.0 as c_uint, size.1 as c_uint, size.0 * 4) };
+        }
+    }
+
+    // A square wave while the sound timer is active
+    fn audio_frame(&mut self) {
+        let samples_per_frame = SAMPLE_RATE / FRAMES_PER_SECOND;
+        let half_period = SAMPLE_RATE / BUZZER_FREQUENCY / 2;
+        let is_sound_active = self.cpu.is_sound_active() && !self.stopped;
+
+        self.audio_samples.clear();
+        for _ in 0..samples_per_frame {
+            let sample = match is_sound_active {
+                true if (self.audio_position / half_period).is_multiple_of(2) => BUZZER_AMPLITUDE,
+                true => -BUZZER_AMPLITUDE,
+                false => 0
+            };
+            self.audio_position = self.audio_position.wrapping_add(1);
+            self.audio_samples.push(sample);
+            self.audio_samples.push(sample);
+        }
+
+        let callbacks = callbacks();
+        match (callbacks.audio_sample_batch, callbacks.audio_sample) {
+            (Some(audio_sample_batch), _) => {
+                unsafe { audio_sample_batch(self.audio_samples.as_ptr(), self.audio_samples.len() / 2) };
+            },
+            (None, Some(audio_sample)) => {
+                for stereo_sample in self.audio_samples.chunks(2) {
+                    unsafe { audio_sample(stereo_sample[0], stereo_sample[1]) };
+                }
+            },
+            (None, None) => {}
+        }
+    }
+}
+
+fn with_core<T>(default: T, action: impl FnOnce(&mut Core) -> T) -> T {
+    return CORE.with(|core| match core.borrow_mut().as_mut() {
+        Some(core) => action(core),
+        None => default
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    return RETRO_API_VERSION;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(environment_callback: RetroEnvironmentFn) {
+    set_callbacks(|callbacks| callbacks.environment = Some(environment_callback));
+
+    let variables = [
+        RetroVariable{key: OPTION_QUIRKS.as_ptr(),
+            value: c"Quirks (applied on reset); auto|chip8|modern|schip|xochip".as_ptr()},
+        RetroVariable{key: OPTION_SPEED.as_ptr(),
+            value: c"Instructions per frame; auto|10|15|20|30|50|100|200|500|1000".as_ptr()},
+        RetroVariable{key: OPTION_PALETTE.as_ptr(),
+            value: c"Palette; auto|classic|green|amber|lcd".as_ptr()},
+        RetroVariable{key: ptr::null(), value: ptr::null()},
+    ];
+    environment(RETRO_ENVIRONMENT_SET_VARIABLES, variables.as_ptr() as *mut c_void);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(video_refresh: RetroVideoRefreshFn) {
+    set_callbacks(|callbacks| callbacks.video_refresh = Some(video_refresh));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(audio_sample: RetroAudioSampleFn) {
+    set_callbacks(|callbacks| callbacks.audio_sample = Some(audio_sample));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(audio_sample_batch: RetroAudioSampleBatchFn) {
+    set_callbacks(|callbacks| callbacks.audio_sample_batch = Some(audio_sample_batch));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(input_poll: RetroInputPollFn) {
+    set_callbacks(|callbacks| callbacks.input_poll = Some(input_poll));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(input_state: RetroInputStateFn) {
+    set_callbacks(|callbacks| callbacks.input_state = Some(input_state));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    CORE.with(|core| core.borrow_mut().take());
+}
+
+/// # Safety
+///
+/// `info` must be null or point to a writable `RetroSystemInfo`. The strings written to it are static.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    if info.is_null() {
+        return;
+    }
+
+    *info = RetroSystemInfo{library_name: c"Chip8".as_ptr(),
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: c"ch8|c8|sc8|xo8".as_ptr(), need_fullpath: false, block_extract: false};
+}
+
+/// # Safety
+///
+/// `info` must be null or point to a writable `RetroSystemAvInfo`.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    if info.is_null() {
+        return;
+    }
+
+    let (width, height) = with_core((consts::DISPLAY_WIDTH, consts::DISPLAY_HEIGHT), |core| (core.cpu.framebuffer().width(), core.cpu.framebuffer().height()));
+    *info = RetroSystemAvInfo{
+        geometry: RetroGameGeometry{base_width: width as c_uint, base_height: height as c_uint, max_width: consts::HIRES_DISPLAY_WIDTH as c_uint,
+            max_height: consts::HIRES_DISPLAY_HEIGHT as c_uint, aspect_ratio: 2.0},
+        timing: RetroSystemTiming{fps: FRAMES_PER_SECOND as f64, sample_rate: SAMPLE_RATE as f64}};
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core((), |core| core.reset());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let callbacks = callbacks();
+    let mut options_updated = false;
+    environment(RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE, &mut options_updated as *mut bool as *mut c_void);
+    if let Some(input_poll) = callbacks.input_poll {
+        unsafe { input_poll() };
+    }
+
+    with_core((), |core| {
+        if options_updated {
+            core.update_options();
+        }
+
+        let keypad = match callbacks.input_state {
+            Some(input_state) => core.keypad_state(input_state),
+            None => Keypad::new()
+        };
+        core.run_frame(&keypad);
+        core.video_frame();
+        core.audio_frame();
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    return with_core(0, |core| core.cpu.save_state().to_bytes().len());
+}
+
+/// # Safety
+///
+/// `data` must be null or valid for writes of `size` bytes for the duration of the call.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    return with_core(false, |core| {
+        let state = core.cpu.save_state().to_bytes();
+        if data.is_null() || state.len() > size {
+            return false;
+        }
+
+        slice::from_raw_parts_mut(data as *mut u8, state.len()).copy_from_slice(&state);
+        return true;
+    });
+}
+
+/// # Safety
+///
+/// `data` must be null or valid for reads of `size` bytes for the duration of the call.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    if data.is_null() {
+        return false;
+    }
+
+    let bytes = slice::from_raw_parts(data as *const u8, size);
+    return with_core(false, |core| {
+        match MachineState::from_bytes(bytes).and_then(|state| core.cpu.load_state(&state)) {
+            Ok(()) => {
+                core.stopped = false;
+                return true;
+            },
+            Err(e) => {
+                error!("LIBRETRO | Failed loading the save state : {}", e);
+                return false;
+            }
+        }
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {
+    with_core((), |core| core.cheats.clear());
+}
+
+/// Codes are "<address>:<value>[?<address>=<value>]" in hex, joined with "+".
+///
+/// # Safety
+///
+/// `code` must be null or point to a nul-terminated string that stays valid for the duration of the call.
+#[no_mangle]
+pub unsafe extern "C" fn retro_cheat_set(index: c_uint, enabled: bool, code: *const c_char) {
+    if code.is_null() {
+        return;
+    }
+
+    let code = CStr::from_ptr(code).to_string_lossy().into_owned();
+    with_core((), |core| match Cheat::parse(&code, &code).and_then(|cheat| cheat.check_addresses(core.quirks.memory_size).map(|_| cheat)) {
+        Ok(mut cheat) => {
+            cheat.enabled = enabled;
+            core.cheats.set(index as usize, cheat);
+        },
+        Err(e) => warn!("LIBRETRO | Ignoring cheat {} : {}", index, e)
+    });
+}
+
+/// # Safety
+///
+/// `game` must be null or point to a valid `RetroGameInfo`, whose `data` is null or valid for reads of `size` bytes.
+/// Both only need to live for the duration of the call, the rom is copied.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+
+    let mut pixel_format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !environment(RETRO_ENVIRONMENT_SET_PIXEL_FORMAT, &mut pixel_format as *mut c_uint as *mut c_void) {
+        error!("LIBRETRO | The frontend doesn't support the XRGB8888 pixel format");
+        return false;
+    }
+
+    let mut input_descriptors: Vec<RetroInputDescriptor> = JOYPAD_KEYS.iter()
+        .map(|(button, _, description)| RetroInputDescriptor{port: 0, device: RETRO_DEVICE_JOYPAD, index: 0, id: *button,
+            description: description.as_ptr()})
+        .collect();
+    input_descriptors.push(RetroInputDescriptor{port: 0, device: 0, index: 0, id: 0, description: ptr::null()});
+    environment(RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS, input_descriptors.as_mut_ptr() as *mut c_void);
+
+    let rom_content = slice::from_raw_parts((*game).data as *const u8, (*game).size).to_vec();
+    match Core::new(rom_content) {
+        Ok(core) => {
+            CORE.with(|current_core| *current_core.borrow_mut() = Some(core));
+            return true;
+        },
+        Err(e) => {
+            error!("LIBRETRO | Failed loading the rom : {}", e);
+            return false;
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const RetroGameInfo, _num_info: usize) -> bool {
+    return false;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    CORE.with(|core| core.borrow_mut().take());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    return RETRO_REGION_NTSC;
+}
+
+// The chip8 memory isn't exposed, writes from outside would skip the decoded instruction caches
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    return ptr::null_mut();
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+    return 0;
+}
@@ -0,0 +1,25 @@
+# Plays pong with random actions, build the module first with `maturin develop` in python/
+import random
+import sys
+
+import chip8_env
+
+rom_path = sys.argv[1] if len(sys.argv) > 1 else "roms/pong.ch8"
+
+# The left paddle is moved with keys 1 and 4, pong writes the score digits of both players to 0x2F3 and 0x2F4
+env = chip8_env.Chip8VecEnv(rom_path, 4, actions=[[], [0x1], [0x4]],
+    rewards=[{"address": 0x2F3}, {"address": 0x2F4, "scale": -1}],
+    done_when=[{"address": 0x2F3, "equals": 3}, {"address": 0x2F4, "equals": 3}], seed=0)
+
+agent_random = random.Random(0)
+env.reset()
+returns = [0.0] * env.num_envs
+finished_episodes = 0
+while finished_episodes < 20:
+    frames, rewards, dones, infos = env.step([agent_random.randrange(env.action_count) for _ in range(env.num_envs)])
+    for index in range(env.num_envs):
+        returns[index] += rewards[index]
+        if dones[index]:
+            print(f"Environment {index} finished an episode of {infos[index]['steps']} steps with a return of {returns[index]}")
+            returns[index] = 0.0
+            finished_episodes += 1
@@ -0,0 +1,191 @@
+use chip8::cpu::CPU;
+use chip8::keypad::Keypad;
+use chip8::memory::Memory;
+use chip8::quirks::Quirks;
+use chip8::state::MachineState;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+
+use std::rc::Rc;
+
+// Where a reward or an end condition reads its value from
+#[derive(Debug, Clone, Copy)]
+pub enum ValueSource {
+    Memory(u16),
+    Register(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RewardMode {
+    // The change of the value during the step, for scores
+    Delta,
+    // The value itself, read after the step
+    Value,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct RewardTerm {
+    pub source: ValueSource,
+    pub scale: f64,
+    pub mode: RewardMode,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct DoneCondition {
+    pub source: ValueSource,
+    pub value: u8,
+}
+
+pub struct EnvironmentConfig {
+    pub rom_content: Vec<u8>,
+    pub quirks: Quirks,
+    pub instructions_per_frame: u32,
+    // Frames run with the same keys held for every step
+    pub frame_skip: u32,
+    // The keys held by each action, as a mask with bit N for key N
+    pub actions: Vec<u16>,
+    pub rewards: Vec<RewardTerm>,
+    pub done_conditions: Vec<DoneCondition>,
+    pub max_steps: Option<u64>,
+}
+
+pub struct StepResult {
+    pub reward: f64,
+    pub done: bool,
+    // Ended by max_steps rather than by the game
+    pub truncated: bool,
+    pub error: Option<String>,
+}
+
+// A rom running in lockstep with an agent, every run with the same seed and actions is the same
+pub struct Environment {
+    config: Rc<EnvironmentConfig>,
+    cpu: CPU,
+    // Draws the seed of every episode that isn't given one
+    seed_rng: StdRng,
+    episode_seed: u64,
+    steps: u64,
+    done: bool,
+}
+
+// Memory addresses are checked against the memory size when the environment is configured
+fn read_value(cpu: &CPU, source: ValueSource) -> Result<u8, String> {
+    match source {
+        ValueSource::Memory(address) => cpu.peek_memory(address as usize).map_err(|e| e.to_string()),
+        ValueSource::Register(register) => Ok(cpu.v(register as usize))
+    }
+}
+
+fn new_cpu(config: &EnvironmentConfig, seed: u64) -> Result<CPU, String> {
+    let memory = Memory::new_from_rom(config.rom_content.clone(), config.quirks.memory_size, config.quirks.address_policy)
+        .map_err(|e| e.to_string())?;
+    let mut cpu = CPU::new(memory, config.quirks);
+    cpu.set_seed(seed);
+    return Ok(cpu);
+}
+
+impl Environment {
+    pub fn new(config: Rc<EnvironmentConfig>, seed: u64) -> Result<Environment, String> {
+        let mut seed_rng = StdRng::seed_from_u64(seed);
+        let episode_seed = seed_rng.gen::<u64>();
+        let cpu = new_cpu(&config, episode_seed)?;
+        return Ok(Environment{config: config, cpu: cpu, seed_rng: seed_rng, episode_seed: episode_seed, steps: 0, done: false});
+    }
+
+    pub fn reset(&mut self, seed: Option<u64>) -> Result<(), String> {
+        if let Some(seed) = seed {
+            self.seed_rng = StdRng::seed_from_u64(seed);
+        }
+        self.episode_seed = self.seed_rng.gen::<u64>();
+        self.cpu = new_cpu(&self.config, self.episode_seed)?;
+        self.steps = 0;
+        self.done = false;
+        return Ok(());
+    }
+
+    pub fn step(&mut self, action: usize) -> Result<StepResult, String> {
+        if self.done {
+            return Err(String::from("Stepped a finished episode, reset it first"));
+        }
+        let keys = *self.config.actions.get(action)
+            .ok_or_else(|| format!("Action {} is out of range, there are {} actions", action, self.config.actions.len()))?;
+        let mut keypad = Keypad::new();
+        for key in 0..16u8 {
+            keypad.set_pressed(key, keys & (1 << key) != 0);
+        }
+
+        let values_before = self.config.rewards.iter().map(|reward| read_value(&self.cpu, reward.source)).collect::<Result<Vec<u8>, String>>()?;
+        let mut error = None;
+        'frames: for _ in 0..self.config.frame_skip {
+            let mut executed = 0;
+            while executed < self.config.instructions_per_frame {
+                match self.cpu.step(&keypad, self.config.instructions_per_frame - executed) {
+                    Ok(count) => executed += count,
+                    Err(e) => {
+                        error = Some(e.to_string());
+                        break 'frames;
+                    }
+                }
+            }
+            self.cpu.tick_timers();
+        }
+        self.steps += 1;
+
+        let mut reward = 0.0;
+        for (term, value_before) in self.config.rewards.iter().zip(values_before) {
+            let value = read_value(&self.cpu, term.source)? as f64;
+            reward += term.scale * match term.mode {
+                RewardMode::Delta => value - value_before as f64,
+                RewardMode::Value => value
+            };
+        }
+
+        let mut is_game_over = false;
+        for condition in &self.config.done_conditions {
+            is_game_over |= read_value(&self.cpu, condition.source)? == condition.value;
+        }
+        let truncated = !is_game_over && error.is_none() && self.config.max_steps.is_some_and(|max_steps| self.steps >= max_steps);
+        self.done = is_game_over || truncated || error.is_some();
+
+        return Ok(StepResult{reward: reward, done: self.done, truncated: truncated, error: error});
+    }
+
+    // One byte per pixel in row major order, 1 for a lit pixel
+    pub fn write_frame(&self, frame: &mut Vec<u8>) {
+        frame.extend(self.cpu.framebuffer().pixels().iter().map(|pixel| *pixel as u8));
+    }
+
+    // Height and width, like numpy shapes
+    pub fn frame_shape(&self) -> (usize, usize) {
+        return (self.cpu.framebuffer().height(), self.cpu.framebuffer().width());
+    }
+
+    pub fn cpu(&self) -> &CPU {
+        return &self.cpu;
+    }
+
+    pub fn action_count(&self) -> usize {
+        return self.config.actions.len();
+    }
+
+    pub fn steps(&self) -> u64 {
+        return self.steps;
+    }
+
+    pub fn episode_seed(&self) -> u64 {
+        return self.episode_seed;
+    }
+
+    // The random number generator isn't part of the state, a loaded state continues with the generator of this episode
+    pub fn save_state(&self) -> Vec<u8> {
+        return self.cpu.save_state().to_bytes();
+    }
+
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), String> {
+        let state = MachineState::from_bytes(bytes).map_err(|e| e.to_string())?;
+        self.cpu.load_state(&state).map_err(|e| e.to_string())?;
+        self.done = false;
+        return Ok(());
+    }
+}
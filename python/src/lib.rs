@@ -0,0 +1,279 @@
+// The method wrappers generated by pyo3 0.22 convert the returned PyErr into itself, with the span of the return type
+#![allow(clippy::useless_conversion)]
+
+mod environment;
+
+use environment::{DoneCondition, Environment, EnvironmentConfig, RewardMode, RewardTerm, StepResult, ValueSource};
+
+use chip8::config::Config;
+use chip8::quirks::Quirks;
+use chip8::romdb::{self, RomDatabase};
+
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use pyo3::types::{PyBytes, PyDict};
+
+use std::path::PathBuf;
+use std::rc::Rc;
+
+// Roms are given as their content or as a path
+fn read_rom(rom: &Bound<'_, PyAny>) -> PyResult<Vec<u8>> {
+    if let Ok(rom_content) = rom.downcast::<PyBytes>() {
+        return Ok(rom_content.as_bytes().to_vec());
+    }
+
+    let rom_path: PathBuf = rom.extract()?;
+    return std::fs::read(&rom_path).map_err(|e| PyValueError::new_err(format!("Failed reading {} : {}", rom_path.display(), e)));
+}
+
+fn parse_source(entry: &Bound<'_, PyDict>) -> PyResult<ValueSource> {
+    match (entry.get_item("address")?, entry.get_item("register")?) {
+        (Some(address), None) => Ok(ValueSource::Memory(address.extract()?)),
+        (None, Some(register)) => {
+            let register: u8 = register.extract()?;
+            if register > 0xF {
+                return Err(PyValueError::new_err(format!("Register {} doesn't exist, registers are 0 to 15", register)));
+            }
+            Ok(ValueSource::Register(register))
+        },
+        _ => Err(PyValueError::new_err(format!("{} needs either an \"address\" or a \"register\"", entry)))
+    }
+}
+
+fn parse_reward(entry: &Bound<'_, PyDict>) -> PyResult<RewardTerm> {
+    let scale = match entry.get_item("scale")? {
+        Some(scale) => scale.extract::<f64>()?,
+        None => 1.0
+    };
+    let mode = match entry.get_item("mode")?.map(|mode| mode.extract::<String>()).transpose()?.as_deref() {
+        None | Some("delta") => RewardMode::Delta,
+        Some("value") => RewardMode::Value,
+        Some(mode) => return Err(PyValueError::new_err(format!("Unknown reward mode \"{}\", expected delta or value", mode)))
+    };
+
+    return Ok(RewardTerm{source: parse_source(entry)?, scale: scale, mode: mode});
+}
+
+fn parse_done_condition(entry: &Bound<'_, PyDict>) -> PyResult<DoneCondition> {
+    let value = entry.get_item("equals")?.ok_or_else(|| PyValueError::new_err(format!("{} needs an \"equals\" value", entry)))?;
+    return Ok(DoneCondition{source: parse_source(entry)?, value: value.extract()?});
+}
+
+// Each action is the list of keys it holds, by default nothing and every single key
+fn parse_actions(actions: Option<Vec<Vec<u8>>>) -> PyResult<Vec<u16>> {
+    let actions = match actions {
+        Some(actions) => actions,
+        None => std::iter::once(Vec::new()).chain((0..16u8).map(|key| vec![key])).collect()
+    };
+    if actions.is_empty() {
+        return Err(PyValueError::new_err("At least one action is needed"));
+    }
+
+    let mut action_masks = Vec::new();
+    for keys in actions {
+        let mut mask = 0u16;
+        for key in keys {
+            if key > 0xF {
+                return Err(PyValueError::new_err(format!("Key {} doesn't exist, keys are 0 to 15", key)));
+            }
+            mask |= 1 << key;
+        }
+        action_masks.push(mask);
+    }
+    return Ok(action_masks);
+}
+
+// Quirks and speed that aren't given come from the rom database, like in the emulator
+#[allow(clippy::too_many_arguments)]
+fn environment_config(rom: &Bound<'_, PyAny>, quirks: Option<&str>, instructions_per_frame: Option<u32>, frame_skip: u32, actions: Option<Vec<Vec<u8>>>,
+    rewards: Option<Vec<Bound<'_, PyDict>>>, done_when: Option<Vec<Bound<'_, PyDict>>>, max_steps: Option<u64>) -> PyResult<Rc<EnvironmentConfig>> {
+    let rom_content = read_rom(rom)?;
+    let mut rom_layers = Vec::new();
+    if let Some(matched_rom) = RomDatabase::load().map_err(PyValueError::new_err)?.lookup(&romdb::rom_sha1(&rom_content)) {
+        rom_layers.push(matched_rom.config_layer());
+    }
+    let rom_config = Config::with_layers(&rom_layers).map_err(PyValueError::new_err)?;
+
+    let quirks = match quirks {
+        Some(profile) => Quirks::from_profile(profile),
+        None => rom_config.quirks()
+    }.map_err(PyValueError::new_err)?;
+    let instructions_per_frame = instructions_per_frame.unwrap_or(rom_config.cpu.instructions_per_frame);
+    if instructions_per_frame == 0 || frame_skip == 0 {
+        return Err(PyValueError::new_err("instructions_per_frame and frame_skip must be positive"));
+    }
+
+    let rewards = rewards.unwrap_or_default().iter().map(parse_reward).collect::<PyResult<Vec<_>>>()?;
+    let done_conditions = done_when.unwrap_or_default().iter().map(parse_done_condition).collect::<PyResult<Vec<_>>>()?;
+    let sources = rewards.iter().map(|reward| reward.source).chain(done_conditions.iter().map(|condition| condition.source));
+    for source in sources {
+        if let ValueSource::Memory(address) = source {
+            if address as usize >= quirks.memory_size {
+                return Err(PyValueError::new_err(format!("Address {:#x} is outside of the {} bytes memory", address, quirks.memory_size)));
+            }
+        }
+    }
+
+    return Ok(Rc::new(EnvironmentConfig{rom_content: rom_content, quirks: quirks, instructions_per_frame: instructions_per_frame,
+        frame_skip: frame_skip, actions: parse_actions(actions)?, rewards: rewards, done_conditions: done_conditions, max_steps: max_steps}));
+}
+
+fn frame_bytes<'py>(py: Python<'py>, environment: &Environment) -> Bound<'py, PyBytes> {
+    let mut frame = Vec::new();
+    environment.write_frame(&mut frame);
+    return PyBytes::new_bound(py, &frame);
+}
+
+fn step_info<'py>(py: Python<'py>, environment: &Environment, result: &StepResult) -> PyResult<Bound<'py, PyDict>> {
+    let info = PyDict::new_bound(py);
+    info.set_item("steps", environment.steps())?;
+    info.set_item("episode_seed", environment.episode_seed())?;
+    info.set_item("frame_shape", environment.frame_shape())?;
+    info.set_item("truncated", result.truncated)?;
+    if let Some(error) = &result.error {
+        info.set_item("error", error)?;
+    }
+    return Ok(info);
+}
+
+// A single rom, with the gym step API: reset() -> frame and step(action) -> (frame, reward, done, info).
+// Frames are bytes with one byte per pixel, numpy.frombuffer(frame, numpy.uint8).reshape(env.frame_shape) turns them into arrays.
+#[pyclass(unsendable)]
+struct Chip8Env {
+    environment: Environment,
+}
+
+#[pymethods]
+impl Chip8Env {
+    #[new]
+    #[pyo3(signature = (rom, quirks=None, instructions_per_frame=None, frame_skip=4, actions=None, rewards=None, done_when=None, max_steps=None, seed=0))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(rom: &Bound<'_, PyAny>, quirks: Option<&str>, instructions_per_frame: Option<u32>, frame_skip: u32, actions: Option<Vec<Vec<u8>>>,
+        rewards: Option<Vec<Bound<'_, PyDict>>>, done_when: Option<Vec<Bound<'_, PyDict>>>, max_steps: Option<u64>, seed: u64) -> PyResult<Chip8Env> {
+        let config = environment_config(rom, quirks, instructions_per_frame, frame_skip, actions, rewards, done_when, max_steps)?;
+        let environment = Environment::new(config, seed).map_err(PyValueError::new_err)?;
+        return Ok(Chip8Env{environment: environment});
+    }
+
+    // Restarts the rom, an episode without a seed gets the next seed of the environment
+    #[pyo3(signature = (seed=None))]
+    fn reset<'py>(&mut self, py: Python<'py>, seed: Option<u64>) -> PyResult<Bound<'py, PyBytes>> {
+        self.environment.reset(seed).map_err(PyRuntimeError::new_err)?;
+        return Ok(frame_bytes(py, &self.environment));
+    }
+
+    fn step<'py>(&mut self, py: Python<'py>, action: usize) -> PyResult<(Bound<'py, PyBytes>, f64, bool, Bound<'py, PyDict>)> {
+        let result = self.environment.step(action).map_err(PyValueError::new_err)?;
+        return Ok((frame_bytes(py, &self.environment), result.reward, result.done, step_info(py, &self.environment, &result)?));
+    }
+
+    #[getter]
+    fn action_count(&self) -> usize {
+        return self.environment.action_count();
+    }
+
+    #[getter]
+    fn frame_shape(&self) -> (usize, usize) {
+        return self.environment.frame_shape();
+    }
+
+    // For finding the addresses a game keeps its score in
+    fn peek(&self, address: usize) -> PyResult<u8> {
+        return self.environment.cpu().peek_memory(address).map_err(|e| PyValueError::new_err(e.to_string()));
+    }
+
+    fn register(&self, register: usize) -> PyResult<u8> {
+        if register > 0xF {
+            return Err(PyValueError::new_err(format!("Register {} doesn't exist, registers are 0 to 15", register)));
+        }
+        return Ok(self.environment.cpu().v(register));
+    }
+
+    fn save_state<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        return PyBytes::new_bound(py, &self.environment.save_state());
+    }
+
+    fn load_state(&mut self, state: &[u8]) -> PyResult<()> {
+        return self.environment.load_state(state).map_err(PyValueError::new_err);
+    }
+}
+
+// Several copies of a rom stepped together, environment N is seeded with seed + N.
+// Finished environments are reset right away, the last frame of their episode is in the "final_frame" of their info.
+#[pyclass(unsendable)]
+struct Chip8VecEnv {
+    environments: Vec<Environment>,
+}
+
+#[pymethods]
+impl Chip8VecEnv {
+    #[new]
+    #[pyo3(signature = (rom, num_envs, quirks=None, instructions_per_frame=None, frame_skip=4, actions=None, rewards=None, done_when=None, max_steps=None, seed=0))]
+    #[allow(clippy::too_many_arguments)]
+    fn new(rom: &Bound<'_, PyAny>, num_envs: usize, quirks: Option<&str>, instructions_per_frame: Option<u32>, frame_skip: u32, actions: Option<Vec<Vec<u8>>>,
+        rewards: Option<Vec<Bound<'_, PyDict>>>, done_when: Option<Vec<Bound<'_, PyDict>>>, max_steps: Option<u64>, seed: u64) -> PyResult<Chip8VecEnv> {
+        if num_envs == 0 {
+            return Err(PyValueError::new_err("num_envs must be positive"));
+        }
+
+        let config = environment_config(rom, quirks, instructions_per_frame, frame_skip, actions, rewards, done_when, max_steps)?;
+        let environments = (0..num_envs as u64)
+            .map(|index| Environment::new(config.clone(), seed.wrapping_add(index)).map_err(PyValueError::new_err))
+            .collect::<PyResult<Vec<_>>>()?;
+        return Ok(Chip8VecEnv{environments: environments});
+    }
+
+    #[pyo3(signature = (seed=None))]
+    fn reset<'py>(&mut self, py: Python<'py>, seed: Option<u64>) -> PyResult<Vec<Bound<'py, PyBytes>>> {
+        let mut frames = Vec::new();
+        for (index, environment) in self.environments.iter_mut().enumerate() {
+            environment.reset(seed.map(|seed| seed.wrapping_add(index as u64))).map_err(PyRuntimeError::new_err)?;
+            frames.push(frame_bytes(py, environment));
+        }
+        return Ok(frames);
+    }
+
+    #[allow(clippy::type_complexity)]
+    fn step<'py>(&mut self, py: Python<'py>, actions: Vec<usize>) -> PyResult<(Vec<Bound<'py, PyBytes>>, Vec<f64>, Vec<bool>, Vec<Bound<'py, PyDict>>)> {
+        if actions.len() != self.environments.len() {
+            return Err(PyValueError::new_err(format!("Got {} actions for {} environments", actions.len(), self.environments.len())));
+        }
+        // Checked before any environment steps, so a bad action doesn't leave the environments out of step
+        if let Some(action) = actions.iter().find(|action| **action >= self.action_count()) {
+            return Err(PyValueError::new_err(format!("Action {} is out of range, there are {} actions", action, self.action_count())));
+        }
+
+        let (mut frames, mut rewards, mut dones, mut infos) = (Vec::new(), Vec::new(), Vec::new(), Vec::new());
+        for (environment, action) in self.environments.iter_mut().zip(actions) {
+            let result = environment.step(action).map_err(PyValueError::new_err)?;
+            let info = step_info(py, environment, &result)?;
+            if result.done {
+                info.set_item("final_frame", frame_bytes(py, environment))?;
+                environment.reset(None).map_err(PyRuntimeError::new_err)?;
+            }
+
+            frames.push(frame_bytes(py, environment));
+            rewards.push(result.reward);
+            dones.push(result.done);
+            infos.push(info);
+        }
+        return Ok((frames, rewards, dones, infos));
+    }
+
+    #[getter]
+    fn num_envs(&self) -> usize {
+        return self.environments.len();
+    }
+
+    #[getter]
+    fn action_count(&self) -> usize {
+        return self.environments[0].action_count();
+    }
+}
+
+#[pymodule]
+fn chip8_env(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add_class::<Chip8Env>()?;
+    module.add_class::<Chip8VecEnv>()?;
+    return Ok(());
+}
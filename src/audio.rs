@@ -0,0 +1,39 @@
+use crate::config::AudioConfig;
+
+use rodio::{OutputStream, Sink};
+use rodio::source::SineWave;
+
+pub struct Beeper {
+    _stream: OutputStream,
+    sink: Sink,
+    is_playing: bool,
+}
+
+impl Beeper {
+    pub fn new(audio_config: &AudioConfig) -> Result<Beeper, String> {
+        let (stream, stream_handle) = OutputStream::try_default().map_err(|e| e.to_string())?;
+        let sink = Sink::try_new(&stream_handle).map_err(|e| e.to_string())?;
+
+        sink.pause();
+        sink.set_volume(audio_config.volume);
+        sink.append(SineWave::new(audio_config.frequency));
+
+        return Ok(Beeper{_stream: stream, sink: sink, is_playing: false});
+    }
+
+    pub fn set_active(&mut self, active: bool) {
+        if active == self.is_playing {
+            return;
+        }
+
+        if active {
+            debug!("SOUND | Starting buzzer");
+            self.sink.play();
+        } else {
+            debug!("SOUND | Stopping buzzer");
+            self.sink.pause();
+        }
+
+        self.is_playing = active;
+    }
+}
@@ -0,0 +1,333 @@
+use crate::config;
+use crate::cpu::CPU;
+use crate::errors::Chip8Error;
+
+use serde::{Deserialize, Serialize};
+
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+
+pub const CHEATS_DIR_NAME: &str = "cheats";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct CheatCondition {
+    pub address: usize,
+    pub value: u8,
+    // Holds when the address has the value, or when it doesn't
+    pub equal: bool,
+}
+
+// "<address>:<value>" writes the value every frame, "<address>:<value>?<address>=<value>" (or "!=") only while the condition holds
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct CheatCode {
+    pub address: usize,
+    pub value: u8,
+    pub condition: Option<CheatCondition>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Cheat {
+    pub name: String,
+    pub codes: Vec<CheatCode>,
+    #[serde(default = "default_enabled")]
+    pub enabled: bool,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CheatFile {
+    #[serde(default)]
+    cheats: Vec<Cheat>,
+}
+
+// The cheats of a rom, saved in the configuration directory by the rom's hash
+#[derive(Debug, Clone, Default)]
+pub struct CheatList {
+    cheats: Vec<Cheat>,
+    // Cheats of the command line, applied with the others but never saved
+    session_cheats: Vec<Cheat>,
+    rom_sha1: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SearchCondition {
+    Equal(u8),
+    NotEqual(u8),
+    // Compared with the values of the previous search
+    Changed,
+    Unchanged,
+    Increased,
+    Decreased,
+}
+
+// Narrows the addresses holding a value, like a score or a lives counter, by searching again as it changes
+pub struct MemorySearch {
+    candidates: Vec<usize>,
+    previous_values: Vec<u8>,
+}
+
+fn default_enabled() -> bool {
+    return true;
+}
+
+fn parse_hex(text: &str, name: &str) -> Result<usize, String> {
+    let digits = text.trim().trim_start_matches("0x").trim_start_matches("0X");
+    return usize::from_str_radix(digits, 16).map_err(|_| format!("Invalid {} \"{}\", expected a hex number", name, text));
+}
+
+fn parse_byte(text: &str) -> Result<u8, String> {
+    let value = parse_hex(text, "value")?;
+    if value > 0xFF {
+        return Err(format!("Invalid value \"{}\", expected a byte", text));
+    }
+    return Ok(value as u8);
+}
+
+pub fn cheats_path(rom_sha1: &str) -> Option<PathBuf> {
+    return config::config_dir().map(|dir| dir.join(CHEATS_DIR_NAME).join(format!("{}.toml", rom_sha1)));
+}
+
+impl CheatCode {
+    pub fn parse(code: &str) -> Result<CheatCode, String> {
+        let (write, condition) = match code.split_once('?') {
+            Some((write, condition)) => (write, Some(condition)),
+            None => (code, None)
+        };
+
+        let (address, value) = write.split_once(':')
+            .ok_or_else(|| format!("Invalid cheat code \"{}\", expected <address>:<value>[?<address>=<value>]", code))?;
+
+        let condition = match condition {
+            Some(condition) => {
+                let (condition_address, condition_value, equal) = match condition.split_once("!=") {
+                    Some((condition_address, condition_value)) => (condition_address, condition_value, false),
+                    None => {
+                        let (condition_address, condition_value) = condition.split_once('=')
+                            .ok_or_else(|| format!("Invalid cheat condition \"{}\", expected <address>=<value> or <address>!=<value>", condition))?;
+                        (condition_address, condition_value, true)
+                    }
+                };
+                Some(CheatCondition{address: parse_hex(condition_address, "address")?, value: parse_byte(condition_value)?, equal: equal})
+            },
+            None => None
+        };
+
+        return Ok(CheatCode{address: parse_hex(address, "address")?, value: parse_byte(value)?, condition: condition});
+    }
+
+    pub fn check_addresses(&self, memory_size: usize) -> Result<(), String> {
+        let condition_address = self.condition.map(|condition| condition.address);
+        for address in std::iter::once(self.address).chain(condition_address) {
+            if address >= memory_size {
+                return Err(format!("Cheat address {:#05x} is outside of the {} bytes memory", address, memory_size));
+            }
+        }
+        return Ok(());
+    }
+
+    // Writes through the memory like the rom would, an address that already holds the value isn't written again
+    pub fn apply(&self, cpu: &mut CPU) -> Result<(), Chip8Error> {
+        if let Some(condition) = self.condition {
+            if (cpu.peek_memory(condition.address)? == condition.value) != condition.equal {
+                return Ok(());
+            }
+        }
+
+        if cpu.peek_memory(self.address)? != self.value {
+            cpu.write_memory(self.address, self.value)?;
+        }
+
+        return Ok(());
+    }
+}
+
+impl fmt::Display for CheatCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#05x}:{:02x}", self.address, self.value)?;
+        if let Some(condition) = self.condition {
+            write!(f, "?{:#05x}{}{:02x}", condition.address, if condition.equal { "=" } else { "!=" }, condition.value)?;
+        }
+        return Ok(());
+    }
+}
+
+impl TryFrom<String> for CheatCode {
+    type Error = String;
+
+    fn try_from(code: String) -> Result<CheatCode, String> {
+        return CheatCode::parse(&code);
+    }
+}
+
+impl From<CheatCode> for String {
+    fn from(code: CheatCode) -> String {
+        return code.to_string();
+    }
+}
+
+impl Cheat {
+    // Several codes are joined with "+", like the cheats of libretro frontends
+    pub fn parse(name: &str, codes: &str) -> Result<Cheat, String> {
+        let codes = codes.split('+').map(|code| CheatCode::parse(code.trim())).collect::<Result<Vec<CheatCode>, String>>()?;
+        return Ok(Cheat{name: name.to_string(), codes: codes, enabled: true});
+    }
+
+    pub fn check_addresses(&self, memory_size: usize) -> Result<(), String> {
+        for code in &self.codes {
+            code.check_addresses(memory_size)?;
+        }
+        return Ok(());
+    }
+
+    // Freezes an address to a value
+    pub fn freeze(address: usize, value: u8) -> Cheat {
+        return Cheat{name: format!("Freeze {:#05x}", address), codes: vec![CheatCode{address: address, value: value, condition: None}], enabled: true};
+    }
+}
+
+impl CheatList {
+    pub fn new() -> CheatList {
+        CheatList{cheats: Vec::new(), session_cheats: Vec::new(), rom_sha1: None}
+    }
+
+    // The saved cheats of a rom, or none when it has no cheats file
+    // The saved cheats of the rom, a cheat with addresses outside of the memory is disabled
+    pub fn for_rom(rom_sha1: &str, memory_size: usize) -> Result<CheatList, String> {
+        let mut cheat_list = CheatList{cheats: Vec::new(), session_cheats: Vec::new(), rom_sha1: Some(rom_sha1.to_string())};
+        if let Some(path) = cheats_path(rom_sha1).filter(|path| path.is_file()) {
+            let content = fs::read_to_string(&path).map_err(|e| format!("Failed reading {} : {}", path.display(), e))?;
+            let cheat_file: CheatFile = toml::from_str(&content).map_err(|e| format!("Failed parsing {} : {}", path.display(), e))?;
+            cheat_list.cheats = cheat_file.cheats;
+        }
+
+        for cheat in cheat_list.cheats.iter_mut().filter(|cheat| cheat.enabled) {
+            if let Err(e) = cheat.check_addresses(memory_size) {
+                warn!("CHEATS | Disabling cheat \"{}\" : {}", cheat.name, e);
+                cheat.enabled = false;
+            }
+        }
+
+        return Ok(cheat_list);
+    }
+
+    pub fn save(&self) -> Result<PathBuf, String> {
+        let rom_sha1 = self.rom_sha1.as_ref().ok_or_else(|| String::from("The cheats aren't of a known rom"))?;
+        let path = cheats_path(rom_sha1).ok_or_else(|| String::from("Failed finding the configuration directory"))?;
+
+        let content = toml::to_string_pretty(&CheatFile{cheats: self.cheats.clone()}).map_err(|e| e.to_string())?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed creating {} : {}", parent.display(), e))?;
+        }
+        fs::write(&path, content).map_err(|e| format!("Failed writing {} : {}", path.display(), e))?;
+
+        return Ok(path);
+    }
+
+    pub fn cheats(&self) -> &[Cheat] {
+        return &self.cheats;
+    }
+
+    pub fn session_cheats(&self) -> &[Cheat] {
+        return &self.session_cheats;
+    }
+
+    pub fn add_session_cheat(&mut self, cheat: Cheat) {
+        self.session_cheats.push(cheat);
+    }
+
+    pub fn add(&mut self, cheat: Cheat) -> usize {
+        self.cheats.push(cheat);
+        return self.cheats.len() - 1;
+    }
+
+    // Replaces the cheat at an index, the list grows with disabled cheats up to it
+    pub fn set(&mut self, index: usize, cheat: Cheat) {
+        while self.cheats.len() <= index {
+            self.cheats.push(Cheat{name: String::new(), codes: Vec::new(), enabled: false});
+        }
+        self.cheats[index] = cheat;
+    }
+
+    pub fn remove(&mut self, index: usize) -> Result<Cheat, String> {
+        if index >= self.cheats.len() {
+            return Err(format!("No cheat at index {}, there are {} cheats", index, self.cheats.len()));
+        }
+        return Ok(self.cheats.remove(index));
+    }
+
+    pub fn set_enabled(&mut self, index: usize, enabled: bool) -> Result<(), String> {
+        let cheat_count = self.cheats.len();
+        let cheat = self.cheats.get_mut(index).ok_or_else(|| format!("No cheat at index {}, there are {} cheats", index, cheat_count))?;
+        cheat.enabled = enabled;
+        return Ok(());
+    }
+
+    pub fn clear(&mut self) {
+        self.cheats.clear();
+    }
+
+    // Applied at the start of every frame, a cheat that fails is logged and disabled
+    pub fn apply(&mut self, cpu: &mut CPU) {
+        for cheat in self.cheats.iter_mut().chain(self.session_cheats.iter_mut()).filter(|cheat| cheat.enabled) {
+            if let Err(e) = cheat.codes.iter().try_for_each(|code| code.apply(cpu)) {
+                error!("CHEATS | Disabling cheat \"{}\" after it failed : {}", cheat.name, e);
+                cheat.enabled = false;
+            }
+        }
+    }
+}
+
+impl SearchCondition {
+    // "equal" and "not_equal" compare with a value, the others with the previous search
+    pub fn parse(condition: &str, value: Option<u8>) -> Result<SearchCondition, String> {
+        match (condition.to_lowercase().as_str(), value) {
+            ("equal", Some(value)) => Ok(SearchCondition::Equal(value)),
+            ("not_equal", Some(value)) => Ok(SearchCondition::NotEqual(value)),
+            ("equal", None) | ("not_equal", None) => Err(format!("Search condition \"{}\" needs a value", condition)),
+            ("changed", _) => Ok(SearchCondition::Changed),
+            ("unchanged", _) => Ok(SearchCondition::Unchanged),
+            ("increased", _) => Ok(SearchCondition::Increased),
+            ("decreased", _) => Ok(SearchCondition::Decreased),
+            _ => Err(format!("Unknown search condition \"{}\", expected equal, not_equal, changed, unchanged, increased or decreased", condition))
+        }
+    }
+
+    fn matches(&self, previous_value: u8, value: u8) -> bool {
+        match self {
+            SearchCondition::Equal(expected) => value == *expected,
+            SearchCondition::NotEqual(expected) => value != *expected,
+            SearchCondition::Changed => value != previous_value,
+            SearchCondition::Unchanged => value == previous_value,
+            SearchCondition::Increased => value > previous_value,
+            SearchCondition::Decreased => value < previous_value
+        }
+    }
+}
+
+impl MemorySearch {
+    // Every address is a candidate until the first search
+    pub fn new(memory: &[u8]) -> MemorySearch {
+        MemorySearch{candidates: (0..memory.len()).collect(), previous_values: memory.to_vec()}
+    }
+
+    // Keeps the candidates matching the condition and returns how many are left
+    pub fn narrow(&mut self, memory: &[u8], condition: SearchCondition) -> usize {
+        let previous_values = &self.previous_values;
+        self.candidates.retain(|address| match (previous_values.get(*address), memory.get(*address)) {
+            (Some(previous_value), Some(value)) => condition.matches(*previous_value, *value),
+            _ => false
+        });
+        self.previous_values = memory.to_vec();
+        return self.candidates.len();
+    }
+
+    pub fn candidates(&self) -> &[usize] {
+        return &self.candidates;
+    }
+
+    // The value of a candidate when it was last searched
+    pub fn previous_value(&self, address: usize) -> Option<u8> {
+        return self.previous_values.get(address).copied();
+    }
+}
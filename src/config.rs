@@ -0,0 +1,525 @@
+use crate::consts;
+use crate::quirks::Quirks;
+use crate::memory::AddressPolicy;
+use crate::palette::Palette;
+use crate::upscale::Upscaler;
+use crate::watchpoints::Watchpoint;
+
+use serde::{Deserialize, Serialize};
+
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+pub const PROJECT_CONFIG_FILE: &str = "chip8.toml";
+pub const ROM_OVERRIDES_DIR_NAME: &str = "roms";
+// The sections saved for a rom by --save-rom-settings
+const ROM_OVERRIDE_SECTIONS: [&str; 5] = ["keys", "controller", "palette", "cpu", "quirks"];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub keys: HashMap<String, String>,
+    pub controller: ControllerConfig,
+    pub palette: PaletteConfig,
+    pub effects: EffectsConfig,
+    pub display: DisplayConfig,
+    pub cpu: CpuConfig,
+    pub quirks: QuirksConfig,
+    pub audio: AudioConfig,
+    pub log: LogConfig,
+    pub debug: DebugConfig,
+    pub terminal: TerminalConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControllerConfig {
+    pub enabled: bool,
+    pub deadzone: i16,
+    pub mappings_file: Option<String>,
+    // SDL button name -> chip8 key
+    pub buttons: HashMap<String, String>,
+    // SDL axis name with a direction ("leftx-", "lefty+") -> chip8 key
+    pub axes: HashMap<String, String>,
+    // Player number -> layout replacing the buttons and axes above for that controller
+    pub players: HashMap<String, ControllerLayout>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ControllerLayout {
+    pub buttons: HashMap<String, String>,
+    pub axes: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct PaletteConfig {
+    // A named palette, or "custom" for the foreground and background colors below
+    pub name: String,
+    pub foreground: String,
+    pub background: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct EffectsConfig {
+    // How much of a pixel's brightness is kept every frame after it is turned off, 0 disables
+    pub persistence: f32,
+    pub frame_blending: bool,
+    pub scanlines: f32,
+    pub pixel_grid: f32,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum WindowMode {
+    Windowed,
+    Fullscreen,
+    Borderless,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ScalingMode {
+    // Largest whole multiple of the chip8 resolution that fits the window
+    Integer,
+    // Fills the window as much as possible while keeping the aspect ratio
+    Fit,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DisplayConfig {
+    // Initial window size, in window pixels per chip8 pixel
+    pub scale: u32,
+    pub scaling: ScalingMode,
+    pub window_mode: WindowMode,
+    pub software_renderer: bool,
+    pub upscaler: String,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Engine {
+    // Fetches and decodes every instruction as it runs
+    Interpreter,
+    // Runs blocks of code decoded ahead, self modifying code still goes through the interpreter
+    Recompiler,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct CpuConfig {
+    pub instructions_per_frame: u32,
+    pub engine: Engine,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct QuirksConfig {
+    pub profile: String,
+    pub shift_uses_vy: Option<bool>,
+    pub load_store_increments_index: Option<bool>,
+    pub jump_uses_vx: Option<bool>,
+    pub logic_resets_vf: Option<bool>,
+    pub clip_sprites: Option<bool>,
+    pub stack_depth: Option<usize>,
+    pub memory_stack: Option<bool>,
+    pub memory_size: Option<usize>,
+    pub address_policy: Option<AddressPolicy>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct AudioConfig {
+    pub enabled: bool,
+    pub volume: f32,
+    pub frequency: u32,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LogConfig {
+    pub level: String,
+    pub file: Option<String>,
+    pub file_level: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct DebugConfig {
+    // Watchpoints as "<access>:<start>[-<end>][:<action>]", like "w:0x300-0x30F:pause"
+    pub watchpoints: Vec<String>,
+    pub report_self_modifying_code: bool,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum TerminalCharset {
+    // Two pixels per character, one above the other
+    HalfBlock,
+    // Eight pixels per character, 2 wide and 4 high
+    Braille,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TerminalConfig {
+    pub charset: TerminalCharset,
+    // Most terminals only report key presses, a key is released when it wasn't repeated for this long
+    pub key_hold_millis: u64,
+    // Ring the terminal bell when the buzzer starts
+    pub bell: bool,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        let keys = consts::get_chip_to_real_key_map().iter()
+            .map(|(chip_key, real_key)| (format!("{:X}", chip_key), real_key.to_string()))
+            .collect();
+
+        Config{keys: keys, controller: ControllerConfig::default(), palette: PaletteConfig::default(), effects: EffectsConfig::default(), display: DisplayConfig::default(), cpu: CpuConfig::default(),
+            quirks: QuirksConfig::default(), audio: AudioConfig::default(), log: LogConfig::default(),
+            debug: DebugConfig::default(), terminal: TerminalConfig::default()}
+    }
+}
+
+impl Default for ControllerConfig {
+    fn default() -> ControllerConfig {
+        let buttons = [("dpup", "5"), ("dpleft", "7"), ("dpdown", "8"), ("dpright", "9"), ("a", "6"), ("b", "4"), ("start", "F")]
+            .iter().map(|(button, chip_key)| (button.to_string(), chip_key.to_string())).collect();
+        let axes = [("lefty-", "5"), ("leftx-", "7"), ("lefty+", "8"), ("leftx+", "9")]
+            .iter().map(|(axis, chip_key)| (axis.to_string(), chip_key.to_string())).collect();
+
+        ControllerConfig{enabled: true, deadzone: 8000, mappings_file: None, buttons: buttons, axes: axes, players: HashMap::new()}
+    }
+}
+
+impl ControllerLayout {
+    pub fn button_map(&self) -> Result<HashMap<String, u8>, String> {
+        return parse_binding_map(&self.buttons);
+    }
+
+    pub fn axis_map(&self) -> Result<HashMap<String, u8>, String> {
+        return parse_binding_map(&self.axes);
+    }
+}
+
+impl ControllerConfig {
+    // The layout of the n'th connected controller, starting from 1
+    pub fn player_layout(&self, player: usize) -> ControllerLayout {
+        let mut layout = ControllerLayout{buttons: self.buttons.clone(), axes: self.axes.clone()};
+        if let Some(player_layout) = self.players.get(&player.to_string()) {
+            layout.buttons.extend(player_layout.buttons.clone());
+            layout.axes.extend(player_layout.axes.clone());
+        }
+
+        return layout;
+    }
+}
+
+impl Default for PaletteConfig {
+    fn default() -> PaletteConfig {
+        PaletteConfig{name: String::from("custom"), foreground: String::from("#FFFFFF"), background: String::from("#000000")}
+    }
+}
+
+impl Default for DisplayConfig {
+    fn default() -> DisplayConfig {
+        DisplayConfig{scale: consts::DEFAULT_SCALE_FACTOR, scaling: ScalingMode::Integer, window_mode: WindowMode::Windowed, software_renderer: false,
+            upscaler: String::from("nearest")}
+    }
+}
+
+impl Default for CpuConfig {
+    fn default() -> CpuConfig {
+        CpuConfig{instructions_per_frame: consts::DEFAULT_INSTRUCTIONS_PER_FRAME, engine: Engine::Interpreter}
+    }
+}
+
+impl Default for AudioConfig {
+    fn default() -> AudioConfig {
+        AudioConfig{enabled: true, volume: 0.25, frequency: 440}
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        LogConfig{level: String::from("info"), file: Some(String::from("Chip8.log")), file_level: String::from("trace")}
+    }
+}
+
+impl Default for DebugConfig {
+    fn default() -> DebugConfig {
+        DebugConfig{watchpoints: Vec::new(), report_self_modifying_code: true}
+    }
+}
+
+impl Default for TerminalConfig {
+    fn default() -> TerminalConfig {
+        TerminalConfig{charset: TerminalCharset::HalfBlock, key_hold_millis: 150, bell: true}
+    }
+}
+
+impl Config {
+    // Layers are applied in order: defaults, user config, project config, rom layers, then the explicitly given file
+    pub fn load(explicit_config_path: Option<&str>, rom_layers: &[toml::Value]) -> Result<Config, String> {
+        let mut merged = toml::Value::try_from(Config::default()).map_err(|e| e.to_string())?;
+
+        let mut layer_paths: Vec<PathBuf> = Vec::new();
+        if let Some(user_dir) = config_dir() {
+            layer_paths.push(user_dir.join(CONFIG_FILE_NAME));
+        }
+        layer_paths.push(PathBuf::from(PROJECT_CONFIG_FILE));
+
+        for path in layer_paths {
+            if path.is_file() {
+                merge_values(&mut merged, read_layer(&path)?);
+            }
+        }
+
+        for rom_layer in rom_layers {
+            merge_values(&mut merged, rom_layer.clone());
+        }
+
+        if let Some(path) = explicit_config_path {
+            merge_values(&mut merged, read_layer(Path::new(path))?);
+        }
+
+        let config: Config = merged.try_into().map_err(|e| format!("Invalid configuration : {}", e))?;
+        config.validate()?;
+
+        return Ok(config);
+    }
+
+    // Only the defaults and the given layers, for frontends that don't read the configuration files
+    pub fn with_layers(layers: &[toml::Value]) -> Result<Config, String> {
+        let mut merged = toml::Value::try_from(Config::default()).map_err(|e| e.to_string())?;
+        for layer in layers {
+            merge_values(&mut merged, layer.clone());
+        }
+
+        let config: Config = merged.try_into().map_err(|e| format!("Invalid configuration : {}", e))?;
+        config.validate()?;
+
+        return Ok(config);
+    }
+
+    pub fn validate(&self) -> Result<(), String> {
+        self.key_map()?;
+        self.quirks()?;
+        parse_binding_map(&self.controller.buttons)?;
+        parse_binding_map(&self.controller.axes)?;
+        for (player, layout) in &self.controller.players {
+            if player.parse::<usize>().map_or(true, |player| player == 0) {
+                return Err(format!("Invalid controller player \"{}\", expected a number starting from 1", player));
+            }
+            layout.button_map()?;
+            layout.axis_map()?;
+        }
+        Palette::from_config(&self.palette)?;
+        Upscaler::from_name(&self.display.upscaler)?;
+        self.watchpoints()?;
+
+        if self.display.scale == 0 {
+            return Err(String::from("Display scale must be at least 1"));
+        }
+
+        if self.cpu.instructions_per_frame == 0 {
+            return Err(String::from("Instructions per frame must be at least 1"));
+        }
+
+        let effect_strengths = [("persistence", self.effects.persistence), ("scanlines", self.effects.scanlines),
+            ("pixel_grid", self.effects.pixel_grid)];
+        for (effect_name, strength) in effect_strengths {
+            if !(0.0..=1.0).contains(&strength) {
+                return Err(format!("Effect {} must be between 0 and 1, got {}", effect_name, strength));
+            }
+        }
+
+        if !(0.0..=1.0).contains(&self.audio.volume) {
+            return Err(format!("Audio volume must be between 0 and 1, got {}", self.audio.volume));
+        }
+
+        return Ok(());
+    }
+
+    // Saves the rom specific settings that differ from the lower layers, they are loaded as a rom layer the next time the rom is run
+    pub fn save_rom_override(&self, rom_sha1: &str, lower_layers: &Config) -> Result<PathBuf, String> {
+        let override_path = rom_override_path(rom_sha1).ok_or_else(|| String::from("Failed finding the configuration directory"))?;
+
+        let values = toml::Value::try_from(self).map_err(|e| e.to_string())?;
+        let lower_values = toml::Value::try_from(lower_layers).map_err(|e| e.to_string())?;
+        let mut layer = toml::value::Table::new();
+        for section in ROM_OVERRIDE_SECTIONS {
+            if let Some(changed) = values.get(section).and_then(|value| changed_values(value, lower_values.get(section))) {
+                layer.insert(section.to_string(), changed);
+            }
+        }
+
+        let content = toml::to_string_pretty(&toml::Value::Table(layer)).map_err(|e| e.to_string())?;
+        if let Some(parent) = override_path.parent() {
+            fs::create_dir_all(parent).map_err(|e| format!("Failed creating {} : {}", parent.display(), e))?;
+        }
+        fs::write(&override_path, content).map_err(|e| format!("Failed writing {} : {}", override_path.display(), e))?;
+
+        return Ok(override_path);
+    }
+
+    pub fn key_map(&self) -> Result<HashMap<u8, String>, String> {
+        let mut map = HashMap::<u8, String>::new();
+        for (chip_key, real_key) in &self.keys {
+            map.insert(parse_chip_key(chip_key)?, real_key.clone());
+        }
+
+        return Ok(map);
+    }
+
+    pub fn watchpoints(&self) -> Result<Vec<Watchpoint>, String> {
+        return self.debug.watchpoints.iter().map(|spec| Watchpoint::parse(spec)).collect();
+    }
+
+    pub fn quirks(&self) -> Result<Quirks, String> {
+        let mut quirks = if self.quirks.profile.is_empty() {
+            Quirks::default()
+        } else {
+            Quirks::from_profile(&self.quirks.profile)?
+        };
+
+        if let Some(value) = self.quirks.shift_uses_vy {
+            quirks.shift_uses_vy = value;
+        }
+        if let Some(value) = self.quirks.load_store_increments_index {
+            quirks.load_store_increments_index = value;
+        }
+        if let Some(value) = self.quirks.jump_uses_vx {
+            quirks.jump_uses_vx = value;
+        }
+        if let Some(value) = self.quirks.logic_resets_vf {
+            quirks.logic_resets_vf = value;
+        }
+        if let Some(value) = self.quirks.clip_sprites {
+            quirks.clip_sprites = value;
+        }
+        if let Some(value) = self.quirks.stack_depth {
+            quirks.stack_depth = value;
+        }
+        if let Some(value) = self.quirks.memory_stack {
+            quirks.memory_stack = value;
+        }
+        if let Some(value) = self.quirks.memory_size {
+            quirks.memory_size = value;
+        }
+        if let Some(value) = self.quirks.address_policy {
+            quirks.address_policy = value;
+        }
+
+        if quirks.stack_depth == 0 || quirks.stack_depth > consts::MAX_STACK_DEPTH {
+            return Err(format!("Stack depth must be between 1 and {}, got {}", consts::MAX_STACK_DEPTH, quirks.stack_depth));
+        }
+        if quirks.memory_size != consts::MEMORY_SIZE && quirks.memory_size != consts::XOCHIP_MEMORY_SIZE {
+            return Err(format!("Memory size must be {} or {} bytes, got {}", consts::MEMORY_SIZE, consts::XOCHIP_MEMORY_SIZE, quirks.memory_size));
+        }
+        if quirks.memory_stack && quirks.stack_depth * 2 > consts::VIP_STACK_SIZE {
+            return Err(format!("A memory stack holds up to {} entries, got a stack depth of {}", consts::VIP_STACK_SIZE / 2, quirks.stack_depth));
+        }
+
+        return Ok(quirks);
+    }
+}
+
+pub fn config_dir() -> Option<PathBuf> {
+    if let Some(xdg_config_home) = env::var_os("XDG_CONFIG_HOME") {
+        return Some(PathBuf::from(xdg_config_home).join("chip8"));
+    }
+
+    if let Some(app_data) = env::var_os("APPDATA") {
+        return Some(PathBuf::from(app_data).join("chip8"));
+    }
+
+    return env::var_os("HOME").map(|home| PathBuf::from(home).join(".config").join("chip8"));
+}
+
+pub fn rom_override_path(rom_sha1: &str) -> Option<PathBuf> {
+    return config_dir().map(|dir| dir.join(ROM_OVERRIDES_DIR_NAME).join(format!("{}.toml", rom_sha1)));
+}
+
+pub fn load_rom_override(rom_sha1: &str) -> Result<Option<toml::Value>, String> {
+    match rom_override_path(rom_sha1) {
+        Some(path) if path.is_file() => Ok(Some(read_layer(&path)?)),
+        _ => Ok(None)
+    }
+}
+
+pub fn parse_chip_key(chip_key: &str) -> Result<u8, String> {
+    match u8::from_str_radix(chip_key, 16) {
+        Ok(key) if key < 16 => Ok(key),
+        _ => Err(format!("Invalid chip8 key \"{}\", expected a hex digit between 0 and F", chip_key))
+    }
+}
+
+fn parse_binding_map(bindings: &HashMap<String, String>) -> Result<HashMap<String, u8>, String> {
+    let mut map = HashMap::<String, u8>::new();
+    for (input_name, chip_key) in bindings {
+        map.insert(input_name.to_lowercase(), parse_chip_key(chip_key)?);
+    }
+
+    return Ok(map);
+}
+
+pub fn parse_color(hex_color: &str) -> Result<(u8, u8, u8), String> {
+    let digits = hex_color.trim_start_matches('#');
+    if digits.len() != 6 {
+        return Err(format!("Invalid color \"{}\", expected #RRGGBB", hex_color));
+    }
+
+    let value = u32::from_str_radix(digits, 16).map_err(|_| format!("Invalid color \"{}\", expected #RRGGBB", hex_color))?;
+    return Ok(((value >> 16) as u8, (value >> 8) as u8, value as u8));
+}
+
+fn read_layer(path: &Path) -> Result<toml::Value, String> {
+    debug!("CONFIG | Reading configuration layer {}", path.display());
+
+    let content = fs::read_to_string(path).map_err(|e| format!("Failed reading config file {} : {}", path.display(), e))?;
+    return content.parse::<toml::Value>().map_err(|e| format!("Failed parsing config file {} : {}", path.display(), e));
+}
+
+// The parts of a value that a layer on top of lower would have to set, None when there are none
+fn changed_values(value: &toml::Value, lower: Option<&toml::Value>) -> Option<toml::Value> {
+    match (value, lower) {
+        (toml::Value::Table(table), Some(toml::Value::Table(lower_table))) => {
+            let mut changed_table = toml::value::Table::new();
+            for (key, table_value) in table {
+                if let Some(changed) = changed_values(table_value, lower_table.get(key)) {
+                    changed_table.insert(key.clone(), changed);
+                }
+            }
+
+            return if changed_table.is_empty() { None } else { Some(toml::Value::Table(changed_table)) };
+        },
+        (value, Some(lower)) if value == lower => None,
+        (value, _) => Some(value.clone())
+    }
+}
+
+fn merge_values(base: &mut toml::Value, layer: toml::Value) {
+    match (base, layer) {
+        (toml::Value::Table(base_table), toml::Value::Table(layer_table)) => {
+            for (key, layer_value) in layer_table {
+                match base_table.get_mut(&key) {
+                    Some(base_value) => merge_values(base_value, layer_value),
+                    None => {
+                        base_table.insert(key, layer_value);
+                    }
+                }
+            }
+        },
+        (base, layer) => {
+            *base = layer;
+        }
+    }
+}
@@ -1,11 +1,20 @@
 use std::collections::HashMap;
 
 pub const MEMORY_SIZE: usize = 4096;
+pub const XOCHIP_MEMORY_SIZE: usize = 65536;
 pub const PROGRAM_MEMORY_ADDR: usize = 0x200;
 pub const DISPLAY_WIDTH: usize = 64;
 pub const DISPLAY_HEIGHT: usize = 32;
-pub const SCALE_FACTOR: usize = 8;
-pub const TIMER_TICK_MILLIS: f32 = 1000 as f32 / 60 as f32;
+pub const HIRES_DISPLAY_WIDTH: usize = 128;
+pub const HIRES_DISPLAY_HEIGHT: usize = 64;
+pub const DEFAULT_SCALE_FACTOR: u32 = 8;
+pub const TIMER_TICK_MILLIS: f32 = 1000.0 / 60.0;
+pub const DEFAULT_INSTRUCTIONS_PER_FRAME: u32 = 10;
+// The COSMAC VIP interpreter kept its stack in the work area at the top of memory
+pub const VIP_STACK_ADDR: usize = 0xEA0;
+pub const VIP_STACK_SIZE: usize = 0x30;
+// Save states store the stack depth in a single byte
+pub const MAX_STACK_DEPTH: usize = 0xFF;
 
 pub const FONT_START_ADDR: usize = 0x00;
 pub const FONT_CONTENT: [[u8; 5]; 16] = [
@@ -0,0 +1,121 @@
+use crate::consts;
+use crate::instruction;
+
+use std::collections::HashMap;
+
+pub struct Coverage {
+    rom_content: Vec<u8>,
+    // Executions of every address, indexed by the address
+    address_counts: Vec<u64>,
+    // Skip instruction address -> (times skipped, times not skipped)
+    branches: HashMap<u16, (u64, u64)>,
+}
+
+pub struct CoverageReport {
+    pub disassembly: String,
+    pub lcov: String,
+    pub executed_instructions: usize,
+    pub total_instructions: usize,
+}
+
+impl Coverage {
+    pub fn new(rom_content: Vec<u8>) -> Coverage {
+        Coverage{rom_content: rom_content, address_counts: vec![0; u16::MAX as usize + 1], branches: HashMap::new()}
+    }
+
+    pub fn record_instruction(&mut self, address: u16) {
+        self.address_counts[address as usize] += 1;
+    }
+
+    pub fn record_branch(&mut self, address: u16, taken: bool) {
+        let (taken_count, not_taken_count) = self.branches.entry(address).or_insert((0, 0));
+        if taken {
+            *taken_count += 1;
+        } else {
+            *not_taken_count += 1;
+        }
+    }
+
+    fn rom_byte(&self, address: usize) -> u8 {
+        return self.rom_content.get(address - consts::PROGRAM_MEMORY_ADDR).copied().unwrap_or(0);
+    }
+
+    // An annotated disassembly of the rom, and an lcov tracefile whose lines refer to the disassembly
+    pub fn report(&self, source_name: &str) -> CoverageReport {
+        let rom_end = consts::PROGRAM_MEMORY_ADDR + self.rom_content.len();
+        let mut disassembly = String::new();
+        let mut line_counts: Vec<(usize, u64)> = Vec::new();
+        // Skip instructions that were never executed have no branch counts
+        let mut line_branches: Vec<(usize, Option<(u64, u64)>)> = Vec::new();
+        let mut line_number = 0;
+
+        let mut address = consts::PROGRAM_MEMORY_ADDR;
+        while address < rom_end {
+            line_number += 1;
+            let count = self.address_counts[address];
+
+            // A byte before an instruction at an odd address can't be an instruction itself
+            if count == 0 && self.address_counts.get(address + 1).copied().unwrap_or(0) > 0 {
+                disassembly.push_str(&format!("{:>10}  {:#06x}  {:02x}    db {:#04x}\n", "", address, self.rom_byte(address), self.rom_byte(address)));
+                address += 1;
+                continue;
+            }
+
+            let opcode = ((self.rom_byte(address) as u16) << 8) + self.rom_byte(address + 1) as u16;
+            let decoded = instruction::decode(opcode).ok();
+            let text = match decoded {
+                Some(decoded) => decoded.to_string(),
+                None => format!("dw {:#06x}", opcode)
+            };
+            let count_column = if count > 0 { count.to_string() } else { String::from("-") };
+            disassembly.push_str(&format!("{:>10}  {:#06x}  {:04x}  {}", count_column, address, opcode, text));
+
+            if count > 0 {
+                line_counts.push((line_number, count));
+                if let Some((taken, not_taken)) = self.branches.get(&(address as u16)) {
+                    disassembly.push_str(&format!("    ; skipped {}, not skipped {}", taken, not_taken));
+                    line_branches.push((line_number, Some((*taken, *not_taken))));
+                }
+            } else if let Some(decoded) = decoded.filter(|_| opcode != 0) {
+                // Only lines that look like code count as not covered, zeros and invalid opcodes are data
+                line_counts.push((line_number, 0));
+                if decoded.is_skip() {
+                    line_branches.push((line_number, None));
+                }
+            }
+
+            disassembly.push('\n');
+            address += 2;
+        }
+
+        let mut lcov = String::new();
+        lcov.push_str("TN:\n");
+        lcov.push_str(&format!("SF:{}\n", source_name));
+        for (line, count) in &line_counts {
+            lcov.push_str(&format!("DA:{},{}\n", line, count));
+        }
+        let mut branches_hit = 0;
+        for (line, counts) in &line_branches {
+            match counts {
+                Some((taken, not_taken)) => {
+                    lcov.push_str(&format!("BRDA:{},0,0,{}\n", line, taken));
+                    lcov.push_str(&format!("BRDA:{},0,1,{}\n", line, not_taken));
+                    branches_hit += (*taken > 0) as usize + (*not_taken > 0) as usize;
+                },
+                None => {
+                    lcov.push_str(&format!("BRDA:{},0,0,-\n", line));
+                    lcov.push_str(&format!("BRDA:{},0,1,-\n", line));
+                }
+            }
+        }
+        lcov.push_str(&format!("BRF:{}\n", line_branches.len() * 2));
+        lcov.push_str(&format!("BRH:{}\n", branches_hit));
+
+        let executed_instructions = line_counts.iter().filter(|(_, count)| *count > 0).count();
+        lcov.push_str(&format!("LF:{}\n", line_counts.len()));
+        lcov.push_str(&format!("LH:{}\n", executed_instructions));
+        lcov.push_str("end_of_record\n");
+
+        return CoverageReport{disassembly: disassembly, lcov: lcov, executed_instructions: executed_instructions, total_instructions: line_counts.len()};
+    }
+}
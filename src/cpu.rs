@@ -1,401 +1,870 @@
 use crate::memory::Memory;
-use crate::errors::Chip8Error;
+use crate::errors::{Chip8Error, MachineContext};
 use crate::consts;
 use crate::stack::Stack;
 use crate::delay_timer::DelayTimer;
 use crate::sound_timer::SoundTimer;
+use crate::quirks::Quirks;
+use crate::keypad::Keypad;
+use crate::framebuffer::Framebuffer;
+use crate::watchpoints::MemoryEvent;
+use crate::instruction::Instruction;
+use crate::profiler::Profiler;
+use crate::coverage::Coverage;
+use crate::recompiler::{self, Block, BlockCache};
+use crate::state::MachineState;
+
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
 
-use sdl2::pixels::Color;
-use sdl2::rect::Rect;
-use sdl2::render::Canvas;
-use sdl2::video::Window;
-use sdl2::keyboard::Scancode;
-
-use rand::Rng;
-use rand::rngs::ThreadRng;
-
-use std::collections::HashMap;
 use std::num::Wrapping;
+use std::rc::Rc;
+use std::time::Instant;
 
 pub struct CPU {
     memory_space: Memory,
     program_counter: u16,
-    canvas: Canvas<Window>,
     stack: Stack,
     registers: [u8; 16],
     index_register: u16,
-    rng: ThreadRng,
-    chip_to_real_key_map: HashMap::<u8, &'static str>,
+    rng: StdRng,
     wait_for_key_register: Option::<u8>,
     delay_timer: DelayTimer,
     sound_timer: SoundTimer,
-    display_state: Vec<Vec<bool>>,
+    display_state: Framebuffer,
+    quirks: Quirks,
+    memory_events: Vec<(u16, MemoryEvent)>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    block_cache: Option<BlockCache>,
 }
 
 impl CPU {
-    pub fn new(memory: Memory, canvas: Canvas<Window>) -> CPU {
-        let rng = rand::thread_rng();
-        CPU{memory_space: memory, program_counter: consts::PROGRAM_MEMORY_ADDR as u16, canvas: canvas, stack: Stack::new(), 
-            registers: [0x00; 16], index_register: 0x00, rng: rng, chip_to_real_key_map : consts::get_chip_to_real_key_map(), 
+    pub fn new(memory: Memory, quirks: Quirks) -> CPU {
+        let rng = StdRng::from_entropy();
+        CPU{memory_space: memory, program_counter: consts::PROGRAM_MEMORY_ADDR as u16, stack: Stack::new(quirks.stack_depth), 
+            registers: [0x00; 16], index_register: 0x00, rng: rng, 
             wait_for_key_register: None, delay_timer: DelayTimer::init_timer(), sound_timer: SoundTimer::init_timer(),
-            display_state: vec![vec![false; consts::DISPLAY_HEIGHT]; consts::DISPLAY_WIDTH]}
+            display_state: Framebuffer::new(consts::DISPLAY_WIDTH, consts::DISPLAY_HEIGHT), quirks: quirks,
+            memory_events: Vec::new(), profiler: None, coverage: None, block_cache: None}
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        return &self.display_state;
+    }
+
+    // Called once per frame, at 60hz
+    pub fn tick_timers(&mut self) {
+        self.delay_timer.tick();
+        self.sound_timer.tick();
+
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.end_frame();
+        }
+    }
+
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new());
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        return self.profiler.as_ref();
+    }
+
+    pub fn enable_coverage(&mut self, rom_content: Vec<u8>) {
+        self.coverage = Some(Coverage::new(rom_content));
+    }
+
+    pub fn coverage(&self) -> Option<&Coverage> {
+        return self.coverage.as_ref();
+    }
+
+    // Runs whole blocks of decoded code instead of single instructions, see step
+    pub fn enable_recompiler(&mut self) {
+        self.block_cache = Some(BlockCache::new(self.memory_space.size()));
+        self.memory_space.enable_write_log();
+    }
+
+    pub fn is_recompiler_enabled(&self) -> bool {
+        return self.block_cache.is_some();
+    }
+
+    // Register access for recompiled roms, which run the simple instructions natively
+    pub fn v(&self, register: usize) -> u8 {
+        return self.registers[register];
+    }
+
+    pub fn set_v(&mut self, register: usize, value: u8) {
+        self.registers[register] = value;
+    }
+
+    pub fn index_register(&self) -> u16 {
+        return self.index_register;
+    }
+
+    pub fn set_index_register(&mut self, value: u16) {
+        self.index_register = value;
+    }
+
+    pub fn program_counter(&self) -> u16 {
+        return self.program_counter;
+    }
+
+    pub fn set_program_counter(&mut self, program_counter: u16) {
+        self.program_counter = program_counter;
+    }
+
+    pub fn is_waiting_for_key(&self) -> bool {
+        return self.wait_for_key_register.is_some();
+    }
+
+    // Runs an instruction as if it was fetched from address, the program counter is left after it
+    pub fn execute_at(&mut self, address: u16, instruction: Instruction, keypad: &Keypad) -> Result<(), Chip8Error> {
+        self.program_counter = address;
+        return self.execute_decoded(instruction, keypad).map_err(|error| {
+            Chip8Error::ExecutionError{context: self.machine_context(address), error: Box::new(error)}
+        });
+    }
+
+    // Makes CXNN return the same numbers on every run
+    pub fn set_seed(&mut self, seed: u64) {
+        self.rng = StdRng::seed_from_u64(seed);
+    }
+
+    // Reads memory without triggering watchpoints
+    pub fn peek_memory(&self, address: usize) -> Result<u8, Chip8Error> {
+        return self.memory_space.peek_value(address);
+    }
+
+    // Writes from outside of the rom, like cheats, seen by the watchpoints and the recompiler like the writes of the rom
+    pub fn write_memory(&mut self, address: usize, value: u8) -> Result<(), Chip8Error> {
+        self.memory_space.set_value(address, value)?;
+        if let Some(block_cache) = self.block_cache.as_mut() {
+            for index in self.memory_space.take_writes() {
+                block_cache.on_write(index);
+            }
+        }
+
+        for event in self.memory_space.take_events() {
+            self.memory_events.push((self.program_counter, event));
+        }
+
+        return Ok(());
+    }
+
+    pub fn is_sound_active(&self) -> bool {
+        return self.sound_timer.is_active();
+    }
+
+    // Delay and sound timer values
+    pub fn timers(&self) -> (u8, u8) {
+        return (self.delay_timer.get_value(), self.sound_timer.get_value());
+    }
+
+    pub fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer.set_value(value);
+    }
+
+    pub fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer.set_value(value);
+    }
+
+    pub fn memory_size(&self) -> usize {
+        return self.memory_space.size();
+    }
+
+    pub fn stack(&self) -> &Stack {
+        return &self.stack;
+    }
+
+    pub fn save_state(&self) -> MachineState {
+        let (delay_timer, sound_timer) = self.timers();
+        return MachineState{program_counter: self.program_counter, index_register: self.index_register, registers: self.registers,
+            stack_frames: self.stack.frames().to_vec(), stack_depth: self.stack.max_depth(), wait_for_key_register: self.wait_for_key_register,
+            delay_timer: delay_timer, sound_timer: sound_timer, display_width: self.display_state.width(),
+            display_height: self.display_state.height(), pixels: self.display_state.pixels().to_vec(),
+            memory: self.memory_space.contents().to_vec()};
+    }
+
+    // States are only loaded into machines with the same memory size and stack depth they were saved from
+    pub fn load_state(&mut self, state: &MachineState) -> Result<(), Chip8Error> {
+        if state.stack_depth != self.stack.max_depth() {
+            return Err(Chip8Error::InvalidSaveState(format!("Stack of {} entries doesn't fit the {} entries of this machine", state.stack_depth, self.stack.max_depth())));
+        }
+        if state.pixels.len() != state.display_width * state.display_height {
+            return Err(Chip8Error::InvalidSaveState(format!("Got {} pixels for a {}x{} display", state.pixels.len(), state.display_width, state.display_height)));
+        }
+        if state.wait_for_key_register.is_some_and(|register| register >= 16) {
+            return Err(Chip8Error::InvalidSaveState(format!("Waiting for a key in register {}, there are only 16", state.wait_for_key_register.unwrap())));
+        }
+        self.memory_space.restore(&state.memory)?;
+
+        self.program_counter = state.program_counter;
+        self.index_register = state.index_register;
+        self.registers = state.registers;
+        self.stack.clear();
+        for frame in &state.stack_frames {
+            self.stack.push(*frame)?;
+        }
+        self.wait_for_key_register = state.wait_for_key_register;
+        self.delay_timer.set_value(state.delay_timer);
+        self.sound_timer.set_value(state.sound_timer);
+        self.display_state = Framebuffer::from_pixels(state.display_width, state.display_height, state.pixels.clone());
+
+        // Blocks were compiled from the replaced memory
+        if self.block_cache.is_some() {
+            self.block_cache = Some(BlockCache::new(self.memory_space.size()));
+            self.memory_space.take_writes();
+        }
+
+        return Ok(());
+    }
+
+    // With the memory stack quirk the return addresses are also written to memory, and read back from there
+    fn push_stack(&mut self, return_address: u16) -> Result<(), Chip8Error> {
+        let slot_address = consts::VIP_STACK_ADDR + self.stack.depth() * 2;
+        self.stack.push(return_address)?;
+        trace!("STACK | Pushed {:#06x}, depth is {}/{}", return_address, self.stack.depth(), self.stack.max_depth());
+
+        if self.quirks.memory_stack {
+            self.memory_space.set_value(slot_address, (return_address >> 8) as u8)?;
+            self.memory_space.set_value(slot_address + 1, (return_address & 0xFF) as u8)?;
+        }
+
+        return Ok(());
+    }
+
+    fn pop_stack(&mut self) -> Result<u16, Chip8Error> {
+        let mut return_address = self.stack.pop()?;
+
+        if self.quirks.memory_stack {
+            let slot_address = consts::VIP_STACK_ADDR + self.stack.depth() * 2;
+            return_address = ((self.memory_space.get_value(slot_address)? as u16) << 8) + self.memory_space.get_value(slot_address + 1)? as u16;
+        }
+
+        return Ok(return_address);
+    }
+
+    pub fn is_hires(&self) -> bool {
+        return self.display_state.width() == consts::HIRES_DISPLAY_WIDTH;
+    }
+
+    // Switches between the 64x32 and the SUPER-CHIP 128x64 modes, clearing the screen
+    fn set_hires(&mut self, hires: bool) {
+        if hires == self.is_hires() {
+            return;
+        }
+
+        if hires {
+            self.display_state = Framebuffer::new(consts::HIRES_DISPLAY_WIDTH, consts::HIRES_DISPLAY_HEIGHT);
+        } else {
+            self.display_state = Framebuffer::new(consts::DISPLAY_WIDTH, consts::DISPLAY_HEIGHT);
+        }
+
+        info!("DRAW_ACTION | Display resolution changed to {}x{}", self.display_state.width(), self.display_state.height());
     }
 
-    pub fn draw_sprite(&mut self, sprite_content: Vec<u8>, x_coord: u8, y_coord: u8) -> Result<(), String> {
+    // Every row is sprite_width pixels wide, most significant bit first
+    // Returns whether any lit pixel was turned off
+    pub fn draw_sprite(&mut self, sprite_content: Vec<u16>, sprite_width: i32, x_coord: u8, y_coord: u8) -> bool {
         debug!("DRAW_ACTION | Displaying sprite");
         debug!("DRAW_ACTION | Sprite content : {:?}", sprite_content);
         debug!("DRAW_ACTION | Sprite coords : ({},{})", x_coord, y_coord);
+
+        let display_width = self.display_state.width() as i32;
+        let display_height = self.display_state.height() as i32;
     
-        let mut y = (y_coord as i32) % consts::DISPLAY_HEIGHT as i32;
-        for sprite in sprite_content {
-            let mut value = sprite;
+        let mut collision = false;
+        let start_x = (x_coord as i32) % display_width;
+        let start_y = (y_coord as i32) % display_height;
+        for (row, sprite) in sprite_content.iter().enumerate() {
+            let mut y = start_y + row as i32;
+            if y >= display_height {
+                if self.quirks.clip_sprites {
+                    break;
+                }
+                y %= display_height;
+            }
+
+            let mut value = *sprite;
             
-            for i in 0..8 {
-                let x = (x_coord as i32 + (7 - i)) % consts::DISPLAY_WIDTH as i32;
+            for i in 0..sprite_width {
+                let mut x = start_x + (sprite_width - 1 - i);
+                let sprite_pixel = (value & 0b1) as u8;
+                value >>= 1;
+                // Unset sprite pixels leave the screen as it is
+                if sprite_pixel == 0 {
+                    continue;
+                }
+                if x >= display_width {
+                    if self.quirks.clip_sprites {
+                        continue;
+                    }
+                    x %= display_width;
+                }
 
-                let is_pixel_on = self.display_state[x as usize][y as usize] as u8;
-                if ((value & 0b1) ^ is_pixel_on) == 1 {
-                    self.canvas.set_draw_color(Color::WHITE);
-                    self.display_state[x as usize][y as usize] = true;
-                } else {
-                    self.canvas.set_draw_color(Color::BLACK);
-                    self.display_state[x as usize][y as usize] = false;
+                let is_pixel_on = self.display_state.get_pixel(x as usize, y as usize) as u8;
+                if sprite_pixel == 1 && is_pixel_on == 1 {
+                    collision = true;
                 }
-                
-                value = value >> 1;
-                self.canvas.fill_rect(Rect::new(x, y, 1, 1))?;
+
+                self.display_state.set_pixel(x as usize, y as usize, (sprite_pixel ^ is_pixel_on) == 1);
             }
-            y += 1;
-            y %= consts::DISPLAY_HEIGHT as i32;
         }
     
-        self.canvas.present();
-    
-        return Ok(());
+        return collision;
     }
 
-    pub fn execute_instruction(&mut self, windows_pressed_keys: Vec::<Scancode>) -> Result<(),Chip8Error> {
-        // Check if wait for keyboard
-        if self.wait_for_key_register.is_some() {
-            // Wait for enter press
-            let mut found_key = false;
-            'find_key: for key in &windows_pressed_keys {
-                let windows_pressed_key_name = key.name();
-                trace!("KEYPAD_ACTION | Pressed key name : {}", windows_pressed_key_name);
-                for chip_key in self.chip_to_real_key_map.keys() {
-                    let windows_is_pressed_key = self.chip_to_real_key_map[chip_key];
-                    if windows_is_pressed_key == windows_pressed_key_name {
-                        let x_register = self.wait_for_key_register.unwrap();
-                        self.registers[x_register as usize] = *chip_key;
-                        self.wait_for_key_register = None;
-                        
-                        debug!("KEYPAD_ACTION | Leaving wait for keypress mode");
-                        found_key = true;
-        
-                        break 'find_key;
-                    }
+    pub fn execute_instruction(&mut self, keypad: &Keypad) -> Result<(),Chip8Error> {
+        let program_counter = self.program_counter;
+        let instruction_start_time = self.profiler.as_ref().map(|_| Instant::now());
+        let result = self.run_instruction(keypad);
+
+        if let (Some(profiler), Some(start_time)) = (self.profiler.as_mut(), instruction_start_time) {
+            profiler.record_instruction_time(start_time.elapsed());
+        }
+
+        for event in self.memory_space.take_events() {
+            self.memory_events.push((program_counter, event));
+        }
+
+        return result.map_err(|error| {
+            Chip8Error::ExecutionError{context: self.machine_context(program_counter), error: Box::new(error)}
+        });
+    }
+
+    // Runs up to max_instructions and returns how many ran, one instruction with the interpreter and a block with the recompiler.
+    // Blocks stop early at the end of the budget, so frames run exactly as many instructions with both.
+    pub fn step(&mut self, keypad: &Keypad, max_instructions: u32) -> Result<u32, Chip8Error> {
+        let block = match self.block_cache.as_ref() {
+            Some(block_cache) if self.wait_for_key_register.is_none() => block_cache.get(self.program_counter),
+            _ => {
+                self.execute_instruction(keypad)?;
+                return Ok(1);
+            }
+        };
+
+        let block = match block.or_else(|| self.compile_block(self.program_counter)) {
+            Some(block) => block,
+            None => {
+                // Self modifying code, invalid opcodes and code that wraps around memory
+                self.execute_instruction(keypad)?;
+                return Ok(1);
+            }
+        };
+
+        let mut executed = 0;
+        for instruction in block.instructions.iter().take(max_instructions as usize) {
+            let program_counter = self.program_counter;
+            executed += 1;
+            let instruction_start_time = self.profiler.as_ref().map(|_| Instant::now());
+            let result = self.execute_decoded(*instruction, keypad);
+            if let (Some(profiler), Some(start_time)) = (self.profiler.as_mut(), instruction_start_time) {
+                profiler.record_instruction_time(start_time.elapsed());
+            }
+            for event in self.memory_space.take_events() {
+                self.memory_events.push((program_counter, event));
+            }
+            result.map_err(|error| {
+                Chip8Error::ExecutionError{context: self.machine_context(program_counter), error: Box::new(error)}
+            })?;
+
+            if self.memory_space.has_writes() {
+                let block_cache = self.block_cache.as_mut().unwrap();
+                let mut modified_code = false;
+                for index in self.memory_space.take_writes() {
+                    modified_code |= block_cache.on_write(index);
+                }
+
+                // The rest of the block may have changed
+                if modified_code {
+                    break;
                 }
             }
+        }
 
-            if !found_key {
-                return Ok(());
+        return Ok(executed);
+    }
+
+    fn compile_block(&mut self, start: u16) -> Option<Rc<Block>> {
+        let block_cache = self.block_cache.as_ref()?;
+        let mut instructions = Vec::new();
+        let mut address = start as usize;
+        while instructions.len() < recompiler::MAX_BLOCK_LENGTH && block_cache.can_compile(address) {
+            // Compiled code counts as executed, so that writing over it is still reported as self modifying code
+            let instruction = match self.memory_space.fetch_instruction(address) {
+                Ok((_, instruction)) => instruction,
+                Err(_) => break
+            };
+
+            instructions.push(instruction);
+            address += 2;
+            if recompiler::ends_block(&instruction) {
+                break;
+            }
+        }
+
+        if instructions.is_empty() {
+            return None;
+        }
+
+        trace!("RECOMPILER | Compiled {} instructions at {:#06x}", instructions.len(), start);
+        return Some(self.block_cache.as_mut()?.insert(Block{start: start, instructions: instructions}));
+    }
+
+    // The watchpoint hits and self modifying code since the last call, with the address of the instruction that caused them
+    pub fn take_memory_events(&mut self) -> Vec<(u16, MemoryEvent)> {
+        return std::mem::take(&mut self.memory_events);
+    }
+
+    pub fn machine_context(&self, program_counter: u16) -> MachineContext {
+        let opcode = ((self.memory_space.peek_value(program_counter as usize).unwrap_or(0) as u16) << 8)
+            + self.memory_space.peek_value(program_counter as usize + 1).unwrap_or(0) as u16;
+
+        return MachineContext{program_counter: program_counter, opcode: opcode, index_register: self.index_register,
+            registers: self.registers, stack_frames: self.stack().frames().to_vec()};
+    }
+
+    fn run_instruction(&mut self, keypad: &Keypad) -> Result<(),Chip8Error> {
+        // Check if wait for keyboard
+        if let Some(x_register) = self.wait_for_key_register {
+            match keypad.first_pressed() {
+                Some(chip_key) => {
+                    trace!("KEYPAD_ACTION | Pressed key : {:X}", chip_key);
+                    self.registers[x_register as usize] = chip_key;
+                    self.wait_for_key_register = None;
+
+                    debug!("KEYPAD_ACTION | Leaving wait for keypress mode");
+                },
+                None => {
+                    return Ok(());
+                }
             }
         }
 
         // Parse instruction
-        let instruction_double: u16 = ((self.memory_space.get_value(self.program_counter) as u16) << 8) + self.memory_space.get_value(self.program_counter + 1) as u16;
+        let (instruction_double, instruction) = self.memory_space.fetch_instruction(self.program_counter as usize).map_err(|error| {
+            if let Chip8Error::InvalidInstruction(opcode) = error {
+                error!("Invalid instruction : {:#06x}", opcode);
+            }
+            error
+        })?;
         trace!("CURRENT_OPCODE | {:#06x} -> {:#06x}", self.program_counter, instruction_double);
-        
-        // Execute simple instructions
-        match instruction_double {
-            0x00E0 => { //CLS - Clear screen
-                self.canvas.clear();
-            },
-            0x00EE => { // RETURN
-                self.program_counter = self.stack.pop()?;
-            },
-            _ => { // If the instruction requires parsing the opcode nibbles
-                // Parse nibbles
-                let instruction_nibbles: [u8; 4] = [
-                    ((instruction_double >> 12) & 0b00001111).try_into().unwrap(),
-                    ((instruction_double >> 8) & 0b00001111).try_into().unwrap(),
-                    ((instruction_double >> 4) & 0b00001111).try_into().unwrap(),
-                    (instruction_double & 0b0001111).try_into().unwrap()
-                ];
-
-                // Decode and execute instruction
-                match instruction_nibbles[0] {
-                    0 => {}, //SYS Addr, ignored by modern interpreters
-                    1 => { //JUMP
-                        let new_addr = instruction_double & 0b0000111111111111;
-                        self.program_counter = new_addr;
-                        return Ok(());
-                    },
-                    2 => { //CALL
-                        let new_addr = instruction_double & 0b0000111111111111;
-                        self.stack.push(self.program_counter);
-
-                        self.program_counter = new_addr;
-                        return Ok(());
-                    },
-                    3 => { //SE - Skip if equal
-                        let register_index = instruction_nibbles[1];
-                        let comp_value = (instruction_nibbles[2] << 4) + instruction_nibbles[3];
-
-                        if self.registers[register_index as usize] == comp_value {
-                            debug!("Register {:X} has value {}, skipping next instruction", register_index, comp_value);
-                            self.program_counter += 2;
-                        } else {
-                            debug!("Register {:X} has value {} instead of {}, not skipping next instruction", 
-                                register_index, self.registers[register_index as usize], comp_value);
-                        }
-                    },
-                    4 => { //SNE - Skip if not equal
-                        let register_index = instruction_nibbles[1];
-                        let comp_value = (instruction_nibbles[2] << 4) + instruction_nibbles[3];
-
-                        if self.registers[register_index as usize] != comp_value {
-                            self.program_counter += 2;
-                        }
-                    },
-                    5 => { //SE - Skip if registers equals
-                        if instruction_nibbles[3] != 0 {
-                            error!("Invalid instruction : {:#06x}", instruction_double);
-                            return Err(Chip8Error::InvalidInstruction);
-                        }
-
-                        let first_register_index = instruction_nibbles[1];
-                        let second_register_index = instruction_nibbles[2];
-                        
-                        if self.registers[first_register_index as usize] == self.registers[second_register_index as usize] {
-                            self.program_counter += 2;
-                        }
-                    },
-                    6 => { //LD - Set register value
-                        let register_index = instruction_nibbles[1];
-                        let new_value = (instruction_nibbles[2] << 4) + instruction_nibbles[3];
-
-                        self.registers[register_index as usize] = new_value;
-                        trace!("Loading value {} to register {:#x}", new_value, register_index);
-                    },
-                    7 => { // ADD - Add to register
-                        let add_value = (instruction_nibbles[2] << 4) + instruction_nibbles[3];
-                        self.registers[instruction_nibbles[1] as usize] = (Wrapping(self.registers[instruction_nibbles[1] as usize] as u8) + Wrapping(add_value as u8)).0;
-                    },
-                    8 =>{ //LD - Registers
-                        let x_register = instruction_nibbles[1] as usize;
-                        let y_register = instruction_nibbles[2] as usize;
-
-                        match instruction_nibbles[3] {
-                            0 => { //Assign
-                                self.registers[x_register] = self.registers[y_register];
-                            },
-                            1 => { //Bitwise or
-                                self.registers[x_register] = self.registers[x_register] | self.registers[y_register];
-                            },
-                            2 => { //Bitwise and
-                                self.registers[x_register] = self.registers[x_register] & self.registers[y_register];
-                            },
-                            3 => { //Bitwise xor
-                                self.registers[x_register] = self.registers[x_register] ^ self.registers[y_register];
-                            },
-                            4 => { //Add
-                                let add_result: u16 = self.registers[x_register] as u16 + self.registers[y_register] as u16;
-                                if add_result > u8::MAX as u16 {
-                                    self.registers[0x0F] = 1;
-                                } else {
-                                    self.registers[0x0F] = 0;
-                                }
-
-                                self.registers[x_register] = add_result as u8;
-                            },
-                            5 => { //Sub
-                                if self.registers[x_register] > self.registers[y_register] {
-                                    self.registers[0x0F] = 1;
-                                } else {
-                                    self.registers[0x0F] = 0;
-                                }
-
-                                self.registers[x_register] = (Wrapping(self.registers[x_register]) - Wrapping(self.registers[y_register])).0;
-                            },
-                            6 => { //Shift Right
-                                let before_value = self.registers[x_register];
-
-                                self.registers[0x0F] = self.registers[x_register] & 0b00000001;
-                                self.registers[x_register] = self.registers[x_register] >> 1;
-
-                                trace!("Register {} shifted right from {} to {}", x_register, before_value, self.registers[x_register]);
-                            },
-                            7 => { //SubN
-                                if self.registers[y_register] > self.registers[x_register] {
-                                    self.registers[0x0F] = 1;
-                                } else {
-                                    self.registers[0x0F] = 0;
-                                }
-
-                                self.registers[x_register] = self.registers[y_register] - self.registers[x_register];
-                            },
-                            0xE => { //Shift Left
-                                let before_value = self.registers[x_register];
-                                self.registers[0x0F] = self.registers[x_register] & 0b10000000;
-                                self.registers[x_register] = self.registers[x_register] << 1;
-
-                                trace!("Register {} shifted left from {} to {}", x_register, before_value, self.registers[x_register]);
-                            },
-                            _ => {
-                                error!("Invalid instruction : {:#06x}", instruction_double);
-                                return Err(Chip8Error::InvalidInstruction);
-                            }
-                        }
-                    },
-                    9 => { //SNE
-                        if instruction_nibbles[3] != 0 {
-                            error!("Invalid instruction : {:#06x}", instruction_double);
-                            return Err(Chip8Error::InvalidInstruction);
-                        }
-
-                        let x_register = instruction_nibbles[1] as usize;
-                        let y_register = instruction_nibbles[2] as usize;
-
-                        if self.registers[x_register] != self.registers[y_register] {
-                            self.program_counter += 2;
-                        }
-                    }
-                    0xA => { // LD I - Set Index register
-                        let new_value: u16 = instruction_double % 0x1000;
-                        self.index_register = new_value;
-                    },
-                    0xB => { //Jump V0
-                        let mut target_addr: u16 = instruction_double % 0x1000;
-                        target_addr += self.registers[0x00] as u16;
-
-                        self.program_counter = target_addr;
-                    },
-                    0xC => { //RND
-                        let x_register = instruction_nibbles[1] as usize;
-                        let and_mask = ((instruction_nibbles[2]) << 4) + instruction_nibbles[3];
-
-                        let rand_value = self.rng.gen::<u8>() & and_mask;
-                        self.registers[x_register] = rand_value;
+
+        return self.execute_decoded(instruction, keypad);
+    }
+
+    // Runs an instruction that is at the program counter
+    fn execute_decoded(&mut self, instruction: Instruction, keypad: &Keypad) -> Result<(),Chip8Error> {
+        let instruction_address = self.program_counter;
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.record_instruction(instruction_address, &instruction);
+        }
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.record_instruction(instruction_address);
+        }
+
+        // Execute instruction
+        match instruction {
+            Instruction::ClearScreen => {
+                self.display_state.clear();
+            },
+            Instruction::Return => {
+                self.program_counter = self.pop_stack()?;
+            },
+            Instruction::LowResolution => { // SUPER-CHIP 64x32 mode
+                self.set_hires(false);
+            },
+            Instruction::HighResolution => { // SUPER-CHIP 128x64 mode
+                self.set_hires(true);
+            },
+            Instruction::System(_) => {}, // Ignored by modern interpreters
+            Instruction::Jump(new_addr) => {
+                self.program_counter = new_addr;
+                return Ok(());
+            },
+            Instruction::Call(new_addr) => {
+                self.push_stack(self.program_counter)?;
+
+                self.program_counter = new_addr;
+                return Ok(());
+            },
+            Instruction::SkipEqualByte{x, value} => {
+                if self.registers[x as usize] == value {
+                    debug!("Register {:X} has value {}, skipping next instruction", x, value);
+                    self.program_counter = self.program_counter.wrapping_add(2);
+                } else {
+                    debug!("Register {:X} has value {} instead of {}, not skipping next instruction",
+                        x, self.registers[x as usize], value);
+                }
+            },
+            Instruction::SkipNotEqualByte{x, value} => {
+                if self.registers[x as usize] != value {
+                    self.program_counter = self.program_counter.wrapping_add(2);
+                }
+            },
+            Instruction::SkipEqualRegister{x, y} => {
+                if self.registers[x as usize] == self.registers[y as usize] {
+                    self.program_counter = self.program_counter.wrapping_add(2);
+                }
+            },
+            Instruction::LoadByte{x, value} => {
+                self.registers[x as usize] = value;
+                trace!("Loading value {} to register {:#x}", value, x);
+            },
+            Instruction::AddByte{x, value} => {
+                self.registers[x as usize] = (Wrapping(self.registers[x as usize]) + Wrapping(value)).0;
+            },
+            Instruction::Assign{x, y} => {
+                self.registers[x as usize] = self.registers[y as usize];
+            },
+            Instruction::Or{x, y} => {
+                self.registers[x as usize] |= self.registers[y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.registers[0x0F] = 0;
+                }
+            },
+            Instruction::And{x, y} => {
+                self.registers[x as usize] &= self.registers[y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.registers[0x0F] = 0;
+                }
+            },
+            Instruction::Xor{x, y} => {
+                self.registers[x as usize] ^= self.registers[y as usize];
+                if self.quirks.logic_resets_vf {
+                    self.registers[0x0F] = 0;
+                }
+            },
+            Instruction::AddRegister{x, y} => {
+                let add_result: u16 = self.registers[x as usize] as u16 + self.registers[y as usize] as u16;
+                self.registers[x as usize] = add_result as u8;
+                self.registers[0x0F] = (add_result > u8::MAX as u16) as u8;
+            },
+            Instruction::Sub{x, y} => {
+                let (x_value, y_value) = (self.registers[x as usize], self.registers[y as usize]);
+                self.registers[x as usize] = (Wrapping(x_value) - Wrapping(y_value)).0;
+                self.registers[0x0F] = (x_value > y_value) as u8;
+            },
+            Instruction::ShiftRight{x, y} => {
+                let before_value = if self.quirks.shift_uses_vy {
+                    self.registers[y as usize]
+                } else {
+                    self.registers[x as usize]
+                };
+
+                self.registers[x as usize] = before_value >> 1;
+                self.registers[0x0F] = before_value & 0b00000001;
+
+                trace!("Register {} shifted right from {} to {}", x, before_value, self.registers[x as usize]);
+            },
+            Instruction::SubN{x, y} => {
+                let (x_value, y_value) = (self.registers[x as usize], self.registers[y as usize]);
+                self.registers[x as usize] = (Wrapping(y_value) - Wrapping(x_value)).0;
+                self.registers[0x0F] = (y_value > x_value) as u8;
+            },
+            Instruction::ShiftLeft{x, y} => {
+                let before_value = if self.quirks.shift_uses_vy {
+                    self.registers[y as usize]
+                } else {
+                    self.registers[x as usize]
+                };
+
+                self.registers[x as usize] = before_value << 1;
+                self.registers[0x0F] = (before_value & 0b10000000) >> 7;
+
+                trace!("Register {} shifted left from {} to {}", x, before_value, self.registers[x as usize]);
+            },
+            Instruction::SkipNotEqualRegister{x, y} => {
+                if self.registers[x as usize] != self.registers[y as usize] {
+                    self.program_counter = self.program_counter.wrapping_add(2);
+                }
+            },
+            Instruction::LoadIndex(new_value) => {
+                self.index_register = new_value;
+            },
+            Instruction::JumpOffset{x, address} => {
+                if self.quirks.jump_uses_vx {
+                    self.program_counter = address + self.registers[x as usize] as u16;
+                } else {
+                    self.program_counter = address + self.registers[0x00] as u16;
+                }
+
+                return Ok(());
+            },
+            Instruction::Random{x, mask} => {
+                let rand_value = self.rng.gen::<u8>() & mask;
+                self.registers[x as usize] = rand_value;
+            },
+            Instruction::Draw{x, y, height} => {
+                let sprite_memory_addr = self.index_register as usize;
+                trace!("Reading sprite content from address {:#06x}", sprite_memory_addr);
+
+                let mut sprite_content = Vec::<u16>::new();
+                let mut sprite_width = 8;
+
+                if height == 0 && self.is_hires() { // SUPER-CHIP 16x16 sprite
+                    sprite_width = 16;
+                    for i in 0..16 {
+                        let row_addr = sprite_memory_addr + i * 2;
+                        sprite_content.push(((self.memory_space.get_value(row_addr)? as u16) << 8) + self.memory_space.get_value(row_addr + 1)? as u16);
                     }
-                    0xD => { // DRW - Draw sprite on screen
-                        let sprite_length = instruction_nibbles[3];
-                        let sprite_memory_addr = self.index_register;
-                        trace!("Reading sprite content from address {:#06x}", sprite_memory_addr);
-
-                        let mut sprite_content = Vec::<u8>::new();
-
-                        for i in 0..sprite_length {
-                            sprite_content.push(self.memory_space.get_value(sprite_memory_addr + i as u16));
-                        }
-
-                        let x_coord = self.registers[instruction_nibbles[1] as usize];
-                        let y_coord = self.registers[instruction_nibbles[2] as usize];
-
-                        let draw_return = self.draw_sprite(sprite_content, x_coord, y_coord);
-                        if draw_return.is_err() {
-                            let error_msg = draw_return.unwrap_err();
-                            error!("Display error : {}", error_msg);
-                            return Err(Chip8Error::DisplayError(error_msg));
-                        }
-
-                    },
-                    0xE => { // SKP - If key pressed / not pressed
-                        let x_register = instruction_nibbles[1] as usize;
-                        let keycode = self.registers[x_register];
-                        let target_key_real_name = self.chip_to_real_key_map[&keycode];
-
-                        if instruction_nibbles[2] == 9 && instruction_nibbles[3] == 0xE { //Skip if pressed
-                            'find_key_exists: for key in &windows_pressed_keys {
-                                if target_key_real_name == key.name() {
-                                    self.program_counter += 2;
-                                    break 'find_key_exists;
-                                }
-                            }
-                        } else if instruction_nibbles[2] == 0xA && instruction_nibbles[3] == 1 { //Skip if not pressed
-                            let mut found_key = false;
-                            'find_key_notexists: for key in &windows_pressed_keys {
-                                if target_key_real_name == key.name() {
-                                    found_key = true;
-                                    break 'find_key_notexists;
-                                }
-                            }
-
-                            if !found_key {
-                                self.program_counter += 2;
-                            }
-                        }
-                    },
-                    0xF => {
-                        let last_byte = ((instruction_nibbles[2]) << 4) + instruction_nibbles[3];
-                        let x_register = instruction_nibbles[1] as usize;
-
-
-                        match last_byte {
-                            0x07 => { // Get delay timer
-                                self.registers[x_register] = self.delay_timer.get_value();
-                            },
-                            0x0A => { // Wait for keypress
-                                debug!("KEYPAD_ACTION | Entering wait for keypress mode");
-                                if self.wait_for_key_register.is_some() {
-                                    return Err(Chip8Error::WaitForKeypressDuringWaitMode);
-                                }
-
-                                self.wait_for_key_register = Some(x_register as u8);
-                            },
-                            0x15 => { // Set delay timer
-                                self.delay_timer.set_value(self.registers[x_register]);
-                            },
-                            0x18 => { // Set sound timer
-                                self.sound_timer.set_value(self.registers[x_register]);
-                            },
-                            0x1E => { // ADD Index,Vx
-                                self.index_register = (Wrapping(self.index_register) + Wrapping(self.registers[x_register] as u16)).0;
-                                trace!("Index register has value : {:#06x}", self.index_register);
-                            },
-                            0x29 => { // Get digit font addr
-                                self.index_register = self.memory_space.get_font_addr(self.registers[x_register])?;
-                            },
-                            0x33 => { // Store Decimal representation of register
-                                let x_value = self.registers[x_register];
-                                trace!("Storing decimal representation value of {}", x_value);
-
-                                let ones_digit: u8 = (x_value % 10) as u8;
-                                let tens_digit: u8 = (x_value % 100) / 10 as u8;
-                                let hunderds_digit: u8 = (x_value) / 100 as u8;
-
-                                self.memory_space.set_value(self.index_register + 1, hunderds_digit);
-                                self.memory_space.set_value(self.index_register + 2, tens_digit);
-                                self.memory_space.set_value(self.index_register + 3, ones_digit);
-                            },
-                            0x55 => { // Store registers to memory
-                                for i in 0..x_register+1 {
-                                    self.index_register += 1;
-                                    self.memory_space.set_value(self.index_register, self.registers[i]);
-                                }
-                            },
-                            0x65 => { // Read register from memory
-                                for i in 0..x_register+1 {
-                                    self.index_register += 1;
-                                    self.registers[i] = self.memory_space.get_value(self.index_register);
-                                    trace!("Register {}(#{:#6x}) = {}", i, self.index_register, self.registers[i]);
-                                }
-                            }
-                            _ => {
-                                error!("Invalid instruction : {:#06x}", instruction_double);
-                                return Err(Chip8Error::InvalidInstruction);   
-                            }
-                        }
-                    },
-                    _ => {
-                        error!("Invalid instruction : {:#06x}", instruction_double);
-                        return Err(Chip8Error::InvalidInstruction);
+                } else {
+                    for i in 0..height {
+                        sprite_content.push(self.memory_space.get_value(sprite_memory_addr + i as usize)? as u16);
                     }
                 }
+
+                let x_coord = self.registers[x as usize];
+                let y_coord = self.registers[y as usize];
+
+                let draw_start_time = self.profiler.as_ref().map(|_| Instant::now());
+                let collision = self.draw_sprite(sprite_content, sprite_width, x_coord, y_coord);
+                self.registers[0x0F] = collision as u8;
+                if let (Some(profiler), Some(start_time)) = (self.profiler.as_mut(), draw_start_time) {
+                    profiler.record_draw_time(start_time.elapsed());
+                }
+            },
+            Instruction::SkipKeyPressed{x} => {
+                if keypad.is_pressed(self.registers[x as usize]) {
+                    self.program_counter = self.program_counter.wrapping_add(2);
+                }
+            },
+            Instruction::SkipKeyNotPressed{x} => {
+                if !keypad.is_pressed(self.registers[x as usize]) {
+                    self.program_counter = self.program_counter.wrapping_add(2);
+                }
+            },
+            Instruction::LoadDelayTimer{x} => {
+                self.registers[x as usize] = self.delay_timer.get_value();
+            },
+            Instruction::WaitForKey{x} => {
+                debug!("KEYPAD_ACTION | Entering wait for keypress mode");
+                if self.wait_for_key_register.is_some() {
+                    return Err(Chip8Error::WaitForKeypressDuringWaitMode);
+                }
+
+                self.wait_for_key_register = Some(x);
+            },
+            Instruction::SetDelayTimer{x} => {
+                self.delay_timer.set_value(self.registers[x as usize]);
+            },
+            Instruction::SetSoundTimer{x} => {
+                self.sound_timer.set_value(self.registers[x as usize]);
+            },
+            Instruction::AddIndex{x} => {
+                self.index_register = (Wrapping(self.index_register) + Wrapping(self.registers[x as usize] as u16)).0;
+                trace!("Index register has value : {:#06x}", self.index_register);
+            },
+            Instruction::LoadFont{x} => {
+                self.index_register = self.memory_space.get_font_addr(self.registers[x as usize])?;
+            },
+            Instruction::StoreDecimal{x} => {
+                let x_value = self.registers[x as usize];
+                trace!("Storing decimal representation value of {}", x_value);
+
+                let ones_digit: u8 = x_value % 10;
+                let tens_digit: u8 = (x_value % 100) / 10;
+                let hunderds_digit: u8 = x_value / 100;
+
+                let index_addr = self.index_register as usize;
+                self.memory_space.set_value(index_addr, hunderds_digit)?;
+                self.memory_space.set_value(index_addr + 1, tens_digit)?;
+                self.memory_space.set_value(index_addr + 2, ones_digit)?;
+            },
+            Instruction::StoreRegisters{x} => {
+                for i in 0..x as usize + 1 {
+                    self.memory_space.set_value(self.index_register as usize + i, self.registers[i])?;
+                }
+
+                if self.quirks.load_store_increments_index {
+                    self.index_register = self.index_register.wrapping_add(x as u16 + 1);
+                }
+            },
+            Instruction::LoadRegisters{x} => {
+                for i in 0..x as usize + 1 {
+                    self.registers[i] = self.memory_space.get_value(self.index_register as usize + i)?;
+                    trace!("Register {}(#{:#6x}) = {}", i, self.index_register as usize + i, self.registers[i]);
+                }
+
+                if self.quirks.load_store_increments_index {
+                    self.index_register = self.index_register.wrapping_add(x as u16 + 1);
+                }
+            }
+        }
+
+        if instruction.is_skip() {
+            if let Some(coverage) = self.coverage.as_mut() {
+                coverage.record_branch(instruction_address, self.program_counter != instruction_address);
             }
         }
 
-        self.program_counter += 2;
+        self.program_counter = self.program_counter.wrapping_add(2);
         return Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::AddressPolicy;
+
+    const POLICIES: [AddressPolicy; 3] = [AddressPolicy::Wrap, AddressPolicy::Error, AddressPolicy::Clamp];
+
+    fn new_cpu(rom: &[u8], address_policy: AddressPolicy) -> CPU {
+        let mut quirks = Quirks::from_profile("chip8").unwrap();
+        quirks.address_policy = address_policy;
+        let memory = Memory::new_from_rom(rom.to_vec(), consts::MEMORY_SIZE, address_policy).unwrap();
+        return CPU::new(memory, quirks);
+    }
+
+    fn run(cpu: &mut CPU, instructions: usize) -> Result<(), Chip8Error> {
+        let keypad = Keypad::new();
+        for _ in 0..instructions {
+            cpu.step(&keypad, 1)?;
+        }
+        return Ok(());
+    }
+
+    fn assert_out_of_range(result: Result<(), Chip8Error>, address: usize) {
+        match result {
+            Err(Chip8Error::ExecutionError{error, ..}) => assert_eq!(*error, Chip8Error::AddressOutOfRange(address)),
+            other => panic!("Expected an out of range access at {:#x}, got {:?}", address, other)
+        }
+    }
+
+    #[test]
+    fn store_decimal_past_the_end_of_memory() {
+        // LD I, 0xFFE / LD V0, 251 / LD B, V0
+        let rom = [0xAF, 0xFE, 0x60, 0xFB, 0xF0, 0x33];
+        for policy in POLICIES {
+            let mut cpu = new_cpu(&rom, policy);
+            let result = run(&mut cpu, 3);
+            assert_eq!(cpu.peek_memory(0xFFE), Ok(2), "{:?}", policy);
+            match policy {
+                AddressPolicy::Wrap => {
+                    result.unwrap();
+                    assert_eq!(cpu.peek_memory(0xFFF), Ok(5));
+                    assert_eq!(cpu.peek_memory(0x000), Ok(1));
+                },
+                AddressPolicy::Error => {
+                    assert_out_of_range(result, 0x1000);
+                    assert_eq!(cpu.peek_memory(0xFFF), Ok(5));
+                },
+                AddressPolicy::Clamp => {
+                    result.unwrap();
+                    assert_eq!(cpu.peek_memory(0xFFF), Ok(1));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn store_registers_past_the_end_of_memory() {
+        // LD I, 0xFFE / LD V0, 1 / LD V1, 2 / LD V2, 3 / LD [I], V2
+        let rom = [0xAF, 0xFE, 0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xF2, 0x55];
+        for policy in POLICIES {
+            let mut cpu = new_cpu(&rom, policy);
+            let result = run(&mut cpu, 5);
+            assert_eq!(cpu.peek_memory(0xFFE), Ok(1), "{:?}", policy);
+            match policy {
+                AddressPolicy::Wrap => {
+                    result.unwrap();
+                    assert_eq!(cpu.peek_memory(0xFFF), Ok(2));
+                    assert_eq!(cpu.peek_memory(0x000), Ok(3));
+                    assert_eq!(cpu.index_register(), 0x1001);
+                },
+                AddressPolicy::Error => {
+                    assert_out_of_range(result, 0x1000);
+                    assert_eq!(cpu.peek_memory(0xFFF), Ok(2));
+                    assert_eq!(cpu.index_register(), 0xFFE);
+                },
+                AddressPolicy::Clamp => {
+                    result.unwrap();
+                    assert_eq!(cpu.peek_memory(0xFFF), Ok(3));
+                    assert_eq!(cpu.index_register(), 0x1001);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn load_registers_past_the_end_of_memory() {
+        // LD I, 0xFFE / LD V2, [I]
+        let rom = [0xAF, 0xFE, 0xF2, 0x65];
+        for policy in POLICIES {
+            let mut cpu = new_cpu(&rom, policy);
+            cpu.write_memory(0xFFE, 0x11).unwrap();
+            cpu.write_memory(0xFFF, 0x22).unwrap();
+            let result = run(&mut cpu, 2);
+            match policy {
+                AddressPolicy::Wrap => {
+                    result.unwrap();
+                    // The first byte of the font
+                    assert_eq!((cpu.v(0), cpu.v(1), cpu.v(2)), (0x11, 0x22, 0xF0));
+                },
+                AddressPolicy::Error => assert_out_of_range(result, 0x1000),
+                AddressPolicy::Clamp => {
+                    result.unwrap();
+                    assert_eq!((cpu.v(0), cpu.v(1), cpu.v(2)), (0x11, 0x22, 0x22));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn draw_sprite_past_the_end_of_memory() {
+        // LD I, 0xFFE / DRW V0, V1, 3
+        let rom = [0xAF, 0xFE, 0xD0, 0x13];
+        for policy in POLICIES {
+            let mut cpu = new_cpu(&rom, policy);
+            cpu.write_memory(0xFFE, 0x80).unwrap();
+            cpu.write_memory(0xFFF, 0x80).unwrap();
+            let result = run(&mut cpu, 2);
+            let framebuffer = cpu.framebuffer();
+            let third_row: Vec<bool> = framebuffer.pixels()[framebuffer.width() * 2..framebuffer.width() * 2 + 8].to_vec();
+            match policy {
+                AddressPolicy::Wrap => {
+                    result.unwrap();
+                    assert_eq!(third_row, [true, true, true, true, false, false, false, false]);
+                },
+                AddressPolicy::Error => {
+                    assert_out_of_range(result, 0x1000);
+                    assert!(framebuffer.pixels().iter().all(|pixel| !pixel));
+                },
+                AddressPolicy::Clamp => {
+                    result.unwrap();
+                    assert_eq!(third_row, [true, false, false, false, false, false, false, false]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn jump_with_offset_past_the_end_of_memory() {
+        // LD V0, 1 / JP V0, 0xFFF
+        let rom = [0x60, 0x01, 0xBF, 0xFF];
+        for policy in POLICIES {
+            let mut cpu = new_cpu(&rom, policy);
+            // LD VA, 0x42 at the start of memory, and LD V0, 0x60 read twice from the last byte
+            cpu.write_memory(0x000, 0x6A).unwrap();
+            cpu.write_memory(0x001, 0x42).unwrap();
+            cpu.write_memory(0xFFF, 0x60).unwrap();
+            run(&mut cpu, 2).unwrap();
+            assert_eq!(cpu.program_counter(), 0x1000, "{:?}", policy);
+
+            let result = run(&mut cpu, 1);
+            match policy {
+                AddressPolicy::Wrap => {
+                    result.unwrap();
+                    assert_eq!(cpu.v(0xA), 0x42);
+                },
+                AddressPolicy::Error => assert_out_of_range(result, 0x1000),
+                AddressPolicy::Clamp => {
+                    result.unwrap();
+                    assert_eq!(cpu.v(0), 0x60);
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,39 @@
+use crate::instruction::Instruction;
+
+// Decoded instructions by the memory index of their first byte, an entry is dropped when one of its bytes is written to
+pub struct DecodeCache {
+    entries: Vec<Option<(u16, Instruction)>>,
+    enabled: bool,
+}
+
+impl DecodeCache {
+    pub fn new(memory_size: usize) -> DecodeCache {
+        DecodeCache{entries: vec![None; memory_size], enabled: true}
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        self.clear();
+    }
+
+    pub fn clear(&mut self) {
+        self.entries.iter_mut().for_each(|entry| *entry = None);
+    }
+
+    pub fn get(&self, index: usize) -> Option<(u16, Instruction)> {
+        return self.entries[index];
+    }
+
+    pub fn insert(&mut self, index: usize, opcode: u16, instruction: Instruction) {
+        if self.enabled {
+            self.entries[index] = Some((opcode, instruction));
+        }
+    }
+
+    // A written byte is either the first byte of an instruction or the second byte of the one before it
+    pub fn invalidate(&mut self, index: usize) {
+        let previous_index = (index + self.entries.len() - 1) % self.entries.len();
+        self.entries[index] = None;
+        self.entries[previous_index] = None;
+    }
+}
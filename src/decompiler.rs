@@ -0,0 +1,369 @@
+use crate::consts;
+use crate::flow_graph::FlowGraph;
+use crate::instruction::Instruction;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+const DATA_BYTES_PER_LINE: usize = 8;
+const INDENT: &str = "  ";
+
+enum Statement {
+    Loop,
+    Again,
+    // Built from a skip followed by a forward jump, the body runs when the skip is taken
+    IfBegin(Instruction),
+    Else,
+    End,
+    Instruction(Instruction),
+    Invalid(u16),
+    Byte(u8),
+}
+
+enum Structure {
+    Loop{again_address: usize},
+    If{else_address: Option<usize>, end: usize},
+}
+
+// The address at which the innermost open structure closes, nested structures have to close before it
+fn structure_limit(structures: &[Structure], rom_end: usize) -> usize {
+    match structures.last() {
+        Some(Structure::Loop{again_address}) => *again_address,
+        Some(Structure::If{else_address: Some(else_address), ..}) => *else_address,
+        Some(Structure::If{end, ..}) => *end,
+        None => rom_end
+    }
+}
+
+// The condition of a skip instruction in Octo syntax, either the one under which it skips or its negation
+fn condition(skip: &Instruction, when_skipped: bool) -> String {
+    let (equal, not_equal) = if when_skipped { ("==", "!=") } else { ("!=", "==") };
+    let (key, not_key) = if when_skipped { ("key", "-key") } else { ("-key", "key") };
+    match skip {
+        Instruction::SkipEqualByte{x, value} => format!("v{:x} {} {}", x, equal, value),
+        Instruction::SkipNotEqualByte{x, value} => format!("v{:x} {} {}", x, not_equal, value),
+        Instruction::SkipEqualRegister{x, y} => format!("v{:x} {} v{:x}", x, equal, y),
+        Instruction::SkipNotEqualRegister{x, y} => format!("v{:x} {} v{:x}", x, not_equal, y),
+        Instruction::SkipKeyPressed{x} => format!("v{:x} {}", x, key),
+        Instruction::SkipKeyNotPressed{x} => format!("v{:x} {}", x, not_key),
+        _ => String::new()
+    }
+}
+
+fn target_name(labels: &BTreeMap<usize, String>, address: u16) -> String {
+    return labels.get(&(address as usize)).cloned().unwrap_or_else(|| format!("{:#05x}", address));
+}
+
+fn statement_text(instruction: &Instruction, labels: &BTreeMap<usize, String>) -> String {
+    match instruction {
+        Instruction::ClearScreen => String::from("clear"),
+        Instruction::Return => String::from("return"),
+        Instruction::LowResolution => String::from("lores"),
+        Instruction::HighResolution => String::from("hires"),
+        Instruction::System(address) => format!("{:#04x} {:#04x}  # machine code routine, ignored", address >> 8, address & 0xFF),
+        Instruction::Jump(address) => format!("jump {}", target_name(labels, *address)),
+        Instruction::Call(address) => match labels.get(&(*address as usize)) {
+            Some(label) => label.clone(),
+            None => format!(":call {:#05x}", address)
+        },
+        Instruction::SkipEqualByte{..} | Instruction::SkipNotEqualByte{..} | Instruction::SkipEqualRegister{..} |
+        Instruction::SkipNotEqualRegister{..} | Instruction::SkipKeyPressed{..} | Instruction::SkipKeyNotPressed{..} => {
+            format!("if {} then", condition(instruction, false))
+        },
+        Instruction::LoadByte{x, value} => format!("v{:x} := {}", x, value),
+        Instruction::AddByte{x, value} => format!("v{:x} += {}", x, value),
+        Instruction::Assign{x, y} => format!("v{:x} := v{:x}", x, y),
+        Instruction::Or{x, y} => format!("v{:x} |= v{:x}", x, y),
+        Instruction::And{x, y} => format!("v{:x} &= v{:x}", x, y),
+        Instruction::Xor{x, y} => format!("v{:x} ^= v{:x}", x, y),
+        Instruction::AddRegister{x, y} => format!("v{:x} += v{:x}", x, y),
+        Instruction::Sub{x, y} => format!("v{:x} -= v{:x}", x, y),
+        Instruction::ShiftRight{x, y} => format!("v{:x} >>= v{:x}", x, y),
+        Instruction::SubN{x, y} => format!("v{:x} =- v{:x}", x, y),
+        Instruction::ShiftLeft{x, y} => format!("v{:x} <<= v{:x}", x, y),
+        Instruction::LoadIndex(address) => format!("i := {}", target_name(labels, *address)),
+        Instruction::JumpOffset{address, ..} => format!("jump0 {}", target_name(labels, *address)),
+        Instruction::Random{x, mask} => format!("v{:x} := random {:#04x}", x, mask),
+        Instruction::Draw{x, y, height} => format!("sprite v{:x} v{:x} {}", x, y, height),
+        Instruction::LoadDelayTimer{x} => format!("v{:x} := delay", x),
+        Instruction::WaitForKey{x} => format!("v{:x} := key", x),
+        Instruction::SetDelayTimer{x} => format!("delay := v{:x}", x),
+        Instruction::SetSoundTimer{x} => format!("buzzer := v{:x}", x),
+        Instruction::AddIndex{x} => format!("i += v{:x}", x),
+        Instruction::LoadFont{x} => format!("i := hex v{:x}", x),
+        Instruction::StoreDecimal{x} => format!("bcd v{:x}", x),
+        Instruction::StoreRegisters{x} => format!("save v{:x}", x),
+        Instruction::LoadRegisters{x} => format!("load v{:x}", x),
+    }
+}
+
+fn push_line(text: &mut String, depth: usize, line: &str) {
+    text.push_str(&INDENT.repeat(depth));
+    text.push_str(line);
+    text.push('\n');
+}
+
+// Lifts a rom to Octo source, jumps become loops and ifs where they nest, the rest keep their labels.
+// Bytes that are not reached from 0x200 are written as data, sprites one row per line.
+pub fn decompile(rom_content: &[u8], source_name: &str) -> String {
+    let flow_graph = FlowGraph::analyse(rom_content);
+    let rom_end = flow_graph.rom_end;
+
+    let mut decoded: HashMap<usize, (u16, Option<Instruction>)> = HashMap::new();
+    // Sprite address -> the largest size drawn from it
+    let mut sprite_sizes: HashMap<usize, usize> = HashMap::new();
+    for block in flow_graph.blocks.values() {
+        let mut index_register = None;
+        for decoded_instruction in &block.instructions {
+            decoded.insert(decoded_instruction.address as usize, (decoded_instruction.opcode, decoded_instruction.instruction));
+            match decoded_instruction.instruction {
+                Some(Instruction::LoadIndex(address)) => index_register = Some(address as usize),
+                Some(Instruction::AddIndex{..}) | Some(Instruction::LoadFont{..}) => index_register = None,
+                Some(Instruction::Draw{height, ..}) => {
+                    if let Some(address) = index_register {
+                        let size = if height == 0 { 32 } else { height as usize };
+                        let sprite_size = sprite_sizes.entry(address).or_insert(0);
+                        *sprite_size = size.max(*sprite_size);
+                    }
+                },
+                _ => {}
+            }
+        }
+    }
+
+    // The rom is written in address order, as instructions where code was reached and as bytes elsewhere
+    let mut code_starts = BTreeSet::new();
+    let mut boundaries = BTreeSet::new();
+    let mut address = consts::PROGRAM_MEMORY_ADDR;
+    while address < rom_end {
+        boundaries.insert(address);
+        if decoded.contains_key(&address) {
+            code_starts.insert(address);
+            address += 2;
+        } else {
+            address += 1;
+        }
+    }
+
+    let instruction_at = |address: usize| -> Option<Instruction> {
+        if !code_starts.contains(&address) {
+            return None;
+        }
+        return decoded[&address].1;
+    };
+    let is_skip_at = |address: Option<usize>| -> bool {
+        return address.and_then(&instruction_at).is_some_and(|instruction| instruction.is_skip());
+    };
+
+    let mut entered = BTreeSet::new();
+    let mut backward_jumps: BTreeMap<usize, Vec<usize>> = BTreeMap::new();
+    for address in &code_starts {
+        match instruction_at(*address) {
+            Some(Instruction::Jump(target)) | Some(Instruction::Call(target)) => {
+                entered.insert(target as usize);
+                if matches!(instruction_at(*address), Some(Instruction::Jump(_))) && (target as usize) <= *address && code_starts.contains(&(target as usize)) {
+                    backward_jumps.entry(target as usize).or_default().push(*address);
+                }
+            },
+            _ => {}
+        }
+    }
+
+    // A skip at address followed by a forward jump, returns the end of the if and where its else starts
+    let find_if = |address: usize, limit: usize| -> Option<(usize, Option<usize>)> {
+        let target = match instruction_at(address + 2) {
+            Some(Instruction::Jump(target)) => target as usize,
+            _ => return None
+        };
+        if target <= address + 4 || target > limit || !code_starts.contains(&target) || entered.contains(&(address + 2)) {
+            return None;
+        }
+
+        let else_address = target - 2;
+        if let Some(Instruction::Jump(end)) = instruction_at(else_address) {
+            let end = end as usize;
+            if else_address >= address + 4 && end >= target && end <= limit && boundaries.contains(&end) &&
+                !entered.contains(&else_address) && !is_skip_at(else_address.checked_sub(2)) && !is_skip_at(end.checked_sub(2)) {
+                return Some((end, Some(else_address)));
+            }
+        }
+
+        if is_skip_at(Some(target - 2)) {
+            return None;
+        }
+        return Some((target, None));
+    };
+
+    let mut statements: Vec<(usize, Statement)> = Vec::new();
+    let mut structures: Vec<Structure> = Vec::new();
+    let mut consumed_jumps = BTreeSet::new();
+    let mut loops_opened_at = None;
+    let mut address = consts::PROGRAM_MEMORY_ADDR;
+    while address < rom_end {
+        // Structures that end here are closed innermost first
+        match structures.last_mut() {
+            Some(Structure::If{else_address, end: _}) if *else_address == Some(address) => {
+                statements.push((address, Statement::Else));
+                *else_address = None;
+                address += 2;
+                continue;
+            },
+            Some(Structure::If{else_address: None, end}) if *end == address => {
+                statements.push((address, Statement::End));
+                structures.pop();
+                continue;
+            },
+            Some(Structure::Loop{again_address}) if *again_address == address => {
+                statements.push((address, Statement::Again));
+                structures.pop();
+                address += 2;
+                continue;
+            },
+            _ => {}
+        }
+
+        if !code_starts.contains(&address) {
+            statements.push((address, Statement::Byte(rom_content[address - consts::PROGRAM_MEMORY_ADDR])));
+            address += 1;
+            continue;
+        }
+
+        // Backward jumps become loops, the outermost one first
+        if loops_opened_at != Some(address) {
+            loops_opened_at = Some(address);
+            let mut again_addresses = backward_jumps.get(&address).cloned().unwrap_or_default();
+            again_addresses.sort_by(|first, second| second.cmp(first));
+            for again_address in again_addresses {
+                if again_address < structure_limit(&structures, rom_end) && !is_skip_at(address.checked_sub(2)) && !is_skip_at(again_address.checked_sub(2)) {
+                    statements.push((address, Statement::Loop));
+                    structures.push(Structure::Loop{again_address: again_address});
+                    consumed_jumps.insert(again_address);
+                }
+            }
+            continue;
+        }
+
+        match decoded[&address] {
+            (_, Some(instruction)) if instruction.is_skip() => {
+                if let Some((end, else_address)) = find_if(address, structure_limit(&structures, rom_end)) {
+                    statements.push((address, Statement::IfBegin(instruction)));
+                    structures.push(Structure::If{else_address: else_address, end: end});
+                    consumed_jumps.insert(address + 2);
+                    consumed_jumps.extend(else_address);
+                    address += 4;
+                    continue;
+                }
+                statements.push((address, Statement::Instruction(instruction)));
+            },
+            (_, Some(instruction)) => statements.push((address, Statement::Instruction(instruction))),
+            (opcode, None) => statements.push((address, Statement::Invalid(opcode)))
+        }
+        address += 2;
+    }
+
+    let mut labels: BTreeMap<usize, String> = BTreeMap::new();
+    for address in &code_starts {
+        match instruction_at(*address) {
+            Some(Instruction::Jump(target)) if !consumed_jumps.contains(address) => {
+                labels.insert(target as usize, format!("label_{:03x}", target));
+            },
+            Some(Instruction::LoadIndex(target)) | Some(Instruction::JumpOffset{address: target, ..}) => {
+                let target = target as usize;
+                let prefix = if code_starts.contains(&target) { "label" } else if sprite_sizes.contains_key(&target) { "sprite" } else { "data" };
+                labels.entry(target).or_insert_with(|| format!("{}_{:03x}", prefix, target));
+            },
+            _ => {}
+        }
+    }
+    for subroutine in &flow_graph.subroutines {
+        labels.insert(*subroutine as usize, format!("sub_{:03x}", subroutine));
+    }
+    labels.insert(consts::PROGRAM_MEMORY_ADDR, String::from("main"));
+    labels.retain(|address, _| boundaries.contains(address));
+
+    let mut sprite_bytes = BTreeSet::new();
+    for (address, size) in &sprite_sizes {
+        sprite_bytes.extend((*address..*address + *size).filter(|address| boundaries.contains(address) && !code_starts.contains(address)));
+    }
+
+    let mut text = String::new();
+    text.push_str(&format!("# Decompiled from {}\n", source_name));
+    let mut depth = 0;
+    let mut labelled = BTreeSet::new();
+    let mut data_line: Vec<String> = Vec::new();
+    // The "if ... then" of a skip, written on the line of the instruction it skips
+    let mut pending_then: Option<String> = None;
+
+    for (address, statement) in &statements {
+        let is_labelled = labels.contains_key(address) && labelled.insert(*address);
+        let is_data_byte = matches!(statement, Statement::Byte(_)) && !sprite_bytes.contains(address);
+        if !data_line.is_empty() && (is_labelled || !is_data_byte || data_line.len() == DATA_BYTES_PER_LINE) {
+            push_line(&mut text, depth, &data_line.join(" "));
+            data_line.clear();
+        }
+        let joins_then = !is_labelled && matches!(statement, Statement::Instruction(_) | Statement::Invalid(_));
+        if !joins_then {
+            if let Some(then) = pending_then.take() {
+                push_line(&mut text, depth, &then);
+            }
+        }
+
+        if is_labelled {
+            if *address == consts::PROGRAM_MEMORY_ADDR || flow_graph.subroutines.contains(&(*address as u16)) {
+                text.push('\n');
+            }
+            text.push_str(&format!(": {}\n", labels[address]));
+        }
+
+        let line = match statement {
+            Statement::Loop => {
+                depth += 1;
+                Some((depth - 1, String::from("loop")))
+            },
+            Statement::Again => {
+                depth -= 1;
+                Some((depth, String::from("again")))
+            },
+            Statement::IfBegin(skip) => {
+                depth += 1;
+                Some((depth - 1, format!("if {} begin", condition(skip, true))))
+            },
+            Statement::Else => Some((depth - 1, String::from("else"))),
+            Statement::End => {
+                depth -= 1;
+                Some((depth, String::from("end")))
+            },
+            Statement::Instruction(instruction) if instruction.is_skip() => {
+                // Skips of skips are chained on the same line
+                pending_then = Some(match pending_then.take() {
+                    Some(then) => format!("{} {}", then, statement_text(instruction, &labels)),
+                    None => statement_text(instruction, &labels)
+                });
+                None
+            },
+            Statement::Instruction(instruction) => Some((depth, statement_text(instruction, &labels))),
+            Statement::Invalid(opcode) => Some((depth, format!("{:#04x} {:#04x}  # invalid opcode", opcode >> 8, opcode & 0xFF))),
+            Statement::Byte(value) if sprite_bytes.contains(address) => Some((depth, format!("0b{:08b}", value))),
+            Statement::Byte(value) => {
+                data_line.push(format!("{:#04x}", value));
+                None
+            }
+        };
+
+        if let Some((line_depth, line)) = line {
+            let line = match pending_then.take() {
+                Some(then) => format!("{} {}", then, line),
+                None => line
+            };
+            push_line(&mut text, line_depth, &line);
+        }
+    }
+
+    if !data_line.is_empty() {
+        push_line(&mut text, depth, &data_line.join(" "));
+    }
+    if let Some(then) = pending_then {
+        push_line(&mut text, depth, &then);
+    }
+
+    return text;
+}
@@ -0,0 +1,83 @@
+use crate::config::ScalingMode;
+
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{FullscreenType, Window, WindowContext};
+
+pub struct Layout {
+    // Window pixels per chip8 pixel, rounded up for the fit scaling
+    pub pixel_scale: usize,
+    pub destination: Rect,
+}
+
+pub struct Display<'a> {
+    canvas: Canvas<Window>,
+    texture_creator: &'a TextureCreator<WindowContext>,
+    // The streaming texture and its size, only recreated when the frame size changes
+    texture: Option<(Texture<'a>, usize, usize)>,
+    scaling_mode: ScalingMode,
+}
+
+impl<'a> Display<'a> {
+    pub fn new(canvas: Canvas<Window>, texture_creator: &'a TextureCreator<WindowContext>, scaling_mode: ScalingMode) -> Display<'a> {
+        Display{canvas: canvas, texture_creator: texture_creator, texture: None, scaling_mode: scaling_mode}
+    }
+
+    // Where a frame of the given chip8 resolution is drawn in the window, the rest is letterboxed
+    pub fn layout(&self, frame_width: usize, frame_height: usize) -> Result<Layout, String> {
+        let (output_width, output_height) = self.canvas.output_size()?;
+        let fit_scale = f32::min(output_width as f32 / frame_width as f32, output_height as f32 / frame_height as f32);
+
+        let (pixel_scale, destination_width, destination_height) = match self.scaling_mode {
+            ScalingMode::Integer => {
+                let scale = (fit_scale.floor() as usize).max(1);
+                (scale, (frame_width * scale) as u32, (frame_height * scale) as u32)
+            },
+            ScalingMode::Fit => {
+                let scale = fit_scale.max(1.0);
+                (scale.ceil() as usize, (frame_width as f32 * scale) as u32, (frame_height as f32 * scale) as u32)
+            }
+        };
+
+        let x = (output_width as i32 - destination_width as i32) / 2;
+        let y = (output_height as i32 - destination_height as i32) / 2;
+
+        return Ok(Layout{pixel_scale: pixel_scale, destination: Rect::new(x, y, destination_width, destination_height)});
+    }
+
+    // Presents a frame rendered by the effects pipeline in the layout's destination
+    pub fn present(&mut self, rgb24: &[u8], width: usize, height: usize, layout: &Layout) -> Result<(), String> {
+        let size_changed = match &self.texture {
+            Some((_, texture_width, texture_height)) => *texture_width != width || *texture_height != height,
+            None => true
+        };
+        if size_changed {
+            debug!("DISPLAY | Creating a {}x{} frame texture", width, height);
+            let texture = self.texture_creator.create_texture_streaming(PixelFormatEnum::RGB24, width as u32, height as u32)
+                .map_err(|e| e.to_string())?;
+            self.texture = Some((texture, width, height));
+        }
+
+        let (texture, _, _) = self.texture.as_mut().unwrap();
+        texture.update(None, rgb24, width * 3).map_err(|e| e.to_string())?;
+
+        self.canvas.set_draw_color(Color::BLACK);
+        self.canvas.clear();
+        self.canvas.copy(texture, None, layout.destination)?;
+        self.canvas.present();
+
+        return Ok(());
+    }
+
+    pub fn toggle_fullscreen(&mut self) -> Result<(), String> {
+        let window = self.canvas.window_mut();
+        let new_state = match window.fullscreen_state() {
+            FullscreenType::Off => FullscreenType::Desktop,
+            _ => FullscreenType::Off
+        };
+
+        debug!("DISPLAY | Switching fullscreen state to {:?}", new_state);
+        return window.set_fullscreen(new_state);
+    }
+}
@@ -0,0 +1,135 @@
+use crate::config::EffectsConfig;
+use crate::framebuffer::Framebuffer;
+use crate::palette::{Palette, Rgb};
+use crate::upscale::Upscaler;
+
+// Post processing of the framebuffer, done in software so it doesn't depend on the renderer
+pub struct FrameRenderer {
+    palette: Palette,
+    effects: EffectsConfig,
+    upscaler: Upscaler,
+    scale: usize,
+    phosphor: Vec<f32>,
+    previous_frame: Vec<f32>,
+}
+
+impl FrameRenderer {
+    pub fn new(palette: Palette, effects: EffectsConfig, upscaler: Upscaler, scale: usize) -> FrameRenderer {
+        FrameRenderer{palette: palette, effects: effects, upscaler: upscaler, scale: scale.max(1),
+            phosphor: Vec::new(), previous_frame: Vec::new()}
+    }
+
+    // Window pixels per chip8 pixel, changes with the window size
+    pub fn set_scale(&mut self, scale: usize) {
+        self.scale = scale.max(1);
+    }
+
+    // Renders the framebuffer into RGB24 bytes of the returned width and height
+    pub fn render(&mut self, framebuffer: &Framebuffer) -> (Vec<u8>, usize, usize) {
+        let intensities = self.process(framebuffer);
+        let colors: Vec<Rgb> = intensities.iter().map(|intensity| self.palette.color_at(*intensity)).collect();
+
+        // The upscaler does as much of the scaling as it can, nearest neighbour scaling does the rest
+        let factor = self.upscaler.factor();
+        let upscaled = self.upscaler.apply(&colors, framebuffer.width(), framebuffer.height());
+        let nearest_scale = (self.scale / factor).max(1);
+        let pixel_size = factor * nearest_scale;
+
+        let width = framebuffer.width() * pixel_size;
+        let height = framebuffer.height() * pixel_size;
+        let mut scaled = scale_nearest(&upscaled, framebuffer.width() * factor, framebuffer.height() * factor, nearest_scale);
+
+        if self.effects.scanlines > 0.0 {
+            apply_scanlines(&mut scaled, width, height, pixel_size, self.effects.scanlines);
+        }
+        if self.effects.pixel_grid > 0.0 {
+            apply_pixel_grid(&mut scaled, width, height, pixel_size, self.effects.pixel_grid);
+        }
+
+        return (to_rgb24(&scaled), width, height);
+    }
+
+    // Intensity of every pixel after the phosphor persistence and frame blending
+    pub fn process(&mut self, framebuffer: &Framebuffer) -> Vec<f32> {
+        let pixels = framebuffer.pixels();
+        if self.phosphor.len() != pixels.len() {
+            self.phosphor = vec![0.0; pixels.len()];
+            self.previous_frame = vec![0.0; pixels.len()];
+        }
+
+        let mut intensities: Vec<f32> = pixels.iter().map(|is_lit| if *is_lit { 1.0 } else { 0.0 }).collect();
+
+        if self.effects.persistence > 0.0 {
+            for (intensity, phosphor) in intensities.iter_mut().zip(self.phosphor.iter_mut()) {
+                *phosphor = intensity.max(*phosphor * self.effects.persistence);
+                *intensity = *phosphor;
+            }
+        }
+
+        if self.effects.frame_blending {
+            for (intensity, previous) in intensities.iter_mut().zip(self.previous_frame.iter_mut()) {
+                let current = *intensity;
+                *intensity = (current + *previous) / 2.0;
+                *previous = current;
+            }
+        }
+
+        return intensities;
+    }
+}
+
+pub fn scale_nearest(colors: &[Rgb], width: usize, height: usize, scale: usize) -> Vec<Rgb> {
+    let mut scaled = Vec::with_capacity(width * height * scale * scale);
+    for y in 0..height * scale {
+        let row = &colors[(y / scale) * width..(y / scale + 1) * width];
+        for x in 0..width * scale {
+            scaled.push(row[x / scale]);
+        }
+    }
+
+    return scaled;
+}
+
+fn darken(color: Rgb, strength: f32) -> Rgb {
+    let factor = 1.0 - strength.clamp(0.0, 1.0);
+    return ((color.0 as f32 * factor) as u8, (color.1 as f32 * factor) as u8, (color.2 as f32 * factor) as u8);
+}
+
+// Darkens every other line of the scaled image
+pub fn apply_scanlines(colors: &mut [Rgb], width: usize, height: usize, scale: usize, strength: f32) {
+    if scale < 2 {
+        return;
+    }
+
+    for y in (1..height).step_by(2) {
+        for color in colors[y * width..(y + 1) * width].iter_mut() {
+            *color = darken(*color, strength);
+        }
+    }
+}
+
+// Darkens the last row and column of every scaled pixel
+pub fn apply_pixel_grid(colors: &mut [Rgb], width: usize, height: usize, scale: usize, strength: f32) {
+    if scale < 3 {
+        return;
+    }
+
+    for y in 0..height {
+        for x in 0..width {
+            if x % scale == scale - 1 || y % scale == scale - 1 {
+                colors[y * width + x] = darken(colors[y * width + x], strength);
+            }
+        }
+    }
+}
+
+pub fn to_rgb24(colors: &[Rgb]) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(colors.len() * 3);
+    for (red, green, blue) in colors {
+        bytes.push(*red);
+        bytes.push(*green);
+        bytes.push(*blue);
+    }
+
+    return bytes;
+}
@@ -1,8 +1,103 @@
-#[derive(Debug)]
+use std::error::Error;
+use std::fmt;
+
+// Process exit codes, one per kind of failure
+pub const EXIT_CODE_ARGUMENTS: i32 = 2;
+pub const EXIT_CODE_CONFIG: i32 = 3;
+pub const EXIT_CODE_IO: i32 = 4;
+pub const EXIT_CODE_ROM: i32 = 5;
+pub const EXIT_CODE_FRONTEND: i32 = 6;
+pub const EXIT_CODE_EXECUTION: i32 = 10;
+
+// Summary of the machine state when an instruction failed
+#[derive(Debug, Clone, PartialEq)]
+pub struct MachineContext {
+    pub program_counter: u16,
+    pub opcode: u16,
+    pub index_register: u16,
+    pub registers: [u8; 16],
+    pub stack_frames: Vec<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum Chip8Error {
-    InvalidInstruction,
+    InvalidInstruction(u16),
     TriedPoppingEmptyStack,
-    DisplayError(String),
+    StackOverflow(usize),
     InvalidKeycode(u8),
-    WaitForKeypressDuringWaitMode
-}
\ No newline at end of file
+    WaitForKeypressDuringWaitMode,
+    AddressOutOfRange(usize),
+    RomTooLarge{rom_size: usize, max_size: usize},
+    LintErrors(usize),
+    InvalidArgument(String),
+    ConfigError(String),
+    IoError{path: String, message: String},
+    FrontendError(String),
+    InvalidSaveState(String),
+    // An error raised by an instruction, with the state of the machine it was raised in
+    ExecutionError{context: MachineContext, error: Box<Chip8Error>},
+}
+
+impl Chip8Error {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            Chip8Error::InvalidArgument(_) => EXIT_CODE_ARGUMENTS,
+            Chip8Error::ConfigError(_) => EXIT_CODE_CONFIG,
+            Chip8Error::IoError{..} => EXIT_CODE_IO,
+            Chip8Error::RomTooLarge{..} | Chip8Error::LintErrors(_) => EXIT_CODE_ROM,
+            Chip8Error::FrontendError(_) => EXIT_CODE_FRONTEND,
+            _ => EXIT_CODE_EXECUTION
+        }
+    }
+
+    pub fn io_error(path: &str, error: std::io::Error) -> Chip8Error {
+        return Chip8Error::IoError{path: path.to_string(), message: error.to_string()};
+    }
+}
+
+impl fmt::Display for MachineContext {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PC {:#06x} | Opcode {:#06x} | I {:#06x} | Registers",
+            self.program_counter, self.opcode, self.index_register)?;
+        for (index, value) in self.registers.iter().enumerate() {
+            write!(f, " V{:X}={:#04x}", index, value)?;
+        }
+        write!(f, " | Stack [")?;
+        for (index, frame) in self.stack_frames.iter().enumerate() {
+            write!(f, "{}{:#06x}", if index == 0 { "" } else { ", " }, frame)?;
+        }
+        write!(f, "]")?;
+
+        return Ok(());
+    }
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Chip8Error::InvalidInstruction(opcode) => write!(f, "Invalid instruction {:#06x}", opcode),
+            Chip8Error::TriedPoppingEmptyStack => write!(f, "Returned from a subroutine with an empty stack"),
+            Chip8Error::StackOverflow(max_depth) => write!(f, "Called a subroutine with a full stack of {} entries", max_depth),
+            Chip8Error::InvalidKeycode(keycode) => write!(f, "Invalid key {:#04x}", keycode),
+            Chip8Error::WaitForKeypressDuringWaitMode => write!(f, "Waited for a keypress while already waiting for one"),
+            Chip8Error::AddressOutOfRange(address) => write!(f, "Memory address {:#06x} is out of range", address),
+            Chip8Error::RomTooLarge{rom_size, max_size} => write!(f, "Rom of {} bytes is larger than the {} bytes of program memory", rom_size, max_size),
+            Chip8Error::LintErrors(error_count) => write!(f, "Rom has {} lint errors", error_count),
+            Chip8Error::InvalidArgument(message) => write!(f, "Invalid argument : {}", message),
+            Chip8Error::ConfigError(message) => write!(f, "Configuration error : {}", message),
+            Chip8Error::IoError{path, message} => write!(f, "Failed accessing {} : {}", path, message),
+            Chip8Error::FrontendError(message) => write!(f, "Frontend error : {}", message),
+            Chip8Error::InvalidSaveState(message) => write!(f, "Invalid save state : {}", message),
+            Chip8Error::ExecutionError{context, error} => write!(f, "{} ({})", error, context),
+        }
+    }
+}
+
+impl Error for Chip8Error {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Chip8Error::ExecutionError{error, ..} => Some(error.as_ref()),
+            _ => None
+        }
+    }
+}
@@ -0,0 +1,313 @@
+use crate::consts;
+use crate::instruction::{self, Instruction};
+
+use serde_json::json;
+
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum EdgeKind {
+    Fallthrough,
+    Jump,
+    Call,
+    // From a call to the instruction after it, where the subroutine returns to
+    CallReturn,
+    Return,
+    Skip,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Edge {
+    pub from: u16,
+    pub to: u16,
+    pub kind: EdgeKind,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct DecodedInstruction {
+    pub address: u16,
+    pub opcode: u16,
+    // None for opcodes that fail to decode
+    pub instruction: Option<Instruction>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct BasicBlock {
+    pub start: u16,
+    pub instructions: Vec<DecodedInstruction>,
+    // Ends with a BNNN, whose target is only known at runtime
+    pub computed_jump: bool,
+}
+
+pub struct FlowGraph {
+    pub entry: u16,
+    pub rom_end: usize,
+    pub blocks: BTreeMap<u16, BasicBlock>,
+    pub edges: Vec<Edge>,
+    pub subroutines: BTreeSet<u16>,
+}
+
+fn read_opcode(rom_content: &[u8], address: u16) -> Option<u16> {
+    let offset = (address as usize).checked_sub(consts::PROGRAM_MEMORY_ADDR)?;
+    let high_byte = *rom_content.get(offset)?;
+    let low_byte = *rom_content.get(offset + 1)?;
+    return Some(((high_byte as u16) << 8) + low_byte as u16);
+}
+
+// Instructions after which execution doesn't simply continue with the next instruction
+fn ends_block(instruction: &Option<Instruction>) -> bool {
+    match instruction {
+        None => true,
+        Some(instruction) => match instruction {
+            Instruction::Jump(_) | Instruction::Call(_) | Instruction::Return | Instruction::JumpOffset{..} => true,
+            _ => instruction.is_skip()
+        }
+    }
+}
+
+impl EdgeKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            EdgeKind::Fallthrough => "fallthrough",
+            EdgeKind::Jump => "jump",
+            EdgeKind::Call => "call",
+            EdgeKind::CallReturn => "call_return",
+            EdgeKind::Return => "return",
+            EdgeKind::Skip => "skip",
+        }
+    }
+}
+
+impl BasicBlock {
+    pub fn last(&self) -> &DecodedInstruction {
+        return &self.instructions[self.instructions.len() - 1];
+    }
+
+    pub fn end(&self) -> u16 {
+        return self.last().address.wrapping_add(2);
+    }
+}
+
+impl FlowGraph {
+    // Follows every path from the start of the rom, code only reachable through computed jumps is not found
+    pub fn analyse(rom_content: &[u8]) -> FlowGraph {
+        let entry = consts::PROGRAM_MEMORY_ADDR as u16;
+        let rom_end = consts::PROGRAM_MEMORY_ADDR + rom_content.len();
+
+        let mut leaders = BTreeSet::new();
+        let mut subroutines = BTreeSet::new();
+        let mut decoded: BTreeMap<u16, DecodedInstruction> = BTreeMap::new();
+        let mut pending = vec![entry];
+        leaders.insert(entry);
+
+        while let Some(address) = pending.pop() {
+            if decoded.contains_key(&address) {
+                continue;
+            }
+            let opcode = match read_opcode(rom_content, address) {
+                Some(opcode) => opcode,
+                None => continue
+            };
+
+            let instruction = instruction::decode(opcode).ok();
+            decoded.insert(address, DecodedInstruction{address: address, opcode: opcode, instruction: instruction});
+
+            match instruction {
+                None | Some(Instruction::Return) | Some(Instruction::JumpOffset{..}) => {},
+                Some(Instruction::Jump(target)) => {
+                    leaders.insert(target);
+                    pending.push(target);
+                },
+                Some(Instruction::Call(target)) => {
+                    leaders.insert(target);
+                    leaders.insert(address.wrapping_add(2));
+                    subroutines.insert(target);
+                    pending.push(target);
+                    pending.push(address.wrapping_add(2));
+                },
+                Some(instruction) if instruction.is_skip() => {
+                    leaders.insert(address.wrapping_add(2));
+                    leaders.insert(address.wrapping_add(4));
+                    pending.push(address.wrapping_add(2));
+                    pending.push(address.wrapping_add(4));
+                },
+                Some(_) => {
+                    pending.push(address.wrapping_add(2));
+                }
+            }
+        }
+
+        // Blocks run from a leader until an instruction that ends the block, or until the next leader
+        let mut blocks = BTreeMap::new();
+        for leader in leaders.iter().filter(|leader| decoded.contains_key(leader)) {
+            let mut instructions = Vec::new();
+            let mut address = *leader;
+            while let Some(decoded_instruction) = decoded.get(&address) {
+                instructions.push(decoded_instruction.clone());
+                address = address.wrapping_add(2);
+                if ends_block(&decoded_instruction.instruction) || leaders.contains(&address) {
+                    break;
+                }
+            }
+
+            let computed_jump = matches!(instructions[instructions.len() - 1].instruction, Some(Instruction::JumpOffset{..}));
+            blocks.insert(*leader, BasicBlock{start: *leader, instructions: instructions, computed_jump: computed_jump});
+        }
+
+        let mut edges = Vec::new();
+        for block in blocks.values() {
+            let last = block.last();
+            let next_address = last.address.wrapping_add(2);
+            match last.instruction {
+                None | Some(Instruction::Return) | Some(Instruction::JumpOffset{..}) => {},
+                Some(Instruction::Jump(target)) => {
+                    edges.push(Edge{from: block.start, to: target, kind: EdgeKind::Jump});
+                },
+                Some(Instruction::Call(target)) => {
+                    edges.push(Edge{from: block.start, to: target, kind: EdgeKind::Call});
+                    edges.push(Edge{from: block.start, to: next_address, kind: EdgeKind::CallReturn});
+                },
+                Some(instruction) if instruction.is_skip() => {
+                    edges.push(Edge{from: block.start, to: next_address, kind: EdgeKind::Fallthrough});
+                    edges.push(Edge{from: block.start, to: last.address.wrapping_add(4), kind: EdgeKind::Skip});
+                },
+                Some(_) => {
+                    edges.push(Edge{from: block.start, to: next_address, kind: EdgeKind::Fallthrough});
+                }
+            }
+        }
+
+        let mut graph = FlowGraph{entry: entry, rom_end: rom_end, blocks: blocks, edges: edges, subroutines: subroutines};
+        graph.resolve_returns();
+        return graph;
+    }
+
+    // The blocks reachable from a block without entering subroutines
+    pub fn function_blocks(&self, function_start: u16) -> BTreeSet<u16> {
+        let mut reached = BTreeSet::new();
+        let mut pending = vec![function_start];
+        while let Some(block_start) = pending.pop() {
+            if !self.blocks.contains_key(&block_start) || !reached.insert(block_start) {
+                continue;
+            }
+
+            for edge in self.edges.iter().filter(|edge| edge.from == block_start) {
+                if edge.kind != EdgeKind::Call && edge.kind != EdgeKind::Return {
+                    pending.push(edge.to);
+                }
+            }
+        }
+
+        return reached;
+    }
+
+    // Returns go back to the instruction after every call of the subroutine they are in
+    fn resolve_returns(&mut self) {
+        let mut return_sites: HashMap<u16, Vec<u16>> = HashMap::new();
+        for edge in &self.edges {
+            if edge.kind == EdgeKind::Call {
+                let call_address = self.blocks[&edge.from].last().address;
+                return_sites.entry(edge.to).or_default().push(call_address.wrapping_add(2));
+            }
+        }
+
+        let mut return_edges = Vec::new();
+        for subroutine in &self.subroutines {
+            for block_start in self.function_blocks(*subroutine) {
+                if self.blocks[&block_start].last().instruction == Some(Instruction::Return) {
+                    for return_site in return_sites.get(subroutine).into_iter().flatten() {
+                        return_edges.push(Edge{from: block_start, to: *return_site, kind: EdgeKind::Return});
+                    }
+                }
+            }
+        }
+
+        self.edges.extend(return_edges);
+    }
+
+    pub fn is_in_rom(&self, address: u16) -> bool {
+        return address >= self.entry && (address as usize) < self.rom_end;
+    }
+
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph cfg {\n");
+        dot.push_str("    node [shape=box, fontname=\"monospace\"];\n");
+
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            if self.subroutines.contains(&block.start) {
+                label.push_str(&format!("sub_{:03x}:\\l", block.start));
+            }
+            for decoded in &block.instructions {
+                let text = match decoded.instruction {
+                    Some(instruction) => instruction.to_string(),
+                    None => format!("invalid {:#06x}", decoded.opcode)
+                };
+                label.push_str(&format!("{:#06x}  {}\\l", decoded.address, text));
+            }
+
+            let mut attributes = format!("label=\"{}\"", label);
+            if block.computed_jump {
+                attributes.push_str(", color=red, xlabel=\"computed jump\"");
+            } else if block.last().instruction.is_none() {
+                attributes.push_str(", color=red, xlabel=\"invalid opcode\"");
+            }
+            dot.push_str(&format!("    \"{:#06x}\" [{}];\n", block.start, attributes));
+        }
+
+        // Targets that were not decoded are outside of the rom
+        let missing_targets: BTreeSet<u16> = self.edges.iter().map(|edge| edge.to).filter(|target| !self.blocks.contains_key(target)).collect();
+        for target in missing_targets {
+            dot.push_str(&format!("    \"{:#06x}\" [label=\"{:#06x} (outside rom)\", style=dashed, color=red];\n", target, target));
+        }
+
+        for edge in &self.edges {
+            let attributes = match edge.kind {
+                EdgeKind::Fallthrough => "",
+                EdgeKind::Jump => " [label=\"jump\"]",
+                EdgeKind::Call => " [label=\"call\", style=bold]",
+                EdgeKind::CallReturn => " [style=dashed]",
+                EdgeKind::Return => " [label=\"return\", style=dotted]",
+                EdgeKind::Skip => " [label=\"skip\"]",
+            };
+            dot.push_str(&format!("    \"{:#06x}\" -> \"{:#06x}\"{};\n", edge.from, edge.to, attributes));
+        }
+
+        dot.push_str("}\n");
+        return dot;
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        let blocks: Vec<serde_json::Value> = self.blocks.values().map(|block| {
+            let instructions: Vec<serde_json::Value> = block.instructions.iter().map(|decoded| json!({
+                "address": decoded.address,
+                "opcode": format!("{:04x}", decoded.opcode),
+                "text": decoded.instruction.map(|instruction| instruction.to_string()),
+            })).collect();
+
+            json!({
+                "start": block.start,
+                "end": block.end(),
+                "subroutine": self.subroutines.contains(&block.start),
+                "computed_jump": block.computed_jump,
+                "invalid_opcode": block.last().instruction.is_none(),
+                "instructions": instructions,
+            })
+        }).collect();
+
+        let edges: Vec<serde_json::Value> = self.edges.iter().map(|edge| json!({
+            "from": edge.from,
+            "to": edge.to,
+            "kind": edge.kind.name(),
+            "outside_rom": !self.is_in_rom(edge.to),
+        })).collect();
+
+        return json!({
+            "entry": self.entry,
+            "subroutines": self.subroutines,
+            "blocks": blocks,
+            "edges": edges,
+        });
+    }
+}
@@ -0,0 +1,41 @@
+#[derive(Debug, Clone, PartialEq)]
+pub struct Framebuffer {
+    width: usize,
+    height: usize,
+    pixels: Vec<bool>,
+}
+
+impl Framebuffer {
+    pub fn new(width: usize, height: usize) -> Framebuffer {
+        Framebuffer{width: width, height: height, pixels: vec![false; width * height]}
+    }
+
+    pub fn width(&self) -> usize {
+        return self.width;
+    }
+
+    pub fn height(&self) -> usize {
+        return self.height;
+    }
+
+    // Row major, width * height pixels
+    pub fn pixels(&self) -> &[bool] {
+        return &self.pixels;
+    }
+
+    pub fn get_pixel(&self, x: usize, y: usize) -> bool {
+        return self.pixels[y * self.width + x];
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, value: bool) {
+        self.pixels[y * self.width + x] = value;
+    }
+
+    pub fn from_pixels(width: usize, height: usize, pixels: Vec<bool>) -> Framebuffer {
+        Framebuffer{width: width, height: height, pixels: pixels}
+    }
+
+    pub fn clear(&mut self) {
+        self.pixels.fill(false);
+    }
+}
@@ -0,0 +1,130 @@
+use crate::config::Config;
+use crate::keypad::Keypad;
+
+use sdl2::GameControllerSubsystem;
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::event::Event;
+use sdl2::keyboard::{KeyboardState, Scancode};
+
+use std::collections::HashMap;
+
+struct ControllerBindings {
+    buttons: Vec<(Button, u8)>,
+    axes: Vec<(Axis, bool, u8)>,
+}
+
+pub struct InputMapper {
+    controller_subsystem: GameControllerSubsystem,
+    keyboard_bindings: Vec<(Scancode, u8)>,
+    // Bindings of each player, the last one is used by every controller after it
+    player_bindings: Vec<ControllerBindings>,
+    // Connected controllers in connection order, the n'th controller is the n'th player
+    controllers: Vec<GameController>,
+    controllers_enabled: bool,
+    deadzone: i16,
+}
+
+fn parse_controller_bindings(button_map: &HashMap<String, u8>, axis_map: &HashMap<String, u8>) -> Result<ControllerBindings, String> {
+    let mut buttons = Vec::new();
+    for (button_name, chip_key) in button_map {
+        let button = Button::from_string(button_name).ok_or_else(|| format!("Unknown controller button in configuration : \"{}\"", button_name))?;
+        buttons.push((button, *chip_key));
+    }
+
+    let mut axes = Vec::new();
+    for (axis_name, chip_key) in axis_map {
+        let (name, is_positive) = match (axis_name.strip_suffix('+'), axis_name.strip_suffix('-')) {
+            (Some(name), _) => (name, true),
+            (_, Some(name)) => (name, false),
+            _ => return Err(format!("Controller axis \"{}\" is missing a direction, expected \"{}+\" or \"{}-\"", axis_name, axis_name, axis_name))
+        };
+        let axis = Axis::from_string(name).ok_or_else(|| format!("Unknown controller axis in configuration : \"{}\"", name))?;
+        axes.push((axis, is_positive, *chip_key));
+    }
+
+    return Ok(ControllerBindings{buttons: buttons, axes: axes});
+}
+
+impl InputMapper {
+    pub fn new(config: &Config, controller_subsystem: GameControllerSubsystem) -> Result<InputMapper, String> {
+        let mut keyboard_bindings = Vec::new();
+        for (chip_key, real_key_name) in config.key_map()? {
+            let scancode = Scancode::from_name(&real_key_name).ok_or_else(|| format!("Unknown key name in configuration : \"{}\"", real_key_name))?;
+            keyboard_bindings.push((scancode, chip_key));
+        }
+
+        if let Some(mappings_file) = &config.controller.mappings_file {
+            let mappings_count = controller_subsystem.load_mappings(mappings_file).map_err(|e| e.to_string())?;
+            info!("INPUT | Loaded {} controller mappings from {}", mappings_count, mappings_file);
+        }
+
+        // Players with a layout of their own, and the shared layout for everyone after them
+        let players_count = config.controller.players.keys().filter_map(|player| player.parse::<usize>().ok()).max().unwrap_or(0);
+        let mut player_bindings = Vec::new();
+        for player in 1..players_count + 2 {
+            let layout = config.controller.player_layout(player);
+            player_bindings.push(parse_controller_bindings(&layout.button_map()?, &layout.axis_map()?)?);
+        }
+
+        return Ok(InputMapper{controller_subsystem: controller_subsystem, keyboard_bindings: keyboard_bindings,
+            player_bindings: player_bindings, controllers: Vec::new(), controllers_enabled: config.controller.enabled,
+            deadzone: config.controller.deadzone});
+    }
+
+    // Handles controllers being connected and disconnected, SDL also reports the ones connected at startup this way
+    pub fn handle_event(&mut self, event: &Event) {
+        if !self.controllers_enabled {
+            return;
+        }
+
+        match event {
+            Event::ControllerDeviceAdded {which, ..} => {
+                match self.controller_subsystem.open(*which) {
+                    Ok(controller) => {
+                        info!("INPUT | Controller \"{}\" connected as player {}", controller.name(), self.controllers.len() + 1);
+                        self.controllers.push(controller);
+                    },
+                    Err(e) => {
+                        warn!("INPUT | Failed opening controller {} : {}", which, e);
+                    }
+                }
+            },
+            Event::ControllerDeviceRemoved {which, ..} => {
+                if let Some(position) = self.controllers.iter().position(|controller| controller.instance_id() == *which) {
+                    let controller = self.controllers.remove(position);
+                    info!("INPUT | Controller \"{}\" disconnected", controller.name());
+                }
+            },
+            _ => {}
+        }
+    }
+
+    pub fn keypad_state(&self, keyboard_state: &KeyboardState) -> Keypad {
+        let mut keypad = Keypad::new();
+
+        for (scancode, chip_key) in &self.keyboard_bindings {
+            if keyboard_state.is_scancode_pressed(*scancode) {
+                keypad.set_pressed(*chip_key, true);
+            }
+        }
+
+        for (player_index, controller) in self.controllers.iter().enumerate() {
+            let bindings = &self.player_bindings[player_index.min(self.player_bindings.len() - 1)];
+
+            for (button, chip_key) in &bindings.buttons {
+                if controller.button(*button) {
+                    keypad.set_pressed(*chip_key, true);
+                }
+            }
+
+            for (axis, is_positive, chip_key) in &bindings.axes {
+                let value = controller.axis(*axis);
+                if (*is_positive && value > self.deadzone) || (!*is_positive && value < -self.deadzone) {
+                    keypad.set_pressed(*chip_key, true);
+                }
+            }
+        }
+
+        return keypad;
+    }
+}
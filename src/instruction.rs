@@ -0,0 +1,202 @@
+use crate::errors::Chip8Error;
+
+use std::fmt;
+
+// A decoded opcode, x and y are register indexes
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Instruction {
+    ClearScreen,
+    Return,
+    LowResolution,
+    HighResolution,
+    System(u16),
+    Jump(u16),
+    Call(u16),
+    SkipEqualByte{x: u8, value: u8},
+    SkipNotEqualByte{x: u8, value: u8},
+    SkipEqualRegister{x: u8, y: u8},
+    LoadByte{x: u8, value: u8},
+    AddByte{x: u8, value: u8},
+    Assign{x: u8, y: u8},
+    Or{x: u8, y: u8},
+    And{x: u8, y: u8},
+    Xor{x: u8, y: u8},
+    AddRegister{x: u8, y: u8},
+    Sub{x: u8, y: u8},
+    ShiftRight{x: u8, y: u8},
+    SubN{x: u8, y: u8},
+    ShiftLeft{x: u8, y: u8},
+    SkipNotEqualRegister{x: u8, y: u8},
+    LoadIndex(u16),
+    JumpOffset{x: u8, address: u16},
+    Random{x: u8, mask: u8},
+    Draw{x: u8, y: u8, height: u8},
+    SkipKeyPressed{x: u8},
+    SkipKeyNotPressed{x: u8},
+    LoadDelayTimer{x: u8},
+    WaitForKey{x: u8},
+    SetDelayTimer{x: u8},
+    SetSoundTimer{x: u8},
+    AddIndex{x: u8},
+    LoadFont{x: u8},
+    StoreDecimal{x: u8},
+    StoreRegisters{x: u8},
+    LoadRegisters{x: u8},
+}
+
+pub fn decode(opcode: u16) -> Result<Instruction, Chip8Error> {
+    let x = ((opcode >> 8) & 0xF) as u8;
+    let y = ((opcode >> 4) & 0xF) as u8;
+    let n = (opcode & 0xF) as u8;
+    let nn = (opcode & 0xFF) as u8;
+    let nnn = opcode & 0xFFF;
+
+    let instruction = match opcode >> 12 {
+        0x0 => match opcode {
+            0x00E0 => Instruction::ClearScreen,
+            0x00EE => Instruction::Return,
+            0x00FE => Instruction::LowResolution,
+            0x00FF => Instruction::HighResolution,
+            _ => Instruction::System(nnn)
+        },
+        0x1 => Instruction::Jump(nnn),
+        0x2 => Instruction::Call(nnn),
+        0x3 => Instruction::SkipEqualByte{x: x, value: nn},
+        0x4 => Instruction::SkipNotEqualByte{x: x, value: nn},
+        0x5 if n == 0 => Instruction::SkipEqualRegister{x: x, y: y},
+        0x6 => Instruction::LoadByte{x: x, value: nn},
+        0x7 => Instruction::AddByte{x: x, value: nn},
+        0x8 => match n {
+            0x0 => Instruction::Assign{x: x, y: y},
+            0x1 => Instruction::Or{x: x, y: y},
+            0x2 => Instruction::And{x: x, y: y},
+            0x3 => Instruction::Xor{x: x, y: y},
+            0x4 => Instruction::AddRegister{x: x, y: y},
+            0x5 => Instruction::Sub{x: x, y: y},
+            0x6 => Instruction::ShiftRight{x: x, y: y},
+            0x7 => Instruction::SubN{x: x, y: y},
+            0xE => Instruction::ShiftLeft{x: x, y: y},
+            _ => return Err(Chip8Error::InvalidInstruction(opcode))
+        },
+        0x9 if n == 0 => Instruction::SkipNotEqualRegister{x: x, y: y},
+        0xA => Instruction::LoadIndex(nnn),
+        0xB => Instruction::JumpOffset{x: x, address: nnn},
+        0xC => Instruction::Random{x: x, mask: nn},
+        0xD => Instruction::Draw{x: x, y: y, height: n},
+        0xE => match nn {
+            0x9E => Instruction::SkipKeyPressed{x: x},
+            0xA1 => Instruction::SkipKeyNotPressed{x: x},
+            _ => return Err(Chip8Error::InvalidInstruction(opcode))
+        },
+        0xF => match nn {
+            0x07 => Instruction::LoadDelayTimer{x: x},
+            0x0A => Instruction::WaitForKey{x: x},
+            0x15 => Instruction::SetDelayTimer{x: x},
+            0x18 => Instruction::SetSoundTimer{x: x},
+            0x1E => Instruction::AddIndex{x: x},
+            0x29 => Instruction::LoadFont{x: x},
+            0x33 => Instruction::StoreDecimal{x: x},
+            0x55 => Instruction::StoreRegisters{x: x},
+            0x65 => Instruction::LoadRegisters{x: x},
+            _ => return Err(Chip8Error::InvalidInstruction(opcode))
+        },
+        _ => return Err(Chip8Error::InvalidInstruction(opcode))
+    };
+
+    return Ok(instruction);
+}
+
+impl Instruction {
+    // The opcode pattern of the instruction, like "8XY4", used to group instructions
+    pub fn pattern(&self) -> &'static str {
+        match self {
+            Instruction::ClearScreen => "00E0",
+            Instruction::Return => "00EE",
+            Instruction::LowResolution => "00FE",
+            Instruction::HighResolution => "00FF",
+            Instruction::System(_) => "0NNN",
+            Instruction::Jump(_) => "1NNN",
+            Instruction::Call(_) => "2NNN",
+            Instruction::SkipEqualByte{..} => "3XNN",
+            Instruction::SkipNotEqualByte{..} => "4XNN",
+            Instruction::SkipEqualRegister{..} => "5XY0",
+            Instruction::LoadByte{..} => "6XNN",
+            Instruction::AddByte{..} => "7XNN",
+            Instruction::Assign{..} => "8XY0",
+            Instruction::Or{..} => "8XY1",
+            Instruction::And{..} => "8XY2",
+            Instruction::Xor{..} => "8XY3",
+            Instruction::AddRegister{..} => "8XY4",
+            Instruction::Sub{..} => "8XY5",
+            Instruction::ShiftRight{..} => "8XY6",
+            Instruction::SubN{..} => "8XY7",
+            Instruction::ShiftLeft{..} => "8XYE",
+            Instruction::SkipNotEqualRegister{..} => "9XY0",
+            Instruction::LoadIndex(_) => "ANNN",
+            Instruction::JumpOffset{..} => "BNNN",
+            Instruction::Random{..} => "CXNN",
+            Instruction::Draw{..} => "DXYN",
+            Instruction::SkipKeyPressed{..} => "EX9E",
+            Instruction::SkipKeyNotPressed{..} => "EXA1",
+            Instruction::LoadDelayTimer{..} => "FX07",
+            Instruction::WaitForKey{..} => "FX0A",
+            Instruction::SetDelayTimer{..} => "FX15",
+            Instruction::SetSoundTimer{..} => "FX18",
+            Instruction::AddIndex{..} => "FX1E",
+            Instruction::LoadFont{..} => "FX29",
+            Instruction::StoreDecimal{..} => "FX33",
+            Instruction::StoreRegisters{..} => "FX55",
+            Instruction::LoadRegisters{..} => "FX65",
+        }
+    }
+
+    // Instructions that skip the next instruction when their condition holds
+    pub fn is_skip(&self) -> bool {
+        return matches!(self, Instruction::SkipEqualByte{..} | Instruction::SkipNotEqualByte{..} | Instruction::SkipEqualRegister{..} |
+            Instruction::SkipNotEqualRegister{..} | Instruction::SkipKeyPressed{..} | Instruction::SkipKeyNotPressed{..});
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Instruction::ClearScreen => write!(f, "CLS"),
+            Instruction::Return => write!(f, "RET"),
+            Instruction::LowResolution => write!(f, "LOW"),
+            Instruction::HighResolution => write!(f, "HIGH"),
+            Instruction::System(address) => write!(f, "SYS {:#05x}", address),
+            Instruction::Jump(address) => write!(f, "JP {:#05x}", address),
+            Instruction::Call(address) => write!(f, "CALL {:#05x}", address),
+            Instruction::SkipEqualByte{x, value} => write!(f, "SE V{:X}, {:#04x}", x, value),
+            Instruction::SkipNotEqualByte{x, value} => write!(f, "SNE V{:X}, {:#04x}", x, value),
+            Instruction::SkipEqualRegister{x, y} => write!(f, "SE V{:X}, V{:X}", x, y),
+            Instruction::LoadByte{x, value} => write!(f, "LD V{:X}, {:#04x}", x, value),
+            Instruction::AddByte{x, value} => write!(f, "ADD V{:X}, {:#04x}", x, value),
+            Instruction::Assign{x, y} => write!(f, "LD V{:X}, V{:X}", x, y),
+            Instruction::Or{x, y} => write!(f, "OR V{:X}, V{:X}", x, y),
+            Instruction::And{x, y} => write!(f, "AND V{:X}, V{:X}", x, y),
+            Instruction::Xor{x, y} => write!(f, "XOR V{:X}, V{:X}", x, y),
+            Instruction::AddRegister{x, y} => write!(f, "ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub{x, y} => write!(f, "SUB V{:X}, V{:X}", x, y),
+            Instruction::ShiftRight{x, y} => write!(f, "SHR V{:X}, V{:X}", x, y),
+            Instruction::SubN{x, y} => write!(f, "SUBN V{:X}, V{:X}", x, y),
+            Instruction::ShiftLeft{x, y} => write!(f, "SHL V{:X}, V{:X}", x, y),
+            Instruction::SkipNotEqualRegister{x, y} => write!(f, "SNE V{:X}, V{:X}", x, y),
+            Instruction::LoadIndex(address) => write!(f, "LD I, {:#05x}", address),
+            Instruction::JumpOffset{address, ..} => write!(f, "JP V0, {:#05x}", address),
+            Instruction::Random{x, mask} => write!(f, "RND V{:X}, {:#04x}", x, mask),
+            Instruction::Draw{x, y, height} => write!(f, "DRW V{:X}, V{:X}, {}", x, y, height),
+            Instruction::SkipKeyPressed{x} => write!(f, "SKP V{:X}", x),
+            Instruction::SkipKeyNotPressed{x} => write!(f, "SKNP V{:X}", x),
+            Instruction::LoadDelayTimer{x} => write!(f, "LD V{:X}, DT", x),
+            Instruction::WaitForKey{x} => write!(f, "LD V{:X}, K", x),
+            Instruction::SetDelayTimer{x} => write!(f, "LD DT, V{:X}", x),
+            Instruction::SetSoundTimer{x} => write!(f, "LD ST, V{:X}", x),
+            Instruction::AddIndex{x} => write!(f, "ADD I, V{:X}", x),
+            Instruction::LoadFont{x} => write!(f, "LD F, V{:X}", x),
+            Instruction::StoreDecimal{x} => write!(f, "LD B, V{:X}", x),
+            Instruction::StoreRegisters{x} => write!(f, "LD [I], V{:X}", x),
+            Instruction::LoadRegisters{x} => write!(f, "LD V{:X}, [I]", x),
+        }
+    }
+}
@@ -0,0 +1,34 @@
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Keypad {
+    pressed_keys: [bool; 16]
+}
+
+impl Keypad {
+    pub fn new() -> Keypad {
+        Keypad{pressed_keys: [false; 16]}
+    }
+
+    pub fn set_pressed(&mut self, key: u8, is_pressed: bool) {
+        if let Some(key_state) = self.pressed_keys.get_mut(key as usize) {
+            *key_state = is_pressed;
+        }
+    }
+
+    pub fn is_pressed(&self, key: u8) -> bool {
+        return self.pressed_keys.get(key as usize).copied().unwrap_or(false);
+    }
+
+    pub fn first_pressed(&self) -> Option<u8> {
+        return self.pressed_keys.iter().position(|is_pressed| *is_pressed).map(|key| key as u8);
+    }
+
+    // The keys pressed on either keypad
+    pub fn merged(&self, other: &Keypad) -> Keypad {
+        let mut merged = *self;
+        for (key_state, other_state) in merged.pressed_keys.iter_mut().zip(other.pressed_keys.iter()) {
+            *key_state |= *other_state;
+        }
+        return merged;
+    }
+
+}
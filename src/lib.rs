@@ -0,0 +1,38 @@
+#[macro_use] extern crate log;
+
+pub mod cpu;
+pub mod memory;
+pub mod consts;
+pub mod errors;
+pub mod stack;
+pub mod delay_timer;
+pub mod sound_timer;
+pub mod quirks;
+pub mod config;
+pub mod audio;
+pub mod romdb;
+pub mod keypad;
+pub mod input;
+pub mod framebuffer;
+pub mod palette;
+pub mod effects;
+pub mod overlay;
+pub mod upscale;
+pub mod display;
+pub mod watchpoints;
+pub mod instruction;
+pub mod profiler;
+pub mod coverage;
+pub mod flow_graph;
+pub mod lint;
+pub mod decompiler;
+pub mod decode_cache;
+pub mod recompiler;
+pub mod runtime;
+pub mod static_recompiler;
+pub mod tui;
+pub mod state;
+pub mod rpc;
+pub mod scripting;
+pub mod cheats;
+pub mod machine;
@@ -0,0 +1,185 @@
+use crate::consts;
+use crate::flow_graph::{EdgeKind, FlowGraph};
+use crate::instruction::Instruction;
+
+use serde::Serialize;
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    Info,
+    Warning,
+    Error,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LintIssue {
+    pub address: u16,
+    pub severity: Severity,
+    pub message: String,
+}
+
+// Opcodes of the SUPER-CHIP and XO-CHIP extensions that this interpreter doesn't run
+fn platform_opcode(opcode: u16) -> Option<(&'static str, &'static str)> {
+    match opcode {
+        0x00FB => Some(("SUPER-CHIP", "scroll right")),
+        0x00FC => Some(("SUPER-CHIP", "scroll left")),
+        0x00FD => Some(("SUPER-CHIP", "exit")),
+        0xF000 => Some(("XO-CHIP", "long index load")),
+        0xF002 => Some(("XO-CHIP", "audio pattern load")),
+        _ => match (opcode & 0xF000, opcode & 0x00FF, opcode & 0x000F) {
+            (0x0000, _, _) if opcode & 0xFFF0 == 0x00C0 => Some(("SUPER-CHIP", "scroll down")),
+            (0x0000, _, _) if opcode & 0xFFF0 == 0x00D0 => Some(("XO-CHIP", "scroll up")),
+            (0x5000, _, 0x2) => Some(("XO-CHIP", "store register range")),
+            (0x5000, _, 0x3) => Some(("XO-CHIP", "load register range")),
+            (0xF000, 0x01, _) => Some(("XO-CHIP", "plane select")),
+            (0xF000, 0x30, _) => Some(("SUPER-CHIP", "large font")),
+            (0xF000, 0x3A, _) => Some(("XO-CHIP", "audio pitch")),
+            (0xF000, 0x75, _) => Some(("SUPER-CHIP", "store flags")),
+            (0xF000, 0x85, _) => Some(("SUPER-CHIP", "load flags")),
+            _ => None
+        }
+    }
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Info => f.pad("info"),
+            Severity::Warning => f.pad("warning"),
+            Severity::Error => f.pad("error"),
+        }
+    }
+}
+
+impl fmt::Display for LintIssue {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:#06x}  {:<7}  {}", self.address, self.severity, self.message)
+    }
+}
+
+pub fn lint(rom_content: &[u8]) -> Vec<LintIssue> {
+    let flow_graph = FlowGraph::analyse(rom_content);
+    let mut issues = Vec::new();
+    let mut issue = |address: u16, severity: Severity, message: String| {
+        issues.push(LintIssue{address: address, severity: severity, message: message});
+    };
+
+    for block in flow_graph.blocks.values() {
+        // The value of I when it is known, from an ANNN or an FX29 earlier in the block
+        let mut index_register: Option<u16> = None;
+
+        for decoded in &block.instructions {
+            match decoded.instruction {
+                None => {
+                    match platform_opcode(decoded.opcode) {
+                        Some((platform, description)) => issue(decoded.address, Severity::Error,
+                            format!("{:04x} is the {} {} instruction, which is not supported", decoded.opcode, platform, description)),
+                        None => issue(decoded.address, Severity::Error,
+                            format!("Invalid opcode {:04x}, running it stops the emulator", decoded.opcode))
+                    }
+                },
+                Some(Instruction::LowResolution) | Some(Instruction::HighResolution) => {
+                    issue(decoded.address, Severity::Info, String::from("Switching the resolution requires a SUPER-CHIP platform"));
+                },
+                Some(Instruction::Draw{height: 0, ..}) => {
+                    issue(decoded.address, Severity::Info, String::from("16x16 sprites require a SUPER-CHIP platform, other platforms draw nothing"));
+                },
+                Some(Instruction::JumpOffset{..}) => {
+                    issue(decoded.address, Severity::Info, String::from("Computed jump, the target differs between CHIP-8 (V0) and SUPER-CHIP (VX) and isn't checked"));
+                },
+                Some(Instruction::System(address)) => {
+                    issue(decoded.address, Severity::Warning, format!("Machine code routine call to {:#05x} is ignored", address));
+                },
+                Some(Instruction::LoadIndex(address)) => {
+                    index_register = Some(address);
+                },
+                Some(Instruction::LoadFont{..}) => {
+                    index_register = Some(consts::FONT_START_ADDR as u16);
+                },
+                Some(Instruction::AddIndex{..}) | Some(Instruction::LoadRegisters{..}) => {
+                    index_register = None;
+                },
+                Some(Instruction::StoreDecimal{..}) | Some(Instruction::StoreRegisters{..}) => {
+                    if let Some(address) = index_register.filter(|address| (*address as usize) < consts::PROGRAM_MEMORY_ADDR) {
+                        issue(decoded.address, Severity::Error, format!("Writes to {:#05x}, into the font and interpreter memory", address));
+                    }
+                    index_register = None;
+                },
+                Some(_) => {}
+            }
+        }
+    }
+
+    for edge in &flow_graph.edges {
+        let from_address = flow_graph.blocks[&edge.from].last().address;
+        match edge.kind {
+            EdgeKind::Jump | EdgeKind::Call => {
+                if !flow_graph.is_in_rom(edge.to) {
+                    issue(from_address, Severity::Error, format!("{} to {:#05x}, outside of the loaded rom", if edge.kind == EdgeKind::Jump { "Jump" } else { "Call" }, edge.to));
+                } else if edge.to % 2 != 0 {
+                    issue(from_address, Severity::Warning, format!("{} to the odd address {:#05x}", if edge.kind == EdgeKind::Jump { "Jump" } else { "Call" }, edge.to));
+                }
+            },
+            EdgeKind::Fallthrough | EdgeKind::Skip | EdgeKind::CallReturn => {
+                if !flow_graph.is_in_rom(edge.to) {
+                    issue(from_address, Severity::Error, String::from("Execution continues past the end of the rom"));
+                }
+            },
+            EdgeKind::Return => {}
+        }
+    }
+
+    // A return reached without a call pops an empty stack
+    for block_start in flow_graph.function_blocks(flow_graph.entry) {
+        let last = flow_graph.blocks[&block_start].last();
+        if last.instruction == Some(Instruction::Return) {
+            issue(last.address, Severity::Error, String::from("Return outside of a subroutine, the stack is empty"));
+        }
+    }
+    for subroutine in &flow_graph.subroutines {
+        let returns = flow_graph.function_blocks(*subroutine).iter()
+            .any(|block_start| flow_graph.blocks[block_start].last().instruction == Some(Instruction::Return));
+        if !returns && flow_graph.blocks.contains_key(subroutine) {
+            issue(*subroutine, Severity::Warning, String::from("Subroutine never returns, every call leaves an entry on the stack"));
+        }
+    }
+
+    // Unreached bytes are reported unless they are loaded into I somewhere, those are sprites or other data
+    let mut reached = BTreeSet::new();
+    let mut data_addresses = BTreeSet::new();
+    for block in flow_graph.blocks.values() {
+        for decoded in &block.instructions {
+            reached.insert(decoded.address as usize);
+            reached.insert(decoded.address as usize + 1);
+            if let Some(Instruction::LoadIndex(address)) = decoded.instruction {
+                data_addresses.insert(address as usize);
+            }
+        }
+    }
+
+    let mut address = consts::PROGRAM_MEMORY_ADDR;
+    while address < flow_graph.rom_end {
+        if reached.contains(&address) {
+            address += 1;
+            continue;
+        }
+
+        let region_start = address;
+        while address < flow_graph.rom_end && !reached.contains(&address) {
+            address += 1;
+        }
+        let is_data = data_addresses.range(region_start..address).next().is_some();
+        let is_padding = rom_content[region_start - consts::PROGRAM_MEMORY_ADDR..address - consts::PROGRAM_MEMORY_ADDR].iter().all(|byte| *byte == 0);
+        if !is_data && !is_padding {
+            issue(region_start as u16, Severity::Info, format!("Unreachable bytes {:#05x}-{:#05x}, dead code or data that is never loaded directly",
+                region_start, address - 1));
+        }
+    }
+
+    issues.sort_by(|first, second| first.address.cmp(&second.address).then(second.severity.cmp(&first.severity)));
+    return issues;
+}
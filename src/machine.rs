@@ -0,0 +1,51 @@
+use crate::config::{Config, Engine};
+use crate::cpu::CPU;
+use crate::errors::Chip8Error;
+use crate::memory::Memory;
+use crate::quirks::Quirks;
+use crate::watchpoints::Watchpoint;
+
+// Everything a rom is run with besides its content, so that roms loaded later run the same way as the first one
+#[derive(Debug, Clone)]
+pub struct MachineSetup {
+    pub quirks: Quirks,
+    pub watchpoints: Vec<Watchpoint>,
+    pub report_self_modifying_code: bool,
+    pub profile: bool,
+    pub coverage: bool,
+    pub use_recompiler: bool,
+}
+
+impl MachineSetup {
+    pub fn from_config(config: &Config) -> Result<MachineSetup, String> {
+        return Ok(MachineSetup{quirks: config.quirks()?, watchpoints: config.watchpoints()?,
+            report_self_modifying_code: config.debug.report_self_modifying_code, profile: false, coverage: false,
+            use_recompiler: config.cpu.engine == Engine::Recompiler});
+    }
+
+    // Profiling, coverage and watchpoints see every fetched instruction, the recompiler runs whole blocks instead
+    pub fn needs_interpreter(&self) -> bool {
+        return self.profile || self.coverage || !self.watchpoints.is_empty();
+    }
+
+    pub fn new_cpu(&self, rom_content: &[u8]) -> Result<CPU, Chip8Error> {
+        let mut memory = Memory::new_from_rom(rom_content.to_vec(), self.quirks.memory_size, self.quirks.address_policy)?;
+        for watchpoint in &self.watchpoints {
+            memory.add_watchpoint(watchpoint.clone());
+        }
+        memory.set_report_self_modifying_code(self.report_self_modifying_code);
+
+        let mut cpu = CPU::new(memory, self.quirks);
+        if self.profile {
+            cpu.enable_profiler();
+        }
+        if self.coverage {
+            cpu.enable_coverage(rom_content.to_vec());
+        }
+        if self.use_recompiler && !self.needs_interpreter() {
+            cpu.enable_recompiler();
+        }
+
+        return Ok(cpu);
+    }
+}
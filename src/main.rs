@@ -1,19 +1,35 @@
-mod cpu;
-mod memory;
-mod consts;
-mod errors;
-mod stack;
-mod delay_timer;
-mod sound_timer;
 
-use cpu::CPU;
-use memory::Memory;
+use chip8::cpu::CPU;
+use chip8::memory::Memory;
+use chip8::config::{Config, Engine, WindowMode};
+use chip8::audio::Beeper;
+use chip8::errors::Chip8Error;
+use chip8::romdb::{RomDatabase, RomMatch};
+use chip8::input::InputMapper;
+use chip8::palette::Palette;
+use chip8::effects::FrameRenderer;
+use chip8::upscale::Upscaler;
+use chip8::display::Display;
+use chip8::watchpoints::Watchpoint;
+use chip8::coverage::Coverage;
+use chip8::flow_graph::FlowGraph;
+use chip8::lint::Severity;
+use chip8::keypad::Keypad;
+use chip8::tui::{TerminalEvent, TerminalFrontend};
+use chip8::rpc::RpcServer;
+use chip8::scripting::ScriptHost;
+use chip8::cheats::{Cheat, CheatList};
+use chip8::machine::MachineSetup;
+use chip8::{cheats, config, consts, decompiler, lint, overlay, romdb, static_recompiler};
 
-use std::io::Read;
-use std::fs::File;
+use std::fs::{self, File};
+use std::path::Path;
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
 
 use sdl2::event::Event;
-use sdl2::keyboard::Scancode;
+use sdl2::keyboard::{Keycode, Mod};
 use sdl2::render::Canvas;
 use sdl2::video::Window;
 
@@ -21,12 +37,461 @@ use sdl2::video::Window;
 use simplelog::{ConfigBuilder, Level, CombinedLogger, TermLogger, WriteLogger, LevelFilter, TerminalMode, Color, ColorChoice};
 
 extern crate clap;
-use clap::{Arg, App};
+use clap::{Arg, App, AppSettings, SubCommand};
 
-fn emulate() -> Result<(), String> {
+fn parse_level_filter(level: &str) -> Result<LevelFilter, String> {
+    match level.to_lowercase().as_str() {
+        "off" => Ok(LevelFilter::Off),
+        "trace" => Ok(LevelFilter::Trace),
+        "debug" => Ok(LevelFilter::Debug),
+        "info" => Ok(LevelFilter::Info),
+        "warn" => Ok(LevelFilter::Warn),
+        "error" => Ok(LevelFilter::Error),
+        _ => Err(format!("Unknown verbosity level specified : {}", level))
+    }
+}
+
+fn print_rom_info(rom_file_path: &str) -> Result<(), Chip8Error> {
+    let rom_content = std::fs::read(rom_file_path).map_err(|e| Chip8Error::io_error(rom_file_path, e))?;
+    let rom_sha1 = romdb::rom_sha1(&rom_content);
+
+    println!("File        : {}", rom_file_path);
+    println!("Size        : {} bytes", rom_content.len());
+    println!("SHA-1       : {}", rom_sha1);
+
+    let rom_match: Option<RomMatch> = RomDatabase::load().map_err(Chip8Error::ConfigError)?.lookup(&rom_sha1);
+    match &rom_match {
+        Some(matched_rom) => {
+            println!("Title       : {}", matched_rom.program.title);
+            if let Some(database_file_name) = &matched_rom.rom.file {
+                println!("Known as    : {}", database_file_name);
+            }
+            if !matched_rom.program.authors.is_empty() {
+                println!("Authors     : {}", matched_rom.program.authors.join(", "));
+            }
+            if let Some(release) = &matched_rom.program.release {
+                println!("Release     : {}", release);
+            }
+            if let Some(description) = &matched_rom.program.description {
+                println!("Description : {}", description);
+            }
+            if !matched_rom.rom.platforms.is_empty() {
+                println!("Platforms   : {}", matched_rom.rom.platforms.join(", "));
+            }
+
+            // The settings the rom database entry applies
+            let layer = toml::to_string_pretty(&matched_rom.config_layer()).map_err(|e| Chip8Error::ConfigError(e.to_string()))?;
+            if !layer.trim().is_empty() {
+                println!();
+                println!("Settings applied from the rom database :");
+                print!("{}", layer);
+            }
+        },
+        None => {
+            println!("Title       : Not found in the rom database");
+        }
+    }
+
+    if let Some(override_path) = config::rom_override_path(&rom_sha1) {
+        if override_path.is_file() {
+            println!();
+            println!("User settings for this rom are loaded from {}", override_path.display());
+        }
+    }
+
+    if let Some(cheats_path) = cheats::cheats_path(&rom_sha1).filter(|cheats_path| cheats_path.is_file()) {
+        println!();
+        println!("Cheats for this rom are loaded from {}", cheats_path.display());
+    }
+
+    return Ok(());
+}
+
+fn lint_rom(rom_file_path: &str, format: &str) -> Result<(), Chip8Error> {
+    let rom_content = fs::read(rom_file_path).map_err(|e| Chip8Error::io_error(rom_file_path, e))?;
+    let issues = lint::lint(&rom_content);
+    let error_count = issues.iter().filter(|issue| issue.severity == Severity::Error).count();
+    let warning_count = issues.iter().filter(|issue| issue.severity == Severity::Warning).count();
+
+    if format == "json" {
+        println!("{}", serde_json::to_string_pretty(&issues).map_err(|e| Chip8Error::FrontendError(e.to_string()))?);
+    } else {
+        for issue in &issues {
+            println!("{}", issue);
+        }
+        println!("{} : {} errors, {} warnings", rom_file_path, error_count, warning_count);
+    }
+
+    if error_count > 0 {
+        return Err(Chip8Error::LintErrors(error_count));
+    }
+
+    return Ok(());
+}
+
+fn export_flow_graph(rom_file_path: &str, format: &str, output_path: Option<&str>) -> Result<(), Chip8Error> {
+    let rom_content = fs::read(rom_file_path).map_err(|e| Chip8Error::io_error(rom_file_path, e))?;
+    let flow_graph = FlowGraph::analyse(&rom_content);
+
+    let output = match format {
+        "json" => serde_json::to_string_pretty(&flow_graph.to_json()).map_err(|e| Chip8Error::FrontendError(e.to_string()))? + "\n",
+        _ => flow_graph.to_dot()
+    };
+
+    match output_path {
+        Some(output_path) => fs::write(output_path, output).map_err(|e| Chip8Error::io_error(output_path, e))?,
+        None => print!("{}", output)
+    }
+
+    return Ok(());
+}
+
+// Runs without a display, input or audio, the timers still tick every instructions_per_frame instructions
+fn bench_rom(rom_file_path: &str, instructions: u64, quirks_profile: Option<&str>, engine: Option<&str>, decode_cache: bool) -> Result<(), Chip8Error> {
+    let rom_content = fs::read(rom_file_path).map_err(|e| Chip8Error::io_error(rom_file_path, e))?;
+    let mut config = Config::load(None, &[]).map_err(Chip8Error::ConfigError)?;
+    if let Some(profile) = quirks_profile {
+        config.quirks.profile = profile.to_string();
+    }
+    if let Some(engine) = engine {
+        config.cpu.engine = parse_engine(engine);
+    }
+    config.validate().map_err(Chip8Error::ConfigError)?;
+
+    let quirks = config.quirks().map_err(Chip8Error::ConfigError)?;
+    let mut memory = Memory::new_from_rom(rom_content, quirks.memory_size, quirks.address_policy)?;
+    memory.set_decode_cache(decode_cache);
+    let mut cpu = CPU::new(memory, quirks);
+    if config.cpu.engine == Engine::Recompiler {
+        cpu.enable_recompiler();
+    }
+    let keypad = Keypad::new();
+    let instructions_per_frame = config.cpu.instructions_per_frame.max(1) as u64;
+
+    let start_time = Instant::now();
+    let mut executed = 0;
+    while executed < instructions {
+        let frame_instructions = instructions_per_frame.min(instructions - executed) as u32;
+        let mut frame_executed = 0;
+        while frame_executed < frame_instructions && !cpu.is_waiting_for_key() {
+            frame_executed += cpu.step(&keypad, frame_instructions - frame_executed)?;
+        }
+        cpu.tick_timers();
+        executed += frame_executed as u64;
+
+        // No key is ever pressed, steps waiting for one don't retire instructions
+        if cpu.is_waiting_for_key() {
+            println!("Stopped waiting for a key");
+            break;
+        }
+    }
+    let elapsed = start_time.elapsed().as_secs_f64();
+
+    println!("Executed {} instructions in {:.3}s, {:.1} million instructions per second", executed, elapsed, executed as f64 / elapsed / 1_000_000.0);
+    return Ok(());
+}
+
+fn parse_engine(engine: &str) -> Engine {
+    match engine {
+        "recompiler" => Engine::Recompiler,
+        _ => Engine::Interpreter
+    }
+}
+
+fn decompile_rom(rom_file_path: &str, output_path: Option<&str>) -> Result<(), Chip8Error> {
+    let rom_content = fs::read(rom_file_path).map_err(|e| Chip8Error::io_error(rom_file_path, e))?;
+    let rom_file_name = Path::new(rom_file_path).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_else(|| String::from("rom"));
+    let source = decompiler::decompile(&rom_content, &rom_file_name);
+
+    match output_path {
+        Some(output_path) => fs::write(output_path, source).map_err(|e| Chip8Error::io_error(output_path, e))?,
+        None => print!("{}", source)
+    }
+
+    return Ok(());
+}
+
+fn recompile_rom(rom_file_path: &str, output_dir: &str, quirks_profile: Option<&str>, chip8_path: Option<&str>) -> Result<(), Chip8Error> {
+    let rom_content = fs::read(rom_file_path).map_err(|e| Chip8Error::io_error(rom_file_path, e))?;
+    let mut config = Config::load(None, &[]).map_err(Chip8Error::ConfigError)?;
+    if let Some(profile) = quirks_profile {
+        config.quirks.profile = profile.to_string();
+    }
+    config.validate().map_err(Chip8Error::ConfigError)?;
+    let quirks = config.quirks().map_err(Chip8Error::ConfigError)?;
+
+    let rom_file_name = Path::new(rom_file_path).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_else(|| String::from("rom"));
+    // The generated crate links against this emulator, built from its source directory by default
+    let chip8_path = Path::new(chip8_path.unwrap_or(env!("CARGO_MANIFEST_DIR")));
+    let generated = static_recompiler::recompile(&rom_content, &rom_file_name, &quirks, chip8_path);
+
+    let output_dir = Path::new(output_dir);
+    let source_dir = output_dir.join("src");
+    fs::create_dir_all(&source_dir).map_err(|e| Chip8Error::io_error(&source_dir.to_string_lossy(), e))?;
+    for (path, content) in [(output_dir.join("Cargo.toml"), generated.cargo_toml.as_bytes()), (source_dir.join("main.rs"), generated.main_rs.as_bytes()),
+            (output_dir.join("rom.ch8"), rom_content.as_slice())] {
+        fs::write(&path, content).map_err(|e| Chip8Error::io_error(&path.to_string_lossy(), e))?;
+    }
+
+    info!("Recompiled {} to {}, build it with cargo build --release", rom_file_name, output_dir.display());
+    return Ok(());
+}
+
+fn write_coverage(coverage: &Coverage, coverage_dir: &Path, rom_file_path: &str) -> Result<(), Chip8Error> {
+    let rom_file_name = Path::new(rom_file_path).file_name().map(|name| name.to_string_lossy().to_string()).unwrap_or_else(|| String::from("rom"));
+    let disassembly_path = coverage_dir.join(format!("{}.asm", rom_file_name));
+    let lcov_path = coverage_dir.join("lcov.info");
+
+    let report = coverage.report(&disassembly_path.to_string_lossy());
+    fs::create_dir_all(coverage_dir).map_err(|e| Chip8Error::io_error(&coverage_dir.to_string_lossy(), e))?;
+    fs::write(&disassembly_path, &report.disassembly).map_err(|e| Chip8Error::io_error(&disassembly_path.to_string_lossy(), e))?;
+    fs::write(&lcov_path, &report.lcov).map_err(|e| Chip8Error::io_error(&lcov_path.to_string_lossy(), e))?;
+
+    info!("Executed {} of {} instructions, coverage written to {}", report.executed_instructions, report.total_instructions, coverage_dir.display());
+    return Ok(());
+}
+
+// Runs the instructions of one 60hz frame, a watchpoint or a script that pauses the emulation ends the frame early
+fn run_frame(cpu: &mut CPU, keypad: &Keypad, instructions_per_frame: u32, paused: &mut bool, cheats: &mut CheatList, mut scripts: Option<&mut ScriptHost>) -> Result<(), Chip8Error> {
+    cheats.apply(cpu);
+
+    let mut executed = 0;
+    while executed < instructions_per_frame {
+        if let Some(scripts) = scripts.as_deref_mut() {
+            scripts.before_instruction(cpu, keypad);
+        }
+        executed += cpu.step(keypad, instructions_per_frame - executed)?;
+
+        let memory_events = cpu.take_memory_events();
+        for (program_counter, event) in &memory_events {
+            if event.is_script_hook() {
+                continue;
+            }
+
+            warn!("WATCH | PC {:#06x} | {}", program_counter, event);
+            if event.pauses() {
+                *paused = true;
+            }
+        }
+
+        if let Some(scripts) = scripts.as_deref_mut() {
+            scripts.after_instructions(cpu, &memory_events);
+            *paused |= scripts.take_pause_request();
+        }
+
+        if *paused {
+            info!("Emulation paused, press F5 to resume");
+            break;
+        }
+    }
+
+    cpu.tick_timers();
+    if let Some(scripts) = scripts {
+        scripts.on_frame(cpu);
+        if scripts.take_pause_request() {
+            *paused = true;
+            info!("Emulation paused, press F5 to resume");
+        }
+    }
+
+    return Ok(());
+}
+
+fn run_in_window(cpu: &mut CPU, config: &Config, cheats: &mut CheatList, mut rpc_server: Option<&mut RpcServer>, mut scripts: Option<&mut ScriptHost>) -> Result<(), Chip8Error> {
+    let palette = Palette::from_config(&config.palette).map_err(Chip8Error::ConfigError)?;
+
+    // Initialize sdl
+    let scale_factor = config.display.scale;
+    let sdl_context = sdl2::init().map_err(Chip8Error::FrontendError)?;
+    let video_subsystem = sdl_context.video().map_err(Chip8Error::FrontendError)?;
+    let mut window_builder = video_subsystem.window("Chip8 Emulator", consts::DISPLAY_WIDTH as u32 * scale_factor, consts::DISPLAY_HEIGHT as u32 * scale_factor);
+    window_builder.resizable();
+    match config.display.window_mode {
+        WindowMode::Windowed => {},
+        WindowMode::Fullscreen => {
+            window_builder.fullscreen();
+        },
+        WindowMode::Borderless => {
+            window_builder.fullscreen_desktop();
+        }
+    }
+    let window = window_builder.build().map_err(|e| Chip8Error::FrontendError(e.to_string()))?;
+
+    let mut canvas_builder = window.into_canvas();
+    if config.display.software_renderer {
+        canvas_builder = canvas_builder.software();
+    }
+    let canvas : Canvas<Window> = canvas_builder.build().map_err(|e| Chip8Error::FrontendError(e.to_string()))?;
+
+    let texture_creator = canvas.texture_creator();
+    let mut display = Display::new(canvas, &texture_creator, config.display.scaling);
+    let upscaler = Upscaler::from_name(&config.display.upscaler).map_err(Chip8Error::ConfigError)?;
+    let mut frame_renderer = FrameRenderer::new(palette, config.effects.clone(), upscaler, scale_factor as usize);
+
+    let mut event_pump = sdl_context.event_pump().map_err(Chip8Error::FrontendError)?;
+    let controller_subsystem = sdl_context.game_controller().map_err(Chip8Error::FrontendError)?;
+    let mut input_mapper = InputMapper::new(config, controller_subsystem).map_err(Chip8Error::ConfigError)?;
+
+    let mut beeper: Option<Beeper> = None;
+    if config.audio.enabled {
+        match Beeper::new(&config.audio) {
+            Ok(new_beeper) => beeper = Some(new_beeper),
+            Err(e) => warn!("Failed initializing audio, continuing without sound : {}", e)
+        }
+    }
+
+    let frame_duration = Duration::from_secs_f32(consts::TIMER_TICK_MILLIS / 1000.0);
+
+    // Emulation is paused by watchpoints and F5, the window keeps being drawn while paused
+    let mut paused = false;
+
+    // Main loop
+    'main_loop: loop {
+        let frame_start_time = Instant::now();
+
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Quit {..} => {
+                    error!("Got quit event");
+                    break 'main_loop;
+                },
+                Event::KeyDown {keycode: Some(Keycode::Return), keymod, repeat: false, ..} if keymod.intersects(Mod::LALTMOD | Mod::RALTMOD) => {
+                    display.toggle_fullscreen().map_err(Chip8Error::FrontendError)?;
+                },
+                Event::KeyDown {keycode: Some(Keycode::F5), repeat: false, ..} => {
+                    paused = !paused;
+                    info!("Emulation {}", if paused { "paused" } else { "resumed" });
+                },
+                _ => {}
+            }
+
+            input_mapper.handle_event(&event);
+        }
+
+        let mut keypad = input_mapper.keypad_state(&event_pump.keyboard_state());
+        if let Some(rpc_server) = rpc_server.as_deref_mut() {
+            rpc_server.poll(cpu, &mut paused, cheats);
+            if rpc_server.quit_requested() {
+                break 'main_loop;
+            }
+            keypad = keypad.merged(&rpc_server.keypad());
+        }
+        if let Some(scripts) = scripts.as_deref() {
+            keypad = keypad.merged(&scripts.keypad());
+        }
+
+        if !paused {
+            run_frame(cpu, &keypad, config.cpu.instructions_per_frame, &mut paused, cheats, scripts.as_deref_mut())?;
+        }
+
+        if let Some(beeper) = beeper.as_mut() {
+            beeper.set_active(cpu.is_sound_active() && !paused);
+        }
+
+        // The layout follows the window size and the chip8 resolution, both can change at any frame
+        let layout = display.layout(cpu.framebuffer().width(), cpu.framebuffer().height()).map_err(Chip8Error::FrontendError)?;
+        frame_renderer.set_scale(layout.pixel_scale);
+
+        let (mut frame, frame_width, frame_height) = frame_renderer.render(cpu.framebuffer());
+        if let Some(scripts) = scripts.as_deref() {
+            overlay::draw_text(&mut frame, frame_width, frame_height, &scripts.hud());
+        }
+        display.present(&frame, frame_width, frame_height, &layout).map_err(Chip8Error::FrontendError)?;
+
+        let frame_elapsed_time = frame_start_time.elapsed();
+        if frame_elapsed_time < frame_duration {
+            thread::sleep(frame_duration - frame_elapsed_time);
+        }
+    }
+
+    return Ok(());
+}
+
+// Runs in the terminal the emulator was started from, for machines without a display
+fn run_in_terminal(cpu: &mut CPU, config: &Config, cheats: &mut CheatList, mut rpc_server: Option<&mut RpcServer>, mut scripts: Option<&mut ScriptHost>) -> Result<(), Chip8Error> {
+    let mut terminal = TerminalFrontend::new(config).map_err(Chip8Error::FrontendError)?;
+    let frame_duration = Duration::from_secs_f32(consts::TIMER_TICK_MILLIS / 1000.0);
+
+    // Emulation is paused by watchpoints and F5, the terminal keeps being drawn while paused
+    let mut paused = false;
+
+    'main_loop: loop {
+        let frame_start_time = Instant::now();
+
+        for event in terminal.poll_events().map_err(Chip8Error::FrontendError)? {
+            match event {
+                TerminalEvent::Quit => {
+                    info!("Got quit key");
+                    break 'main_loop;
+                },
+                TerminalEvent::TogglePause => {
+                    paused = !paused;
+                    info!("Emulation {}", if paused { "paused" } else { "resumed" });
+                }
+            }
+        }
+
+        let mut keypad = terminal.keypad_state();
+        if let Some(rpc_server) = rpc_server.as_deref_mut() {
+            rpc_server.poll(cpu, &mut paused, cheats);
+            if rpc_server.quit_requested() {
+                break 'main_loop;
+            }
+            keypad = keypad.merged(&rpc_server.keypad());
+        }
+        if let Some(scripts) = scripts.as_deref() {
+            keypad = keypad.merged(&scripts.keypad());
+        }
+
+        if !paused {
+            run_frame(cpu, &keypad, config.cpu.instructions_per_frame, &mut paused, cheats, scripts.as_deref_mut())?;
+        }
+
+        if let Some(scripts) = scripts.as_deref() {
+            terminal.set_hud(&scripts.hud());
+        }
+        terminal.render(cpu, paused).map_err(Chip8Error::FrontendError)?;
+
+        let frame_elapsed_time = frame_start_time.elapsed();
+        if frame_elapsed_time < frame_duration {
+            thread::sleep(frame_duration - frame_elapsed_time);
+        }
+    }
+
+    return Ok(());
+}
+
+// Runs without a window or sound, driven by the clients of the control server
+fn run_headless(cpu: &mut CPU, config: &Config, cheats: &mut CheatList, rpc_server: &mut RpcServer, mut scripts: Option<&mut ScriptHost>) -> Result<(), Chip8Error> {
+    let frame_duration = Duration::from_secs_f32(consts::TIMER_TICK_MILLIS / 1000.0);
+    let mut paused = false;
+
+    while !rpc_server.quit_requested() {
+        let frame_start_time = Instant::now();
+
+        rpc_server.poll(cpu, &mut paused, cheats);
+        if !paused && !rpc_server.quit_requested() {
+            let keypad = match scripts.as_deref() {
+                Some(scripts) => rpc_server.keypad().merged(&scripts.keypad()),
+                None => rpc_server.keypad()
+            };
+            run_frame(cpu, &keypad, config.cpu.instructions_per_frame, &mut paused, cheats, scripts.as_deref_mut())?;
+        }
+
+        let frame_elapsed_time = frame_start_time.elapsed();
+        if frame_elapsed_time < frame_duration {
+            thread::sleep(frame_duration - frame_elapsed_time);
+        }
+    }
+
+    return Ok(());
+}
+
+fn emulate() -> Result<(), Chip8Error> {
     // Parse command line arguments
     let command_line_args = App::new("Chip8 Emulator")
                             .author("Ron Popov AKA DirtyAxe")
+                            .setting(AppSettings::SubcommandsNegateReqs)
                             .arg(Arg::with_name("Rom File")
                                 .short("f")
                                 .long("rom-file")
@@ -41,21 +506,353 @@ fn emulate() -> Result<(), String> {
                                 .help("Log Verbosity Level")
                                 .takes_value(true)
                                 .required(false))
+                            .arg(Arg::with_name("Config File")
+                                .short("c")
+                                .long("config")
+                                .value_name("FILE_PATH")
+                                .help("Configuration file applied on top of the user and project configuration")
+                                .takes_value(true)
+                                .required(false))
+                            .arg(Arg::with_name("Scale")
+                                .short("s")
+                                .long("scale")
+                                .value_name("FACTOR")
+                                .help("Window scale factor")
+                                .takes_value(true)
+                                .required(false))
+                            .arg(Arg::with_name("Fullscreen")
+                                .long("fullscreen")
+                                .help("Start in fullscreen mode")
+                                .required(false))
+                            .arg(Arg::with_name("Instructions Per Frame")
+                                .long("ipf")
+                                .value_name("COUNT")
+                                .help("Instructions executed per 60hz frame")
+                                .takes_value(true)
+                                .required(false))
+                            .arg(Arg::with_name("Quirks")
+                                .short("q")
+                                .long("quirks")
+                                .value_name("PROFILE")
+                                .help("Quirk profile (chip8, modern, schip, xochip)")
+                                .takes_value(true)
+                                .required(false))
+                            .arg(Arg::with_name("Engine")
+                                .long("engine")
+                                .value_name("ENGINE")
+                                .help("Cpu engine (interpreter, recompiler)")
+                                .takes_value(true)
+                                .possible_values(&["interpreter", "recompiler"])
+                                .required(false))
+                            .arg(Arg::with_name("Palette")
+                                .short("p")
+                                .long("palette")
+                                .value_name("NAME")
+                                .help("Display palette (classic, green, amber, lcd)")
+                                .takes_value(true)
+                                .required(false))
+                            .arg(Arg::with_name("Upscaler")
+                                .long("upscaler")
+                                .value_name("NAME")
+                                .help("Pixel art upscaling filter (nearest, scale2x, scale3x, scale4x, hq2x)")
+                                .takes_value(true)
+                                .required(false))
+                            .arg(Arg::with_name("Mute")
+                                .long("mute")
+                                .help("Disable the buzzer")
+                                .required(false))
+                            .arg(Arg::with_name("Terminal")
+                                .long("tui")
+                                .help("Run in the terminal instead of a window")
+                                .required(false))
+                            .arg(Arg::with_name("Rpc")
+                                .long("rpc")
+                                .value_name("ADDRESS")
+                                .help("Serve JSON-RPC control requests on tcp:<host>:<port> or unix:<path>")
+                                .takes_value(true)
+                                .required(false))
+                            .arg(Arg::with_name("Headless")
+                                .long("headless")
+                                .help("Run without a window or sound, controlled through --rpc")
+                                .requires("Rpc")
+                                .conflicts_with("Terminal")
+                                .required(false))
+                            .arg(Arg::with_name("Cheat")
+                                .long("cheat")
+                                .value_name("CODE")
+                                .help("Cheat code as <address>:<value>[?<address>=<value>], codes are joined with +, can be given more than once")
+                                .takes_value(true)
+                                .multiple(true)
+                                .number_of_values(1)
+                                .required(false))
+                            .arg(Arg::with_name("Script")
+                                .long("script")
+                                .value_name("FILE_PATH")
+                                .help("Rhai script hooked to the emulator events, can be given more than once")
+                                .takes_value(true)
+                                .multiple(true)
+                                .number_of_values(1)
+                                .required(false))
+                            .arg(Arg::with_name("Log File")
+                                .long("log-file")
+                                .value_name("FILE_PATH")
+                                .help("Path of the log file")
+                                .takes_value(true)
+                                .required(false))
+                            .arg(Arg::with_name("Watch")
+                                .short("w")
+                                .long("watch")
+                                .value_name("WATCHPOINT")
+                                .help("Memory watchpoint as <access>:<start>[-<end>][:<action>], like w:0x300-0x30F:pause")
+                                .takes_value(true)
+                                .multiple(true)
+                                .number_of_values(1)
+                                .required(false))
+                            .arg(Arg::with_name("Profile")
+                                .long("profile")
+                                .help("Print a profile of the executed instructions at exit")
+                                .required(false))
+                            .arg(Arg::with_name("Profile Json")
+                                .long("profile-json")
+                                .value_name("FILE_PATH")
+                                .help("Write the profile of the executed instructions as JSON at exit")
+                                .takes_value(true)
+                                .required(false))
+                            .arg(Arg::with_name("Coverage")
+                                .long("coverage")
+                                .value_name("DIR_PATH")
+                                .help("Write an annotated disassembly and an lcov report of the executed instructions at exit")
+                                .takes_value(true)
+                                .required(false))
+                            .arg(Arg::with_name("Save Rom Settings")
+                                .long("save-rom-settings")
+                                .help("Save the keys, palette, speed and quirks used for this rom as its defaults")
+                                .required(false))
+                            .subcommand(SubCommand::with_name("info")
+                                .about("Print the rom database entry matched by a rom file")
+                                .arg(Arg::with_name("Rom File")
+                                    .value_name("FILE_PATH")
+                                    .help("Path of a rom file")
+                                    .index(1)
+                                    .required(true)))
+                            .subcommand(SubCommand::with_name("cfg")
+                                .about("Export the control flow graph of a rom")
+                                .arg(Arg::with_name("Rom File")
+                                    .value_name("FILE_PATH")
+                                    .help("Path of a rom file")
+                                    .index(1)
+                                    .required(true))
+                                .arg(Arg::with_name("Format")
+                                    .long("format")
+                                    .value_name("FORMAT")
+                                    .help("Output format (dot, json)")
+                                    .takes_value(true)
+                                    .possible_values(&["dot", "json"])
+                                    .default_value("dot"))
+                                .arg(Arg::with_name("Output")
+                                    .short("o")
+                                    .long("output")
+                                    .value_name("FILE_PATH")
+                                    .help("Write the graph to a file instead of the standard output")
+                                    .takes_value(true)
+                                    .required(false)))
+                            .subcommand(SubCommand::with_name("decompile")
+                                .about("Decompile a rom to Octo source")
+                                .arg(Arg::with_name("Rom File")
+                                    .value_name("FILE_PATH")
+                                    .help("Path of a rom file")
+                                    .index(1)
+                                    .required(true))
+                                .arg(Arg::with_name("Output")
+                                    .short("o")
+                                    .long("output")
+                                    .value_name("FILE_PATH")
+                                    .help("Write the source to a file instead of the standard output")
+                                    .takes_value(true)
+                                    .required(false)))
+                            .subcommand(SubCommand::with_name("bench")
+                                .about("Run a rom headless as fast as possible and print the instructions per second")
+                                .arg(Arg::with_name("Rom File")
+                                    .value_name("FILE_PATH")
+                                    .help("Path of a rom file")
+                                    .index(1)
+                                    .required(true))
+                                .arg(Arg::with_name("Instructions")
+                                    .long("instructions")
+                                    .value_name("COUNT")
+                                    .help("Instructions to execute")
+                                    .takes_value(true)
+                                    .default_value("50000000"))
+                                .arg(Arg::with_name("Quirks")
+                                    .short("q")
+                                    .long("quirks")
+                                    .value_name("PROFILE")
+                                    .help("Quirk profile (chip8, modern, schip, xochip)")
+                                    .takes_value(true)
+                                    .required(false))
+                                .arg(Arg::with_name("Engine")
+                                    .long("engine")
+                                    .value_name("ENGINE")
+                                    .help("Cpu engine (interpreter, recompiler)")
+                                    .takes_value(true)
+                                    .possible_values(&["interpreter", "recompiler"])
+                                    .required(false))
+                                .arg(Arg::with_name("No Decode Cache")
+                                    .long("no-decode-cache")
+                                    .help("Decode every instruction when it is executed")))
+                            .subcommand(SubCommand::with_name("recompile")
+                                .about("Translate a rom to a standalone Rust program")
+                                .arg(Arg::with_name("Rom File")
+                                    .value_name("FILE_PATH")
+                                    .help("Path of a rom file")
+                                    .index(1)
+                                    .required(true))
+                                .arg(Arg::with_name("Output")
+                                    .short("o")
+                                    .long("output")
+                                    .value_name("DIR")
+                                    .help("Directory to generate the crate in")
+                                    .takes_value(true)
+                                    .required(true))
+                                .arg(Arg::with_name("Quirks")
+                                    .short("q")
+                                    .long("quirks")
+                                    .value_name("PROFILE")
+                                    .help("Quirk profile (chip8, modern, schip, xochip)")
+                                    .takes_value(true)
+                                    .required(false))
+                                .arg(Arg::with_name("Chip8 Path")
+                                    .long("chip8-path")
+                                    .value_name("DIR")
+                                    .help("Path of the emulator source the generated crate depends on")
+                                    .takes_value(true)
+                                    .required(false)))
+                            .subcommand(SubCommand::with_name("lint")
+                                .about("Check a rom for invalid instructions, bad jumps and other mistakes")
+                                .arg(Arg::with_name("Rom File")
+                                    .value_name("FILE_PATH")
+                                    .help("Path of a rom file")
+                                    .index(1)
+                                    .required(true))
+                                .arg(Arg::with_name("Format")
+                                    .long("format")
+                                    .value_name("FORMAT")
+                                    .help("Output format (text, json)")
+                                    .takes_value(true)
+                                    .possible_values(&["text", "json"])
+                                    .default_value("text")))
                             .get_matches();
 
-    let terminal_log_level_filter: LevelFilter = match command_line_args.value_of("Verbosity") {
-        Some(level) => match level.to_lowercase().as_str() {
-            "trace" => LevelFilter::Trace,
-            "debug" => LevelFilter::Debug,
-            "info" => LevelFilter::Info,
-            "warn" => LevelFilter::Warn,
-            "error" => LevelFilter::Error,
-            _ => {
-                panic!("Unkown verbosity level specified");
-            }
-        },
-        None => LevelFilter::Info,
-    };
+    if let Some(info_args) = command_line_args.subcommand_matches("info") {
+        return print_rom_info(info_args.value_of("Rom File").unwrap());
+    }
+
+    if let Some(lint_args) = command_line_args.subcommand_matches("lint") {
+        return lint_rom(lint_args.value_of("Rom File").unwrap(), lint_args.value_of("Format").unwrap_or("text"));
+    }
+
+    if let Some(decompile_args) = command_line_args.subcommand_matches("decompile") {
+        return decompile_rom(decompile_args.value_of("Rom File").unwrap(), decompile_args.value_of("Output"));
+    }
+
+    if let Some(recompile_args) = command_line_args.subcommand_matches("recompile") {
+        return recompile_rom(recompile_args.value_of("Rom File").unwrap(), recompile_args.value_of("Output").unwrap(), recompile_args.value_of("Quirks"),
+            recompile_args.value_of("Chip8 Path"));
+    }
+
+    if let Some(bench_args) = command_line_args.subcommand_matches("bench") {
+        let instructions = bench_args.value_of("Instructions").unwrap_or("50000000");
+        let instructions = instructions.parse::<u64>().map_err(|_| Chip8Error::InvalidArgument(format!("Invalid instruction count : {}", instructions)))?;
+        return bench_rom(bench_args.value_of("Rom File").unwrap(), instructions, bench_args.value_of("Quirks"), bench_args.value_of("Engine"),
+            !bench_args.is_present("No Decode Cache"));
+    }
+
+    if let Some(cfg_args) = command_line_args.subcommand_matches("cfg") {
+        return export_flow_graph(cfg_args.value_of("Rom File").unwrap(), cfg_args.value_of("Format").unwrap_or("dot"), cfg_args.value_of("Output"));
+    }
+
+    // Get rom file path from command line args
+    let rom_file_path: String = command_line_args.value_of("Rom File")
+        .ok_or_else(|| Chip8Error::InvalidArgument(String::from("No rom file given, use --rom-file")))?
+        .to_string();
+
+    let rom_content: Vec<u8> = std::fs::read(&rom_file_path).map_err(|e| Chip8Error::io_error(&rom_file_path, e))?;
+
+    // Settings of the rom from the rom database, and the ones saved by the user for it
+    let rom_sha1 = romdb::rom_sha1(&rom_content);
+    let rom_match = RomDatabase::load().map_err(Chip8Error::ConfigError)?.lookup(&rom_sha1);
+
+    let mut rom_layers: Vec<toml::Value> = Vec::new();
+    if let Some(matched_rom) = &rom_match {
+        rom_layers.push(matched_rom.config_layer());
+    }
+    if let Some(rom_override) = config::load_rom_override(&rom_sha1).map_err(Chip8Error::ConfigError)? {
+        rom_layers.push(rom_override);
+    }
+
+    // Load configuration, command line arguments override it
+    let mut config = Config::load(command_line_args.value_of("Config File"), &rom_layers).map_err(Chip8Error::ConfigError)?;
+
+    if let Some(scale) = command_line_args.value_of("Scale") {
+        config.display.scale = scale.parse::<u32>().map_err(|_| Chip8Error::InvalidArgument(format!("Invalid scale factor : {}", scale)))?;
+    }
+
+    if command_line_args.is_present("Fullscreen") {
+        config.display.window_mode = WindowMode::Fullscreen;
+    }
+
+    if let Some(ipf) = command_line_args.value_of("Instructions Per Frame") {
+        config.cpu.instructions_per_frame = ipf.parse::<u32>()
+            .map_err(|_| Chip8Error::InvalidArgument(format!("Invalid instructions per frame : {}", ipf)))?;
+    }
+
+    if let Some(profile) = command_line_args.value_of("Quirks") {
+        config.quirks.profile = profile.to_string();
+    }
+
+    if let Some(engine) = command_line_args.value_of("Engine") {
+        config.cpu.engine = parse_engine(engine);
+    }
+
+    if let Some(palette_name) = command_line_args.value_of("Palette") {
+        config.palette.name = palette_name.to_string();
+    }
+
+    if let Some(upscaler_name) = command_line_args.value_of("Upscaler") {
+        config.display.upscaler = upscaler_name.to_string();
+    }
+
+    if command_line_args.is_present("Mute") {
+        config.audio.enabled = false;
+    }
+
+    if let Some(log_file) = command_line_args.value_of("Log File") {
+        config.log.file = Some(log_file.to_string());
+    }
+
+    if let Some(level) = command_line_args.value_of("Verbosity") {
+        config.log.level = level.to_string();
+    }
+
+    if let Some(watchpoints) = command_line_args.values_of("Watch") {
+        for watchpoint in watchpoints {
+            Watchpoint::parse(watchpoint).map_err(Chip8Error::InvalidArgument)?;
+            config.debug.watchpoints.push(watchpoint.to_string());
+        }
+    }
+
+    config.validate().map_err(Chip8Error::ConfigError)?;
+
+    if command_line_args.is_present("Save Rom Settings") {
+        // Only what differs from the configuration files and the rom database ends up in the rom settings
+        let database_layers: Vec<toml::Value> = rom_match.iter().map(|matched_rom| matched_rom.config_layer()).collect();
+        let lower_config = Config::load(None, &database_layers).map_err(Chip8Error::ConfigError)?;
+        let override_path = config.save_rom_override(&rom_sha1, &lower_config).map_err(Chip8Error::ConfigError)?;
+        println!("Saved rom settings to {}", override_path.display());
+    }
+
+    let terminal_log_level_filter = parse_level_filter(&config.log.level).map_err(Chip8Error::ConfigError)?;
+    let file_log_level_filter = parse_level_filter(&config.log.file_level).map_err(Chip8Error::ConfigError)?;
 
     // Initialize logger
     let mut config_builder = ConfigBuilder::new();
@@ -63,88 +860,109 @@ fn emulate() -> Result<(), String> {
     config_builder.set_location_level(LevelFilter::Off);
     config_builder.set_target_level(LevelFilter::Error);
 
-    let config = config_builder.build();
-    let term_logger = TermLogger::new(terminal_log_level_filter, config.clone(), TerminalMode::Mixed, ColorChoice::Auto);
-    let mut logging_vector: Vec<Box<dyn simplelog::SharedLogger>> = vec![term_logger];
-    logging_vector.push(WriteLogger::new(LevelFilter::Trace, config.clone(), File::create("Chip8.log").unwrap()));
-    let logger_init_result = CombinedLogger::init(logging_vector);
-
-    if logger_init_result.is_err() {
-        println!("Failed initializing logger : {}", logger_init_result.unwrap_err());
-        return Ok(());
+    let log_config = config_builder.build();
+    let mut logging_vector: Vec<Box<dyn simplelog::SharedLogger>> = Vec::new();
+    // Logging to the terminal would draw over the terminal frontend, only the log file is written then
+    if !command_line_args.is_present("Terminal") {
+        logging_vector.push(TermLogger::new(terminal_log_level_filter, log_config.clone(), TerminalMode::Mixed, ColorChoice::Auto));
     }
+    if let Some(log_file_path) = &config.log.file {
+        let log_file = File::create(log_file_path).map_err(|e| Chip8Error::io_error(log_file_path, e))?;
+        logging_vector.push(WriteLogger::new(file_log_level_filter, log_config.clone(), log_file));
+    }
+    CombinedLogger::init(logging_vector).map_err(|e| Chip8Error::FrontendError(format!("Failed initializing logger : {}", e)))?;
 
     // Logger inialized and arguments parsed, PARTY
     info!("Starting Chip8");
-
-    // Get rom file path from command line args
-    let rom_file_path: String = command_line_args.value_of("Rom File").unwrap_or_else(|| {
-        error!("Command line args are {:?}", command_line_args);
-        panic!("Failed unwrapping rom file path");
-    }).to_string();
-
     info!("Rom file path is \"{}\"", rom_file_path);
 
-    // Initialize memory
-    let rom_file: File = File::open(rom_file_path).expect("Failed opening rom file");
-
-    let rom_content = rom_file.bytes().map(|value| {
-        value.expect("Failed reading rom file")
-    }).collect();
+    match &rom_match {
+        Some(matched_rom) => info!("Rom {} matched \"{}\" in the rom database", rom_sha1, matched_rom.program.title),
+        None => info!("Rom {} was not found in the rom database", rom_sha1)
+    }
 
-    let memory: Memory = Memory::new_from_rom(rom_content);
+    // Scripts register their hooks as they load, the write hooks are watched by the memory
+    let script_paths: Vec<String> = command_line_args.values_of("Script").map(|paths| paths.map(String::from).collect()).unwrap_or_default();
+    let mut scripts = if script_paths.is_empty() { None } else { Some(ScriptHost::load(&script_paths)?) };
 
-    // Initialize sdl
-    let sdl_context = sdl2::init()?;
-    let video_subsystem = sdl_context.video()?;
-    let window = video_subsystem.window("Chip8 Emulator", consts::DISPLAY_WIDTH as u32 * consts::SCALE_FACTOR as u32, consts::DISPLAY_HEIGHT as u32 * consts::SCALE_FACTOR as u32).build().unwrap();
+    // Roms loaded through the rpc server later are set up the same way
+    let mut setup = MachineSetup::from_config(&config).map_err(Chip8Error::ConfigError)?;
+    info!("Using quirks {:?}", setup.quirks);
+    for watchpoint in &setup.watchpoints {
+        info!("WATCH | Watching {:#06x}-{:#06x}", watchpoint.start, watchpoint.end);
+    }
+    if let Some(scripts) = scripts.as_ref() {
+        setup.watchpoints.extend(scripts.watchpoints());
+    }
+    setup.profile = command_line_args.is_present("Profile") || command_line_args.is_present("Profile Json");
+    setup.coverage = command_line_args.is_present("Coverage");
+    if setup.use_recompiler {
+        if setup.needs_interpreter() || scripts.as_ref().is_some_and(|scripts| scripts.needs_interpreter()) {
+            warn!("Profiling, coverage, watchpoints and script hooks need every instruction to be fetched, using the interpreter");
+            setup.use_recompiler = false;
+        } else {
+            info!("Using the recompiler");
+        }
+    }
+    let mut cpu = setup.new_cpu(&rom_content)?;
 
-    let mut canvas : Canvas<Window> = window.into_canvas()
-        .present_vsync()
-        .build().unwrap();
+    // The saved cheats of the rom, and the cheats of the command line on top of them, which are never saved
+    let mut cheats = CheatList::for_rom(&rom_sha1, setup.quirks.memory_size).map_err(Chip8Error::ConfigError)?;
+    if let Some(codes) = command_line_args.values_of("Cheat") {
+        for code in codes {
+            let cheat = Cheat::parse(code, code).map_err(Chip8Error::InvalidArgument)?;
+            cheat.check_addresses(setup.quirks.memory_size).map_err(Chip8Error::InvalidArgument)?;
+            cheats.add_session_cheat(cheat);
+        }
+    }
+    let enabled_cheats = cheats.cheats().iter().chain(cheats.session_cheats()).filter(|cheat| cheat.enabled).count();
+    if enabled_cheats > 0 {
+        info!("Using {} cheats", enabled_cheats);
+    }
 
-    canvas.set_scale(consts::SCALE_FACTOR as f32, consts::SCALE_FACTOR as f32)?;
-    canvas.clear();
+    let mut rpc_server = match command_line_args.value_of("Rpc") {
+        Some(rpc_address) => Some(RpcServer::bind(rpc_address, &config, setup).map_err(Chip8Error::FrontendError)?),
+        None => None
+    };
 
-    let mut event_pump = sdl_context.event_pump()?;
+    // A cpu error still lets the profile be written before stopping
+    let emulation_result = if let (true, Some(rpc_server)) = (command_line_args.is_present("Headless"), rpc_server.as_mut()) {
+        run_headless(&mut cpu, &config, &mut cheats, rpc_server, scripts.as_mut())
+    } else if command_line_args.is_present("Terminal") {
+        run_in_terminal(&mut cpu, &config, &mut cheats, rpc_server.as_mut(), scripts.as_mut())
+    } else {
+        run_in_window(&mut cpu, &config, &mut cheats, rpc_server.as_mut(), scripts.as_mut())
+    };
 
-    // Initialize cpu
-    let mut cpu = CPU::new(memory, canvas);
+    if let Some(profiler) = cpu.profiler() {
+        let report = profiler.report();
+        if command_line_args.is_present("Profile") {
+            println!("{}", report.to_text());
+        }
 
-    // Main loop
-    'main_loop: loop {
-        let keys: Vec<Scancode> = event_pump
-            .keyboard_state()
-            .pressed_scancodes()
-            // .filter_map(Keycode::from_scancode)
-            .collect();
-
-        let cpu_result = cpu.execute_instruction(keys);
-        if cpu_result.is_err() {
-            error!("Leaving main loop, Got cpu error : {:?}", cpu_result.unwrap_err());
-            break 'main_loop;
-        }
-
-        match event_pump.poll_event() {
-            Some(event) => {
-                match event {
-                    Event::Quit {..} => {
-                        error!("Got quit event");
-                        break 'main_loop;
-                    },
-                    _ => {}
-                }
-            },
-            None => {}
+        if let Some(json_path) = command_line_args.value_of("Profile Json") {
+            let json = serde_json::to_string_pretty(&report).map_err(|e| Chip8Error::FrontendError(e.to_string()))?;
+            std::fs::write(json_path, json).map_err(|e| Chip8Error::io_error(json_path, e))?;
+            info!("Profile written to {}", json_path);
         }
     }
 
-    return Ok(());
+    if let (Some(coverage), Some(coverage_dir)) = (cpu.coverage(), command_line_args.value_of("Coverage")) {
+        write_coverage(coverage, Path::new(coverage_dir), &rom_file_path)?;
+    }
+
+    return emulation_result;
 }
 
 fn main() {
     let return_val = emulate();
-    if return_val.is_err() {
-        error!("Stopping due to {}", return_val.unwrap_err());
+    if let Err(error) = return_val {
+        if log::max_level() == LevelFilter::Off {
+            eprintln!("Stopping due to {}", error);
+        } else {
+            error!("Stopping due to {}", error);
+        }
+
+        process::exit(error.exit_code());
     }
 }
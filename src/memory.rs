@@ -1,61 +1,187 @@
 use crate::consts;
 use crate::errors::Chip8Error;
+use crate::watchpoints::{AccessTracker, MemoryEvent, Watchpoint};
+use crate::instruction::{self, Instruction};
+use crate::decode_cache::DecodeCache;
+
+use serde::{Deserialize, Serialize};
+
+// What happens to an access past the end of memory
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AddressPolicy {
+    // The address wraps around to the start of memory, like the address lines of the real hardware
+    Wrap,
+    // The access fails with the address that was accessed
+    Error,
+    // The access goes to the last byte of memory
+    Clamp,
+}
 
 pub struct Memory {
     memory_space: Vec<u8>,
-    font_addresses: [u16; 16]
+    font_addresses: [u16; 16],
+    address_policy: AddressPolicy,
+    tracker: AccessTracker,
+    decode_cache: DecodeCache,
+    // The written addresses since they were last taken, only kept when enabled
+    write_log: Option<Vec<usize>>,
 }
 
 impl Memory {
-    pub fn new() -> Memory {
-        Memory{memory_space: vec![0x00.into(); consts::MEMORY_SIZE], font_addresses: [0; 16]}
+    pub fn new(memory_size: usize, address_policy: AddressPolicy) -> Memory {
+        Memory{memory_space: vec![0u8; memory_size], font_addresses: [0; 16], address_policy: address_policy,
+            tracker: AccessTracker::new(memory_size), decode_cache: DecodeCache::new(memory_size),
+            write_log: None}
     }
 
-    pub fn load_font(&mut self) {
+    pub fn load_font(&mut self) -> Result<(), Chip8Error> {
         let mut font_index: u16 = consts::FONT_START_ADDR as u16;
         for (i,digit_font_content) in consts::FONT_CONTENT.iter().enumerate() {
             self.font_addresses[i] = font_index;
             for b in digit_font_content {
-                self.set_value(font_index, *b);
+                self.set_value(font_index as usize, *b)?;
                 font_index += 1;
             }
         }
+
+        return Ok(());
     }
 
     pub fn get_font_addr(&self, digit: u8) -> Result<u16, Chip8Error> {
-        if (digit as usize) > self.font_addresses.len() {
+        if (digit as usize) >= self.font_addresses.len() {
             return Err(Chip8Error::InvalidKeycode(digit));
         }
 
         return Ok(self.font_addresses[digit as usize]);
     }
 
-    pub fn new_from_rom(rom_content: Vec<u8>) -> Memory {
-        if rom_content.len() > (consts::MEMORY_SIZE - consts::PROGRAM_MEMORY_ADDR) {
-            panic!("Rom content is too much to load to memory");
+    pub fn new_from_rom(rom_content: Vec<u8>, memory_size: usize, address_policy: AddressPolicy) -> Result<Memory, Chip8Error> {
+        let max_rom_size = memory_size - consts::PROGRAM_MEMORY_ADDR;
+        if rom_content.len() > max_rom_size {
+            return Err(Chip8Error::RomTooLarge{rom_size: rom_content.len(), max_size: max_rom_size});
         }
 
         debug!("ROM_LOAD | Loading a rom of length {}", rom_content.len());
 
-        let mut mem: Memory = Memory::new();
-        let mut counter: u16 = consts::PROGRAM_MEMORY_ADDR as u16;
+        let mut mem: Memory = Memory::new(memory_size, address_policy);
+        let mut counter: usize = consts::PROGRAM_MEMORY_ADDR;
         for byte in rom_content {
-            mem.set_value(counter, byte);
+            mem.set_value(counter, byte)?;
             counter += 1;
         }
 
         debug!("ROM_LOAD | Loaded rom to memory in address {} -> {}", consts::PROGRAM_MEMORY_ADDR, counter);
 
-        mem.load_font();
-        return mem;
+        mem.load_font()?;
+        return Ok(mem);
+    }
+
+    // The index in memory an address refers to, according to the address policy
+    pub fn resolve_address(&self, address: usize) -> Result<usize, Chip8Error> {
+        if address < self.memory_space.len() {
+            return Ok(address);
+        }
+
+        match self.address_policy {
+            AddressPolicy::Wrap => Ok(address % self.memory_space.len()),
+            AddressPolicy::Error => Err(Chip8Error::AddressOutOfRange(address)),
+            AddressPolicy::Clamp => Ok(self.memory_space.len() - 1),
+        }
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.tracker.add_watchpoint(watchpoint);
     }
 
-    pub fn get_value(&self, index: u16) -> u8 {
-        self.memory_space[index as usize]
+    pub fn set_report_self_modifying_code(&mut self, report_self_modifying_code: bool) {
+        self.tracker.set_report_self_modifying_code(report_self_modifying_code);
     }
 
-    pub fn set_value(&mut self, index: u16, value: u8) {
-        trace!("Changing memory addr {:#6x} from {} to {}", index, self.get_value(index), value);
-        self.memory_space[index as usize] = value
+    pub fn take_events(&mut self) -> Vec<MemoryEvent> {
+        return self.tracker.take_events();
     }
-}
\ No newline at end of file
+
+    pub fn set_decode_cache(&mut self, enabled: bool) {
+        self.decode_cache.set_enabled(enabled);
+    }
+
+    pub fn size(&self) -> usize {
+        return self.memory_space.len();
+    }
+
+    pub fn contents(&self) -> &[u8] {
+        return &self.memory_space;
+    }
+
+    // Replaces the whole memory, like when loading a save state, without being seen by the watchpoints
+    pub fn restore(&mut self, contents: &[u8]) -> Result<(), Chip8Error> {
+        if contents.len() != self.memory_space.len() {
+            return Err(Chip8Error::InvalidSaveState(format!("Memory of {} bytes doesn't fit the {} bytes of this machine", contents.len(), self.memory_space.len())));
+        }
+
+        self.memory_space.copy_from_slice(contents);
+        self.decode_cache.clear();
+        return Ok(());
+    }
+
+    pub fn enable_write_log(&mut self) {
+        self.write_log = Some(Vec::new());
+    }
+
+    pub fn has_writes(&self) -> bool {
+        return self.write_log.as_ref().is_some_and(|write_log| !write_log.is_empty());
+    }
+
+    pub fn take_writes(&mut self) -> Vec<usize> {
+        return self.write_log.as_mut().map(std::mem::take).unwrap_or_default();
+    }
+
+    // Reads without being seen by the watchpoints, for debugging output
+    pub fn peek_value(&self, address: usize) -> Result<u8, Chip8Error> {
+        let index = self.resolve_address(address)?;
+        return Ok(self.memory_space[index]);
+    }
+
+    pub fn get_value(&mut self, address: usize) -> Result<u8, Chip8Error> {
+        let index = self.resolve_address(address)?;
+        self.tracker.on_read(index, self.memory_space[index]);
+        return Ok(self.memory_space[index]);
+    }
+
+    // Fetches the opcode at an address and decodes it, the decoded instruction is kept until its bytes are written to
+    pub fn fetch_instruction(&mut self, address: usize) -> Result<(u16, Instruction), Chip8Error> {
+        let high_index = self.resolve_address(address)?;
+        let low_index = self.resolve_address(address + 1)?;
+        self.tracker.on_execute(high_index, self.memory_space[high_index]);
+        self.tracker.on_execute(low_index, self.memory_space[low_index]);
+
+        return self.decode_instruction(address);
+    }
+
+    fn decode_instruction(&mut self, address: usize) -> Result<(u16, Instruction), Chip8Error> {
+        let high_index = self.resolve_address(address)?;
+        let low_index = self.resolve_address(address + 1)?;
+        if let Some(cached) = self.decode_cache.get(high_index) {
+            return Ok(cached);
+        }
+
+        let opcode = ((self.memory_space[high_index] as u16) << 8) + self.memory_space[low_index] as u16;
+        let instruction = instruction::decode(opcode)?;
+        self.decode_cache.insert(high_index, opcode, instruction);
+        return Ok((opcode, instruction));
+    }
+
+    pub fn set_value(&mut self, address: usize, value: u8) -> Result<(), Chip8Error> {
+        let index = self.resolve_address(address)?;
+        trace!("Changing memory addr {:#6x} from {} to {}", index, self.memory_space[index], value);
+        self.tracker.on_write(index, self.memory_space[index], value);
+        self.memory_space[index] = value;
+        self.decode_cache.invalidate(index);
+        if let Some(write_log) = self.write_log.as_mut() {
+            write_log.push(index);
+        }
+
+        return Ok(());
+    }
+}
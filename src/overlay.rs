@@ -0,0 +1,152 @@
+// Text drawn over rendered frames, used for the hud of the scripts
+const GLYPH_WIDTH: usize = 3;
+const GLYPH_HEIGHT: usize = 5;
+// Frame pixels between the glyphs, in font pixels
+const GLYPH_SPACING: usize = 1;
+const TEXT_COLOR: [u8; 3] = [0xFF, 0xFF, 0xFF];
+
+// Rows of a 3x5 glyph, the high bit is the left column, lower case is drawn as upper case
+fn glyph(character: char) -> [u8; GLYPH_HEIGHT] {
+    return match character.to_ascii_uppercase() {
+        ' ' => [0, 0, 0, 0, 0],
+        '0' => [7, 5, 5, 5, 7],
+        '1' => [2, 6, 2, 2, 7],
+        '2' => [7, 1, 7, 4, 7],
+        '3' => [7, 1, 7, 1, 7],
+        '4' => [5, 5, 7, 1, 1],
+        '5' => [7, 4, 7, 1, 7],
+        '6' => [7, 4, 7, 5, 7],
+        '7' => [7, 1, 1, 1, 1],
+        '8' => [7, 5, 7, 5, 7],
+        '9' => [7, 5, 7, 1, 7],
+        'A' => [2, 5, 7, 5, 5],
+        'B' => [6, 5, 6, 5, 6],
+        'C' => [3, 4, 4, 4, 3],
+        'D' => [6, 5, 5, 5, 6],
+        'E' => [7, 4, 6, 4, 7],
+        'F' => [7, 4, 6, 4, 4],
+        'G' => [3, 4, 5, 5, 3],
+        'H' => [5, 5, 7, 5, 5],
+        'I' => [7, 2, 2, 2, 7],
+        'J' => [1, 1, 1, 5, 2],
+        'K' => [5, 5, 6, 5, 5],
+        'L' => [4, 4, 4, 4, 7],
+        'M' => [5, 7, 7, 5, 5],
+        'N' => [6, 5, 5, 5, 5],
+        'O' => [2, 5, 5, 5, 2],
+        'P' => [6, 5, 6, 4, 4],
+        'Q' => [2, 5, 5, 6, 3],
+        'R' => [6, 5, 6, 5, 5],
+        'S' => [3, 4, 2, 1, 6],
+        'T' => [7, 2, 2, 2, 2],
+        'U' => [5, 5, 5, 5, 7],
+        'V' => [5, 5, 5, 5, 2],
+        'W' => [5, 5, 7, 7, 5],
+        'X' => [5, 5, 2, 5, 5],
+        'Y' => [5, 5, 2, 2, 2],
+        'Z' => [7, 1, 2, 4, 7],
+        '.' => [0, 0, 0, 0, 2],
+        ',' => [0, 0, 0, 2, 4],
+        ':' => [0, 2, 0, 2, 0],
+        ';' => [0, 2, 0, 2, 4],
+        '-' => [0, 0, 7, 0, 0],
+        '+' => [0, 2, 7, 2, 0],
+        '=' => [0, 7, 0, 7, 0],
+        '*' => [0, 5, 2, 5, 0],
+        '/' => [1, 1, 2, 4, 4],
+        '%' => [5, 1, 2, 4, 5],
+        '!' => [2, 2, 2, 0, 2],
+        '\'' => [2, 2, 0, 0, 0],
+        '"' => [5, 5, 0, 0, 0],
+        '#' => [5, 7, 5, 7, 5],
+        '(' => [1, 2, 2, 2, 1],
+        ')' => [4, 2, 2, 2, 4],
+        '[' => [3, 2, 2, 2, 3],
+        ']' => [6, 2, 2, 2, 6],
+        '<' => [1, 2, 4, 2, 1],
+        '>' => [4, 2, 1, 2, 4],
+        '_' => [0, 0, 0, 0, 7],
+        _ => [7, 1, 2, 0, 2],
+    };
+}
+
+fn set_pixel(rgb24: &mut [u8], width: usize, x: usize, y: usize, color: [u8; 3]) {
+    let offset = (y * width + x) * 3;
+    rgb24[offset..offset + 3].copy_from_slice(&color);
+}
+
+// Draws the text in the top left corner of an RGB24 frame, over a darkened box so it reads on any palette.
+// The font grows with the frame, text that doesn't fit is cut off
+pub fn draw_text(rgb24: &mut [u8], width: usize, height: usize, text: &str) {
+    if text.is_empty() {
+        return;
+    }
+
+    let font_scale = (width / 192).max(1);
+    let advance = (GLYPH_WIDTH + GLYPH_SPACING) * font_scale;
+    let characters = text.chars().count().min(width / advance);
+    let box_width = (characters * advance + GLYPH_SPACING * font_scale).min(width);
+    let box_height = ((GLYPH_HEIGHT + 2 * GLYPH_SPACING) * font_scale).min(height);
+
+    for y in 0..box_height {
+        for x in 0..box_width {
+            let offset = (y * width + x) * 3;
+            for channel in &mut rgb24[offset..offset + 3] {
+                *channel /= 4;
+            }
+        }
+    }
+
+    for (index, character) in text.chars().take(characters).enumerate() {
+        let left = GLYPH_SPACING * font_scale + index * advance;
+        for (row, bits) in glyph(character).iter().enumerate() {
+            for column in 0..GLYPH_WIDTH {
+                if bits & (0b100 >> column) == 0 {
+                    continue;
+                }
+
+                for y in 0..font_scale {
+                    for x in 0..font_scale {
+                        let pixel_x = left + column * font_scale + x;
+                        let pixel_y = (GLYPH_SPACING + row) * font_scale + y;
+                        if pixel_x < width && pixel_y < height {
+                            set_pixel(rgb24, width, pixel_x, pixel_y, TEXT_COLOR);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lit_pixels(rgb24: &[u8]) -> Vec<usize> {
+        return rgb24.chunks(3).enumerate().filter(|(_, pixel)| *pixel == TEXT_COLOR).map(|(index, _)| index).collect();
+    }
+
+    #[test]
+    fn text_is_drawn_in_the_top_left_corner() {
+        let (width, height) = (64, 32);
+        let mut frame = vec![0x80; width * height * 3];
+        draw_text(&mut frame, width, height, "1");
+
+        // The 1 glyph at one pixel per font pixel, after the left margin
+        let expected: Vec<usize> = [(1, 2), (2, 1), (2, 2), (3, 2), (4, 2), (5, 1), (5, 2), (5, 3)]
+            .iter().map(|(y, x)| y * width + x).collect();
+        assert_eq!(lit_pixels(&frame), expected);
+        // The box behind the text is darkened, the rest of the frame is left alone
+        assert_eq!(frame[0], 0x20);
+        assert_eq!(frame[(10 * width + 10) * 3], 0x80);
+    }
+
+    #[test]
+    fn text_is_cut_off_at_the_edge_of_the_frame() {
+        let (width, height) = (8, 4);
+        let mut frame = vec![0; width * height * 3];
+        draw_text(&mut frame, width, height, "hello world");
+        assert!(!lit_pixels(&frame).is_empty());
+    }
+}
@@ -0,0 +1,46 @@
+use crate::config::{self, PaletteConfig};
+
+pub type Rgb = (u8, u8, u8);
+
+pub const PALETTE_NAMES: [&str; 5] = ["custom", "classic", "green", "amber", "lcd"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Palette {
+    pub background: Rgb,
+    pub foreground: Rgb,
+}
+
+impl Palette {
+    pub fn from_name(palette_name: &str) -> Option<Palette> {
+        match palette_name.to_lowercase().as_str() {
+            "classic" => Some(Palette{background: (0x00, 0x00, 0x00), foreground: (0xFF, 0xFF, 0xFF)}),
+            "green" => Some(Palette{background: (0x05, 0x14, 0x08), foreground: (0x33, 0xFF, 0x66)}),
+            "amber" => Some(Palette{background: (0x14, 0x0A, 0x00), foreground: (0xFF, 0xB0, 0x00)}),
+            "lcd" => Some(Palette{background: (0x9B, 0xBC, 0x0F), foreground: (0x0F, 0x38, 0x0F)}),
+            _ => None
+        }
+    }
+
+    // Named palettes, or the foreground and background colors for the custom one
+    pub fn from_config(palette_config: &PaletteConfig) -> Result<Palette, String> {
+        if palette_config.name.is_empty() || palette_config.name.to_lowercase() == "custom" {
+            return Ok(Palette{background: config::parse_color(&palette_config.background)?,
+                foreground: config::parse_color(&palette_config.foreground)?});
+        }
+
+        return Palette::from_name(&palette_config.name)
+            .ok_or_else(|| format!("Unknown palette \"{}\", expected one of {:?}", palette_config.name, PALETTE_NAMES));
+    }
+
+    // Color of a pixel lit at the given intensity, 0 is the background and 1 the foreground
+    pub fn color_at(&self, intensity: f32) -> Rgb {
+        let intensity = intensity.clamp(0.0, 1.0);
+        let blend = |background: u8, foreground: u8| {
+            (background as f32 + (foreground as f32 - background as f32) * intensity).round() as u8
+        };
+
+        return (blend(self.background.0, self.foreground.0),
+                blend(self.background.1, self.foreground.1),
+                blend(self.background.2, self.foreground.2));
+    }
+}
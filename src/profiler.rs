@@ -0,0 +1,136 @@
+use crate::instruction::Instruction;
+
+use serde::Serialize;
+
+use std::collections::HashMap;
+use std::time::Duration;
+
+const HOTSPOTS_COUNT: usize = 20;
+
+pub struct Profiler {
+    // Executions of every address, indexed by the address
+    address_counts: Vec<u64>,
+    // The instruction last executed at every address, for the hotspot listing
+    address_instructions: HashMap<u16, Instruction>,
+    pattern_counts: HashMap<&'static str, u64>,
+    frame_cycles: Vec<u32>,
+    current_frame_cycles: u32,
+    instruction_time: Duration,
+    draw_time: Duration,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Hotspot {
+    pub address: u16,
+    pub count: u64,
+    pub instruction: String,
+}
+
+#[derive(Debug, Serialize)]
+pub struct OpcodeCount {
+    pub pattern: String,
+    pub count: u64,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ProfileReport {
+    pub instructions: u64,
+    pub frames: usize,
+    pub average_cycles_per_frame: f64,
+    pub min_cycles_per_frame: u32,
+    pub max_cycles_per_frame: u32,
+    pub draw_sprite_seconds: f64,
+    pub other_instructions_seconds: f64,
+    pub hotspots: Vec<Hotspot>,
+    // Sorted by executions
+    pub opcode_histogram: Vec<OpcodeCount>,
+}
+
+impl Profiler {
+    pub fn new() -> Profiler {
+        Profiler{address_counts: vec![0; u16::MAX as usize + 1], address_instructions: HashMap::new(), pattern_counts: HashMap::new(),
+            frame_cycles: Vec::new(), current_frame_cycles: 0, instruction_time: Duration::ZERO, draw_time: Duration::ZERO}
+    }
+
+    pub fn record_instruction(&mut self, address: u16, instruction: &Instruction) {
+        self.address_counts[address as usize] += 1;
+        self.address_instructions.insert(address, *instruction);
+        *self.pattern_counts.entry(instruction.pattern()).or_insert(0) += 1;
+        self.current_frame_cycles += 1;
+    }
+
+    // Time of a whole instruction, including the time it spent drawing
+    pub fn record_instruction_time(&mut self, elapsed: Duration) {
+        self.instruction_time += elapsed;
+    }
+
+    pub fn record_draw_time(&mut self, elapsed: Duration) {
+        self.draw_time += elapsed;
+    }
+
+    pub fn end_frame(&mut self) {
+        self.frame_cycles.push(self.current_frame_cycles);
+        self.current_frame_cycles = 0;
+    }
+
+    pub fn report(&self) -> ProfileReport {
+        let instructions: u64 = self.pattern_counts.values().sum();
+
+        let mut hotspots: Vec<Hotspot> = self.address_instructions.iter()
+            .map(|(address, instruction)| Hotspot{address: *address, count: self.address_counts[*address as usize], instruction: instruction.to_string()})
+            .collect();
+        hotspots.sort_by(|first, second| second.count.cmp(&first.count).then(first.address.cmp(&second.address)));
+        hotspots.truncate(HOTSPOTS_COUNT);
+
+        let mut opcode_histogram: Vec<OpcodeCount> = self.pattern_counts.iter()
+            .map(|(pattern, count)| OpcodeCount{pattern: pattern.to_string(), count: *count})
+            .collect();
+        opcode_histogram.sort_by(|first, second| second.count.cmp(&first.count).then(first.pattern.cmp(&second.pattern)));
+
+        let average_cycles_per_frame = if self.frame_cycles.is_empty() {
+            0.0
+        } else {
+            self.frame_cycles.iter().map(|cycles| *cycles as f64).sum::<f64>() / self.frame_cycles.len() as f64
+        };
+
+        return ProfileReport{instructions: instructions, frames: self.frame_cycles.len(), average_cycles_per_frame: average_cycles_per_frame,
+            min_cycles_per_frame: self.frame_cycles.iter().copied().min().unwrap_or(0),
+            max_cycles_per_frame: self.frame_cycles.iter().copied().max().unwrap_or(0),
+            draw_sprite_seconds: self.draw_time.as_secs_f64(),
+            other_instructions_seconds: self.instruction_time.saturating_sub(self.draw_time).as_secs_f64(),
+            hotspots: hotspots, opcode_histogram: opcode_histogram};
+    }
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        return Profiler::new();
+    }
+}
+
+impl ProfileReport {
+    pub fn to_text(&self) -> String {
+        let mut text = String::new();
+        let percent = |count: u64| if self.instructions == 0 { 0.0 } else { count as f64 * 100.0 / self.instructions as f64 };
+
+        text.push_str(&format!("Executed {} instructions in {} frames\n", self.instructions, self.frames));
+        text.push_str(&format!("Cycles per frame : {:.1} average, {} min, {} max\n",
+            self.average_cycles_per_frame, self.min_cycles_per_frame, self.max_cycles_per_frame));
+        text.push_str(&format!("Time in draw_sprite : {:.3}s, other instructions : {:.3}s\n",
+            self.draw_sprite_seconds, self.other_instructions_seconds));
+
+        text.push('\n');
+        text.push_str("Hotspots\n");
+        for hotspot in &self.hotspots {
+            text.push_str(&format!("  {:#06x}  {:>10}  {:>5.1}%  {}\n", hotspot.address, hotspot.count, percent(hotspot.count), hotspot.instruction));
+        }
+
+        text.push('\n');
+        text.push_str("Opcodes\n");
+        for opcode_count in &self.opcode_histogram {
+            text.push_str(&format!("  {}  {:>10}  {:>5.1}%\n", opcode_count.pattern, opcode_count.count, percent(opcode_count.count)));
+        }
+
+        return text;
+    }
+}
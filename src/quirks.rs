@@ -0,0 +1,52 @@
+use crate::consts;
+use crate::memory::AddressPolicy;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Quirks {
+    // 8XY6 / 8XYE shift Vy into Vx instead of shifting Vx in place
+    pub shift_uses_vy: bool,
+    // FX55 / FX65 leave I pointing after the last register
+    pub load_store_increments_index: bool,
+    // BNNN jumps to XNN + Vx instead of NNN + V0
+    pub jump_uses_vx: bool,
+    // 8XY1 / 8XY2 / 8XY3 reset VF to 0
+    pub logic_resets_vf: bool,
+    // Sprites are clipped at the screen edges instead of wrapping around
+    pub clip_sprites: bool,
+    // Subroutine calls that can be nested before the stack overflows
+    pub stack_depth: usize,
+    // Keep the return addresses in memory at 0xEA0 like the COSMAC VIP interpreter did
+    pub memory_stack: bool,
+    // Bytes of memory, 4K on most platforms and 64K on XO-CHIP
+    pub memory_size: usize,
+    // How accesses past the end of memory are handled
+    pub address_policy: AddressPolicy,
+}
+
+pub const QUIRK_PROFILES: [&str; 4] = ["chip8", "modern", "schip", "xochip"];
+
+impl Quirks {
+    pub fn from_profile(profile_name: &str) -> Result<Quirks, String> {
+        match profile_name.to_lowercase().as_str() {
+            "chip8" | "vip" => Ok(Quirks{shift_uses_vy: true, load_store_increments_index: true,
+                jump_uses_vx: false, logic_resets_vf: true, clip_sprites: true, stack_depth: 12, memory_stack: false,
+                memory_size: consts::MEMORY_SIZE, address_policy: AddressPolicy::Wrap}),
+            "modern" => Ok(Quirks{shift_uses_vy: false, load_store_increments_index: true,
+                jump_uses_vx: false, logic_resets_vf: false, clip_sprites: false, stack_depth: 16, memory_stack: false,
+                memory_size: consts::MEMORY_SIZE, address_policy: AddressPolicy::Wrap}),
+            "schip" | "superchip" => Ok(Quirks{shift_uses_vy: false, load_store_increments_index: false,
+                jump_uses_vx: true, logic_resets_vf: false, clip_sprites: true, stack_depth: 16, memory_stack: false,
+                memory_size: consts::MEMORY_SIZE, address_policy: AddressPolicy::Wrap}),
+            "xochip" => Ok(Quirks{shift_uses_vy: true, load_store_increments_index: true,
+                jump_uses_vx: false, logic_resets_vf: false, clip_sprites: false, stack_depth: 16, memory_stack: false,
+                memory_size: consts::XOCHIP_MEMORY_SIZE, address_policy: AddressPolicy::Wrap}),
+            _ => Err(format!("Unknown quirk profile \"{}\", expected one of {:?}", profile_name, QUIRK_PROFILES))
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        return Quirks::from_profile("modern").unwrap();
+    }
+}
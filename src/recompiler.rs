@@ -0,0 +1,82 @@
+use crate::instruction::Instruction;
+
+use std::rc::Rc;
+
+// Longer runs are split, so that a block never crosses too far into data that only looks like code
+pub const MAX_BLOCK_LENGTH: usize = 64;
+
+// Straight line code, decoded once and run without fetching, the instruction at index i is at start + 2 * i
+pub struct Block {
+    pub start: u16,
+    pub instructions: Vec<Instruction>,
+}
+
+// Compiled blocks by start address, writes into a block drop it and leave its bytes to the interpreter
+pub struct BlockCache {
+    blocks: Vec<Option<Rc<Block>>>,
+    // The start of the blocks compiled over each memory byte, entries of dropped blocks are only cleared when the byte is written
+    covering_blocks: Vec<Vec<u16>>,
+    // Bytes written to after being compiled, the code there is self modifying
+    self_modified: Vec<bool>,
+}
+
+// Instructions after which the next instruction isn't known when compiling, or that wait for the next frame
+pub fn ends_block(instruction: &Instruction) -> bool {
+    match instruction {
+        Instruction::Jump(_) | Instruction::Call(_) | Instruction::Return | Instruction::JumpOffset{..} | Instruction::WaitForKey{..} => true,
+        _ => instruction.is_skip()
+    }
+}
+
+impl Block {
+    pub fn end(&self) -> usize {
+        return self.start as usize + self.instructions.len() * 2;
+    }
+}
+
+impl BlockCache {
+    pub fn new(memory_size: usize) -> BlockCache {
+        BlockCache{blocks: vec![None; u16::MAX as usize + 1], covering_blocks: vec![Vec::new(); memory_size], self_modified: vec![false; memory_size]}
+    }
+
+    pub fn get(&self, address: u16) -> Option<Rc<Block>> {
+        return self.blocks[address as usize].clone();
+    }
+
+    // Whether the instruction at an address can be part of a block, addresses that wrap around memory are left to the interpreter
+    pub fn can_compile(&self, address: usize) -> bool {
+        return address + 1 < self.covering_blocks.len() && !self.self_modified[address] && !self.self_modified[address + 1];
+    }
+
+    pub fn insert(&mut self, block: Block) -> Rc<Block> {
+        for index in block.start as usize..block.end() {
+            if !self.covering_blocks[index].contains(&block.start) {
+                self.covering_blocks[index].push(block.start);
+            }
+        }
+
+        let block = Rc::new(block);
+        self.blocks[block.start as usize] = Some(block.clone());
+        return block;
+    }
+
+    // Returns whether the written byte was compiled, the blocks containing it are dropped
+    pub fn on_write(&mut self, index: usize) -> bool {
+        let covering_blocks = std::mem::take(&mut self.covering_blocks[index]);
+        if covering_blocks.is_empty() {
+            return false;
+        }
+
+        self.self_modified[index] = true;
+        for start in covering_blocks {
+            let block = &mut self.blocks[start as usize];
+            // The block at the start may have been dropped, or replaced by one that doesn't reach the byte
+            if block.as_ref().is_some_and(|block| index < block.end()) {
+                let dropped = block.take().unwrap();
+                debug!("RECOMPILER | Dropped the block at {:#06x}, its code at {:#06x} was modified", dropped.start, index);
+            }
+        }
+
+        return true;
+    }
+}
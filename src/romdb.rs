@@ -0,0 +1,267 @@
+use crate::config;
+
+use serde::Deserialize;
+use sha1::{Digest, Sha1};
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+// Bundled copy of the database, in the format of the community chip-8-database
+const BUNDLED_HASHES: &str = include_str!("../data/romdb/sha1-hashes.json");
+const BUNDLED_PROGRAMS: &str = include_str!("../data/romdb/programs.json");
+
+pub const ROMDB_DIR_NAME: &str = "romdb";
+pub const HASHES_FILE_NAME: &str = "sha1-hashes.json";
+pub const PROGRAMS_FILE_NAME: &str = "programs.json";
+
+#[derive(Debug, Clone, Deserialize)]
+pub struct Program {
+    pub title: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub authors: Vec<String>,
+    #[serde(default)]
+    pub release: Option<String>,
+    pub roms: HashMap<String, RomEntry>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RomEntry {
+    #[serde(default)]
+    pub file: Option<String>,
+    #[serde(default)]
+    pub platforms: Vec<String>,
+    #[serde(default)]
+    pub quirky_platforms: HashMap<String, PlatformQuirks>,
+    #[serde(default)]
+    pub tickrate: Option<u32>,
+    #[serde(default)]
+    pub keys: HashMap<String, u8>,
+    #[serde(default)]
+    pub colors: Option<RomColors>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct PlatformQuirks {
+    pub shift: Option<bool>,
+    pub memory_increment_by_x: Option<bool>,
+    pub memory_leave_i_unchanged: Option<bool>,
+    pub wrap: Option<bool>,
+    pub jump: Option<bool>,
+    pub vblank: Option<bool>,
+    pub logic: Option<bool>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct RomColors {
+    #[serde(default)]
+    pub pixels: Vec<String>,
+}
+
+pub struct RomMatch {
+    pub program: Program,
+    pub rom: RomEntry,
+}
+
+pub struct RomDatabase {
+    hashes: HashMap<String, usize>,
+    programs: Vec<Program>,
+}
+
+pub fn rom_sha1(rom_content: &[u8]) -> String {
+    let mut hasher = Sha1::new();
+    hasher.update(rom_content);
+    return format!("{:x}", hasher.finalize());
+}
+
+// Maps the database platform ids to our quirk profiles
+pub fn platform_quirk_profile(platform: &str) -> Option<&'static str> {
+    match platform {
+        "originalChip8" | "hybridVIP" => Some("chip8"),
+        "modernChip8" => Some("modern"),
+        "superchip1" | "superchip" => Some("schip"),
+        "xochip" => Some("xochip"),
+        _ => None
+    }
+}
+
+// Maps the database semantic keys to the keyboard keys they are played with
+fn semantic_key_name(semantic_key: &str) -> Option<&'static str> {
+    // Only the first player is played with the arrows, the second one keeps the hex keys
+    match semantic_key {
+        "up" => Some("Up"),
+        "down" => Some("Down"),
+        "left" => Some("Left"),
+        "right" => Some("Right"),
+        "a" => Some("Space"),
+        "b" => Some("Left Shift"),
+        _ => None
+    }
+}
+
+// Maps the database semantic keys to the player, controller button and axis they are played with
+fn semantic_controller_inputs(semantic_key: &str) -> Option<(usize, &'static str, Option<&'static str>)> {
+    let (player, player_key) = match semantic_key.strip_prefix("player2") {
+        Some(player_key) => (2, player_key.to_lowercase()),
+        None => (1, semantic_key.to_lowercase())
+    };
+
+    match player_key.as_str() {
+        "up" => Some((player, "dpup", Some("lefty-"))),
+        "down" => Some((player, "dpdown", Some("lefty+"))),
+        "left" => Some((player, "dpleft", Some("leftx-"))),
+        "right" => Some((player, "dpright", Some("leftx+"))),
+        "a" => Some((player, "a", None)),
+        "b" => Some((player, "b", None)),
+        _ => None
+    }
+}
+
+impl RomDatabase {
+    // A database placed in the user configuration directory replaces the bundled one
+    pub fn load() -> Result<RomDatabase, String> {
+        if let Some(user_dir) = config::config_dir() {
+            let user_db_dir = user_dir.join(ROMDB_DIR_NAME);
+            if user_db_dir.join(HASHES_FILE_NAME).is_file() && user_db_dir.join(PROGRAMS_FILE_NAME).is_file() {
+                return RomDatabase::load_from_dir(&user_db_dir);
+            }
+        }
+
+        return RomDatabase::parse(BUNDLED_HASHES, BUNDLED_PROGRAMS);
+    }
+
+    pub fn load_from_dir(db_dir: &Path) -> Result<RomDatabase, String> {
+        debug!("ROMDB | Loading rom database from {}", db_dir.display());
+
+        let hashes_path = db_dir.join(HASHES_FILE_NAME);
+        let programs_path = db_dir.join(PROGRAMS_FILE_NAME);
+        let hashes = fs::read_to_string(&hashes_path).map_err(|e| format!("Failed reading {} : {}", hashes_path.display(), e))?;
+        let programs = fs::read_to_string(&programs_path).map_err(|e| format!("Failed reading {} : {}", programs_path.display(), e))?;
+
+        return RomDatabase::parse(&hashes, &programs);
+    }
+
+    pub fn parse(hashes_json: &str, programs_json: &str) -> Result<RomDatabase, String> {
+        let hashes: HashMap<String, usize> = serde_json::from_str(hashes_json).map_err(|e| format!("Invalid rom database hashes : {}", e))?;
+        let programs: Vec<Program> = serde_json::from_str(programs_json).map_err(|e| format!("Invalid rom database programs : {}", e))?;
+
+        return Ok(RomDatabase{hashes: hashes, programs: programs});
+    }
+
+    pub fn lookup(&self, sha1: &str) -> Option<RomMatch> {
+        let sha1 = sha1.to_lowercase();
+        let program = self.programs.get(*self.hashes.get(&sha1)?)?;
+        let rom = program.roms.get(&sha1).cloned().unwrap_or_default();
+
+        return Some(RomMatch{program: program.clone(), rom: rom});
+    }
+}
+
+impl RomMatch {
+    pub fn platform(&self) -> Option<&str> {
+        return self.rom.platforms.first().map(|platform| platform.as_str());
+    }
+
+    // The settings of the matched rom, as a configuration layer
+    pub fn config_layer(&self) -> toml::Value {
+        let mut layer = toml::value::Table::new();
+
+        if let Some(platform) = self.platform() {
+            let mut quirks = toml::value::Table::new();
+            match platform_quirk_profile(platform) {
+                Some(profile) => {
+                    quirks.insert(String::from("profile"), toml::Value::from(profile));
+                },
+                None => {
+                    warn!("ROMDB | Platform {} is not supported, using the configured quirks", platform);
+                }
+            }
+
+            if let Some(platform_quirks) = self.rom.quirky_platforms.get(platform) {
+                if let Some(shift) = platform_quirks.shift {
+                    quirks.insert(String::from("shift_uses_vy"), toml::Value::from(!shift));
+                }
+                if let Some(leave_i_unchanged) = platform_quirks.memory_leave_i_unchanged {
+                    quirks.insert(String::from("load_store_increments_index"), toml::Value::from(!leave_i_unchanged));
+                }
+                if let Some(wrap) = platform_quirks.wrap {
+                    quirks.insert(String::from("clip_sprites"), toml::Value::from(!wrap));
+                }
+                if let Some(jump) = platform_quirks.jump {
+                    quirks.insert(String::from("jump_uses_vx"), toml::Value::from(jump));
+                }
+                if let Some(logic) = platform_quirks.logic {
+                    quirks.insert(String::from("logic_resets_vf"), toml::Value::from(logic));
+                }
+                if platform_quirks.memory_increment_by_x.is_some() || platform_quirks.vblank.is_some() {
+                    debug!("ROMDB | Ignoring unsupported memoryIncrementByX / vblank quirks");
+                }
+            }
+
+            layer.insert(String::from("quirks"), toml::Value::Table(quirks));
+        }
+
+        if let Some(tickrate) = self.rom.tickrate {
+            let mut cpu = toml::value::Table::new();
+            cpu.insert(String::from("instructions_per_frame"), toml::Value::from(tickrate as i64));
+            layer.insert(String::from("cpu"), toml::Value::Table(cpu));
+        }
+
+        let mut keys = toml::value::Table::new();
+        for (semantic_key, chip_key) in &self.rom.keys {
+            if let Some(real_key) = semantic_key_name(semantic_key) {
+                keys.insert(format!("{:X}", chip_key), toml::Value::from(real_key));
+            }
+        }
+        if !keys.is_empty() {
+            layer.insert(String::from("keys"), toml::Value::Table(keys));
+        }
+
+        // Controller layouts, the first player's layout is shared by every controller unless a player has its own
+        let mut player_layouts: HashMap<usize, (toml::value::Table, toml::value::Table)> = HashMap::new();
+        for (semantic_key, chip_key) in &self.rom.keys {
+            if let Some((player, button, axis)) = semantic_controller_inputs(semantic_key) {
+                let (buttons, axes) = player_layouts.entry(player).or_default();
+                buttons.insert(button.to_string(), toml::Value::from(format!("{:X}", chip_key)));
+                if let Some(axis) = axis {
+                    axes.insert(axis.to_string(), toml::Value::from(format!("{:X}", chip_key)));
+                }
+            }
+        }
+        if !player_layouts.is_empty() {
+            let mut controller = toml::value::Table::new();
+            let mut players = toml::value::Table::new();
+            for (player, (buttons, axes)) in player_layouts {
+                let mut player_layout = toml::value::Table::new();
+                player_layout.insert(String::from("buttons"), toml::Value::Table(buttons));
+                player_layout.insert(String::from("axes"), toml::Value::Table(axes));
+
+                if player == 1 {
+                    controller.extend(player_layout);
+                } else {
+                    players.insert(player.to_string(), toml::Value::Table(player_layout));
+                }
+            }
+            if !players.is_empty() {
+                controller.insert(String::from("players"), toml::Value::Table(players));
+            }
+            layer.insert(String::from("controller"), toml::Value::Table(controller));
+        }
+
+        if let Some(colors) = &self.rom.colors {
+            if colors.pixels.len() >= 2 {
+                let mut palette = toml::value::Table::new();
+                palette.insert(String::from("name"), toml::Value::from("custom"));
+                palette.insert(String::from("background"), toml::Value::from(colors.pixels[0].clone()));
+                palette.insert(String::from("foreground"), toml::Value::from(colors.pixels[1].clone()));
+                layer.insert(String::from("palette"), toml::Value::Table(palette));
+            }
+        }
+
+        return toml::Value::Table(layer);
+    }
+}
@@ -0,0 +1,569 @@
+use crate::cheats::{Cheat, CheatList, MemorySearch, SearchCondition};
+use crate::config::Config;
+use crate::cpu::CPU;
+use crate::errors::Chip8Error;
+use crate::keypad::Keypad;
+use crate::machine::MachineSetup;
+use crate::romdb;
+use crate::state::MachineState;
+
+use serde_json::{json, Map, Value};
+
+use std::io::{ErrorKind, Read, Write};
+use std::ops::Range;
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+#[cfg(unix)]
+use std::os::unix::net::{UnixListener, UnixStream};
+
+// JSON-RPC 2.0 error codes
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+const EMULATOR_ERROR: i64 = -32000;
+
+// A client sending a longer line is disconnected
+const MAX_REQUEST_LENGTH: usize = 1024 * 1024;
+// The memory of XO-CHIP machines
+const MAX_READ_LENGTH: usize = 0x10000;
+const DEFAULT_SEARCH_RESULTS: u64 = 100;
+// A single step request blocks the emulation, longer runs have to be split across requests
+const MAX_STEP_INSTRUCTIONS: u64 = 1_000_000;
+const MAX_STEP_FRAMES: u64 = 600;
+
+trait RpcStream: Read + Write {}
+impl RpcStream for TcpStream {}
+#[cfg(unix)]
+impl RpcStream for UnixStream {}
+
+enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(UnixListener, String),
+}
+
+struct Connection {
+    stream: Box<dyn RpcStream>,
+    pending_input: Vec<u8>,
+    pending_output: Vec<u8>,
+    closed: bool,
+}
+
+struct RpcError {
+    code: i64,
+    message: String,
+}
+
+impl RpcError {
+    fn invalid_params(message: String) -> RpcError {
+        return RpcError{code: INVALID_PARAMS, message: message};
+    }
+
+    fn emulator(message: String) -> RpcError {
+        return RpcError{code: EMULATOR_ERROR, message: message};
+    }
+}
+
+impl From<Chip8Error> for RpcError {
+    fn from(error: Chip8Error) -> RpcError {
+        return RpcError::emulator(error.to_string());
+    }
+}
+
+// Serves newline delimited JSON-RPC 2.0 requests without blocking the emulation, on "tcp:<host>:<port>" or "unix:<path>"
+pub struct RpcServer {
+    listener: Listener,
+    connections: Vec<Connection>,
+    // Keys held by the clients, added to the keys of the frontend
+    keypad: Keypad,
+    // Roms loaded by the clients run with the setup and speed of the emulator
+    setup: MachineSetup,
+    instructions_per_frame: u32,
+    quit_requested: bool,
+    // The memory search of the clients, until a rom is loaded
+    search: Option<MemorySearch>,
+}
+
+fn hex_encode(bytes: &[u8]) -> String {
+    return bytes.iter().map(|byte| format!("{:02x}", byte)).collect();
+}
+
+fn hex_decode(hex: &str) -> Result<Vec<u8>, RpcError> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(RpcError::invalid_params(String::from("Hex data must be an even number of hex digits")));
+    }
+
+    return (0..hex.len()).step_by(2)
+        .map(|index| u8::from_str_radix(&hex[index..index + 2], 16).map_err(|_| RpcError::invalid_params(format!("Invalid hex byte \"{}\"", &hex[index..index + 2]))))
+        .collect();
+}
+
+fn param_u64(params: &Map<String, Value>, name: &str, default: Option<u64>) -> Result<u64, RpcError> {
+    match params.get(name) {
+        Some(value) => value.as_u64().ok_or_else(|| RpcError::invalid_params(format!("\"{}\" must be a positive integer", name))),
+        None => default.ok_or_else(|| RpcError::invalid_params(format!("Missing \"{}\"", name)))
+    }
+}
+
+fn param_count(params: &Map<String, Value>, max_count: u64) -> Result<u64, RpcError> {
+    let count = param_u64(params, "count", Some(1))?;
+    if count > max_count {
+        return Err(RpcError::invalid_params(format!("\"count\" must be at most {}, got {}", max_count, count)));
+    }
+    return Ok(count);
+}
+
+fn param_u8(params: &Map<String, Value>, name: &str) -> Result<Option<u8>, RpcError> {
+    match params.get(name) {
+        Some(value) => value.as_u64().filter(|value| *value <= 0xFF).map(|value| Some(value as u8))
+            .ok_or_else(|| RpcError::invalid_params(format!("\"{}\" must be a byte", name))),
+        None => Ok(None)
+    }
+}
+
+fn param_u16(params: &Map<String, Value>, name: &str) -> Result<Option<u16>, RpcError> {
+    match params.get(name) {
+        Some(value) => value.as_u64().filter(|value| *value <= 0xFFFF).map(|value| Some(value as u16))
+            .ok_or_else(|| RpcError::invalid_params(format!("\"{}\" must be a 16 bit integer", name))),
+        None => Ok(None)
+    }
+}
+
+fn param_bytes(params: &Map<String, Value>, name: &str) -> Result<Vec<u8>, RpcError> {
+    match params.get(name) {
+        Some(Value::String(hex)) => hex_decode(hex),
+        Some(Value::Array(values)) => values.iter()
+            .map(|value| value.as_u64().filter(|value| *value <= 0xFF).map(|value| value as u8)
+                .ok_or_else(|| RpcError::invalid_params(format!("\"{}\" must only hold bytes", name))))
+            .collect(),
+        Some(_) => Err(RpcError::invalid_params(format!("\"{}\" must be a hex string or an array of bytes", name))),
+        None => Err(RpcError::invalid_params(format!("Missing \"{}\"", name)))
+    }
+}
+
+fn run_instructions(cpu: &mut CPU, keypad: &Keypad, count: u64) -> Result<u64, Chip8Error> {
+    let mut executed = 0;
+    while executed < count {
+        executed += cpu.step(keypad, (count - executed).min(u32::MAX as u64) as u32)? as u64;
+    }
+    return Ok(executed);
+}
+
+// The addresses from address to address + length, which must all be inside the memory
+fn memory_range(address: u64, length: usize, memory_size: usize) -> Result<Range<usize>, RpcError> {
+    let end = (address as usize).checked_add(length).filter(|end| address < memory_size as u64 && *end <= memory_size);
+    match end {
+        Some(end) => Ok(address as usize..end),
+        None => Err(RpcError::invalid_params(format!("{} bytes at {:#x} go past the end of the {} bytes memory", length, address, memory_size)))
+    }
+}
+
+fn cheats_json(cheats: &CheatList) -> Value {
+    let cheats: Vec<Value> = cheats.cheats().iter()
+        .map(|cheat| json!({"name": cheat.name, "codes": cheat.codes.iter().map(|code| code.to_string()).collect::<Vec<String>>(), "enabled": cheat.enabled}))
+        .collect();
+    return json!({"cheats": cheats});
+}
+
+fn registers(cpu: &CPU) -> Value {
+    let (delay_timer, sound_timer) = cpu.timers();
+    let registers: Vec<u8> = (0..16).map(|register| cpu.v(register)).collect();
+    return json!({"v": registers, "i": cpu.index_register(), "pc": cpu.program_counter(), "delay_timer": delay_timer,
+        "sound_timer": sound_timer, "stack": cpu.stack().frames()});
+}
+
+// Registers, timers and memory are changed through a save state, so that the decoded instruction caches follow
+fn modify_state(cpu: &mut CPU, modify: impl FnOnce(&mut MachineState) -> Result<(), RpcError>) -> Result<(), RpcError> {
+    let mut state = cpu.save_state();
+    modify(&mut state)?;
+    cpu.load_state(&state)?;
+    return Ok(());
+}
+
+impl RpcServer {
+    pub fn bind(address: &str, config: &Config, setup: MachineSetup) -> Result<RpcServer, String> {
+        let listener = match address.split_once(':') {
+            Some(("tcp", tcp_address)) => {
+                // The server can read and write the whole machine, it is never reachable from other hosts
+                let resolved: Vec<SocketAddr> = tcp_address.to_socket_addrs().map_err(|e| format!("Failed resolving {} : {}", tcp_address, e))?.collect();
+                if resolved.is_empty() || !resolved.iter().all(|resolved_address| resolved_address.ip().is_loopback()) {
+                    return Err(format!("Refusing to listen on {}, the control server only listens on loopback addresses", tcp_address));
+                }
+                let listener = TcpListener::bind(&resolved[..]).map_err(|e| format!("Failed listening on {} : {}", tcp_address, e))?;
+                listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+                Listener::Tcp(listener)
+            },
+            #[cfg(unix)]
+            Some(("unix", socket_path)) => {
+                // A socket left by a previous run would fail the bind
+                if std::fs::metadata(socket_path).map(|metadata| std::os::unix::fs::FileTypeExt::is_socket(&metadata.file_type())).unwrap_or(false) {
+                    std::fs::remove_file(socket_path).map_err(|e| format!("Failed removing {} : {}", socket_path, e))?;
+                }
+                let listener = UnixListener::bind(socket_path).map_err(|e| format!("Failed listening on {} : {}", socket_path, e))?;
+                listener.set_nonblocking(true).map_err(|e| e.to_string())?;
+                Listener::Unix(listener, socket_path.to_string())
+            },
+            _ => return Err(format!("Invalid control address \"{}\", expected tcp:<host>:<port> or unix:<path>", address))
+        };
+
+        info!("RPC | Listening on {}", address);
+        return Ok(RpcServer{listener: listener, connections: Vec::new(), keypad: Keypad::new(), setup: setup,
+            instructions_per_frame: config.cpu.instructions_per_frame, quit_requested: false, search: None});
+    }
+
+    pub fn keypad(&self) -> Keypad {
+        return self.keypad;
+    }
+
+    // Set by the quit method, the frontend stops after the frame
+    pub fn quit_requested(&self) -> bool {
+        return self.quit_requested;
+    }
+
+    // Accepts new clients and answers every complete request, called once per frame
+    pub fn poll(&mut self, cpu: &mut CPU, paused: &mut bool, cheats: &mut CheatList) {
+        self.accept_connections();
+
+        for index in 0..self.connections.len() {
+            self.read_connection(index);
+            while let Some(line_end) = self.connections[index].pending_input.iter().position(|byte| *byte == b'\n') {
+                let line: Vec<u8> = self.connections[index].pending_input.drain(..=line_end).collect();
+                let line = String::from_utf8_lossy(&line);
+                if line.trim().is_empty() {
+                    continue;
+                }
+
+                if let Some(response) = self.handle_line(line.trim(), cpu, paused, cheats) {
+                    let connection = &mut self.connections[index];
+                    connection.pending_output.extend_from_slice(response.to_string().as_bytes());
+                    connection.pending_output.push(b'\n');
+                }
+            }
+            self.write_connection(index);
+        }
+
+        let connection_count = self.connections.len();
+        self.connections.retain(|connection| !connection.closed);
+        if self.connections.len() < connection_count {
+            debug!("RPC | {} clients disconnected", connection_count - self.connections.len());
+        }
+    }
+
+    fn accept_connections(&mut self) {
+        loop {
+            let accepted: std::io::Result<Box<dyn RpcStream>> = match &self.listener {
+                Listener::Tcp(listener) => listener.accept().and_then(|(stream, _)| {
+                    stream.set_nonblocking(true)?;
+                    stream.set_nodelay(true)?;
+                    return Ok(Box::new(stream) as Box<dyn RpcStream>);
+                }),
+                #[cfg(unix)]
+                Listener::Unix(listener, _) => listener.accept().and_then(|(stream, _)| {
+                    stream.set_nonblocking(true)?;
+                    return Ok(Box::new(stream) as Box<dyn RpcStream>);
+                })
+            };
+
+            match accepted {
+                Ok(stream) => {
+                    info!("RPC | Client connected");
+                    self.connections.push(Connection{stream: stream, pending_input: Vec::new(), pending_output: Vec::new(), closed: false});
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => {
+                    warn!("RPC | Failed accepting a client : {}", e);
+                    return;
+                }
+            }
+        }
+    }
+
+    fn read_connection(&mut self, index: usize) {
+        let connection = &mut self.connections[index];
+        let mut buffer = [0u8; 4096];
+        loop {
+            match connection.stream.read(&mut buffer) {
+                Ok(0) => {
+                    connection.closed = true;
+                    return;
+                },
+                Ok(length) => connection.pending_input.extend_from_slice(&buffer[..length]),
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    debug!("RPC | Failed reading from a client : {}", e);
+                    connection.closed = true;
+                    return;
+                }
+            }
+
+            if connection.pending_input.len() > MAX_REQUEST_LENGTH {
+                warn!("RPC | Disconnecting a client that sent a request longer than {} bytes", MAX_REQUEST_LENGTH);
+                connection.closed = true;
+                return;
+            }
+        }
+    }
+
+    // Responses that don't fit in the socket buffer are finished on the next frames
+    fn write_connection(&mut self, index: usize) {
+        let connection = &mut self.connections[index];
+        while !connection.pending_output.is_empty() {
+            match connection.stream.write(&connection.pending_output) {
+                Ok(0) => {
+                    connection.closed = true;
+                    return;
+                },
+                Ok(length) => {
+                    connection.pending_output.drain(..length);
+                },
+                Err(e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => {
+                    debug!("RPC | Failed writing to a client : {}", e);
+                    connection.closed = true;
+                    return;
+                }
+            }
+        }
+    }
+
+    // The response to a request, notifications (requests without an id) get none
+    fn handle_line(&mut self, line: &str, cpu: &mut CPU, paused: &mut bool, cheats: &mut CheatList) -> Option<Value> {
+        let request: Value = match serde_json::from_str(line) {
+            Ok(request) => request,
+            Err(e) => return Some(json!({"jsonrpc": "2.0", "id": null, "error": {"code": PARSE_ERROR, "message": e.to_string()}}))
+        };
+
+        let id = request.get("id").cloned();
+        let method = match request.get("method").and_then(|method| method.as_str()) {
+            Some(method) if request.get("jsonrpc").and_then(|version| version.as_str()) == Some("2.0") => method,
+            _ => return Some(json!({"jsonrpc": "2.0", "id": id.unwrap_or(Value::Null),
+                "error": {"code": INVALID_REQUEST, "message": "Expected a JSON-RPC 2.0 request"}}))
+        };
+        let result = match request.get("params") {
+            None => self.handle_request(method, &Map::new(), cpu, paused, cheats),
+            Some(Value::Object(params)) => self.handle_request(method, params, cpu, paused, cheats),
+            Some(_) => Err(RpcError::invalid_params(String::from("Params must be an object")))
+        };
+
+        debug!("RPC | {} {}", method, if result.is_ok() { "succeeded" } else { "failed" });
+        let id = id?;
+        match result {
+            Ok(result) => return Some(json!({"jsonrpc": "2.0", "id": id, "result": result})),
+            Err(e) => return Some(json!({"jsonrpc": "2.0", "id": id, "error": {"code": e.code, "message": e.message}}))
+        }
+    }
+
+    fn handle_request(&mut self, method: &str, params: &Map<String, Value>, cpu: &mut CPU, paused: &mut bool, cheats: &mut CheatList) -> Result<Value, RpcError> {
+        match method {
+            "status" => {
+                let framebuffer = cpu.framebuffer();
+                return Ok(json!({"paused": *paused, "pc": cpu.program_counter(), "width": framebuffer.width(),
+                    "height": framebuffer.height(), "waiting_for_key": cpu.is_waiting_for_key(),
+                    "sound_active": cpu.is_sound_active(), "instructions_per_frame": self.instructions_per_frame}));
+            },
+            "pause" | "resume" => {
+                *paused = method == "pause";
+                info!("RPC | Emulation {}", if *paused { "paused" } else { "resumed" });
+                return Ok(json!({"paused": *paused}));
+            },
+            "load_rom" => {
+                let rom_content = match params.get("path").and_then(|path| path.as_str()) {
+                    Some(rom_path) => std::fs::read(rom_path).map_err(|e| Chip8Error::io_error(rom_path, e))?,
+                    None => param_bytes(params, "data")?
+                };
+                let new_cpu = self.setup.new_cpu(&rom_content)?;
+                *cheats = CheatList::for_rom(&romdb::rom_sha1(&rom_content), self.setup.quirks.memory_size).map_err(RpcError::emulator)?;
+                self.search = None;
+                *cpu = new_cpu;
+                info!("RPC | Loaded a rom of {} bytes", rom_content.len());
+                return Ok(json!({"size": rom_content.len()}));
+            },
+            "step_instructions" => {
+                let keypad = self.keypad;
+                let executed = run_instructions(cpu, &keypad, param_count(params, MAX_STEP_INSTRUCTIONS)?)?;
+                return Ok(json!({"executed": executed, "pc": cpu.program_counter()}));
+            },
+            "step_frames" => {
+                let keypad = self.keypad;
+                let frames = param_count(params, MAX_STEP_FRAMES)?;
+                for _ in 0..frames {
+                    cheats.apply(cpu);
+                    run_instructions(cpu, &keypad, self.instructions_per_frame as u64)?;
+                    cpu.tick_timers();
+                }
+                return Ok(json!({"frames": frames, "pc": cpu.program_counter()}));
+            },
+            "read_memory" => {
+                let address = param_u64(params, "address", None)?;
+                let length = param_u64(params, "length", Some(1))? as usize;
+                if length > MAX_READ_LENGTH {
+                    return Err(RpcError::invalid_params(format!("Reads are limited to {} bytes", MAX_READ_LENGTH)));
+                }
+                let bytes = memory_range(address, length, self.setup.quirks.memory_size)?
+                    .map(|address| cpu.peek_memory(address)).collect::<Result<Vec<u8>, Chip8Error>>()?;
+                return Ok(json!({"address": address, "data": hex_encode(&bytes)}));
+            },
+            "write_memory" => {
+                let address = param_u64(params, "address", None)?;
+                let bytes = param_bytes(params, "data")?;
+                modify_state(cpu, |state| {
+                    let range = memory_range(address, bytes.len(), state.memory.len())?;
+                    state.memory[range].copy_from_slice(&bytes);
+                    return Ok(());
+                })?;
+                return Ok(json!({"written": bytes.len()}));
+            },
+            "get_registers" => return Ok(registers(cpu)),
+            "set_registers" => {
+                let registers_param = match params.get("v") {
+                    Some(Value::Object(registers)) => registers.iter()
+                        .map(|(register, value)| {
+                            let register = usize::from_str_radix(register.trim_start_matches('v').trim_start_matches('V'), 16).ok().filter(|register| *register < 16);
+                            let value = value.as_u64().filter(|value| *value <= 0xFF);
+                            match (register, value) {
+                                (Some(register), Some(value)) => Ok((register, value as u8)),
+                                _ => Err(RpcError::invalid_params(String::from("\"v\" maps registers 0 to F to bytes")))
+                            }
+                        })
+                        .collect::<Result<Vec<_>, RpcError>>()?,
+                    Some(_) => return Err(RpcError::invalid_params(String::from("\"v\" maps registers 0 to F to bytes"))),
+                    None => Vec::new()
+                };
+                let (index_register, program_counter) = (param_u16(params, "i")?, param_u16(params, "pc")?);
+                let (delay_timer, sound_timer) = (param_u8(params, "delay_timer")?, param_u8(params, "sound_timer")?);
+
+                modify_state(cpu, |state| {
+                    for (register, value) in registers_param {
+                        state.registers[register] = value;
+                    }
+                    state.index_register = index_register.unwrap_or(state.index_register);
+                    state.program_counter = program_counter.unwrap_or(state.program_counter);
+                    state.delay_timer = delay_timer.unwrap_or(state.delay_timer);
+                    state.sound_timer = sound_timer.unwrap_or(state.sound_timer);
+                    return Ok(());
+                })?;
+                return Ok(registers(cpu));
+            },
+            "set_keys" => {
+                let keys = match params.get("keys") {
+                    Some(Value::Array(keys)) => keys.iter().map(|key| key.as_u64().filter(|key| *key < 16).map(|key| key as u8))
+                        .collect::<Option<Vec<u8>>>()
+                        .ok_or_else(|| RpcError::invalid_params(String::from("\"keys\" must hold keys 0 to 15")))?,
+                    _ => return Err(RpcError::invalid_params(String::from("Missing \"keys\"")))
+                };
+                self.keypad = Keypad::new();
+                for key in &keys {
+                    self.keypad.set_pressed(*key, true);
+                }
+                return Ok(json!({"keys": keys}));
+            },
+            "get_framebuffer" => {
+                let framebuffer = cpu.framebuffer();
+                let rows: Vec<String> = framebuffer.pixels().chunks(framebuffer.width())
+                    .map(|row| row.iter().map(|pixel| if *pixel { '1' } else { '0' }).collect())
+                    .collect();
+                return Ok(json!({"width": framebuffer.width(), "height": framebuffer.height(), "rows": rows}));
+            },
+            "save_state" => return Ok(json!({"state": hex_encode(&cpu.save_state().to_bytes())})),
+            "load_state" => {
+                let state = match params.get("state").and_then(|state| state.as_str()) {
+                    Some(state) => MachineState::from_bytes(&hex_decode(state)?)?,
+                    None => return Err(RpcError::invalid_params(String::from("Missing \"state\"")))
+                };
+                cpu.load_state(&state)?;
+                return Ok(json!({"pc": cpu.program_counter()}));
+            },
+            "search_start" => {
+                let search = MemorySearch::new(&cpu.save_state().memory);
+                let candidates = search.candidates().len();
+                self.search = Some(search);
+                return Ok(json!({"candidates": candidates}));
+            },
+            "search_narrow" => {
+                let condition = params.get("condition").and_then(|condition| condition.as_str())
+                    .ok_or_else(|| RpcError::invalid_params(String::from("Missing \"condition\"")))?;
+                let condition = SearchCondition::parse(condition, param_u8(params, "value")?).map_err(RpcError::invalid_params)?;
+                let search = self.search.as_mut().ok_or_else(|| RpcError::emulator(String::from("No search was started")))?;
+                let candidates = search.narrow(&cpu.save_state().memory, condition);
+                return Ok(json!({"candidates": candidates}));
+            },
+            "search_results" => {
+                let search = self.search.as_ref().ok_or_else(|| RpcError::emulator(String::from("No search was started")))?;
+                let limit = param_u64(params, "limit", Some(DEFAULT_SEARCH_RESULTS))? as usize;
+                let results = search.candidates().iter().take(limit)
+                    .map(|address| Ok(json!({"address": address, "value": cpu.peek_memory(*address)?, "previous": search.previous_value(*address)})))
+                    .collect::<Result<Vec<Value>, Chip8Error>>()?;
+                return Ok(json!({"candidates": search.candidates().len(), "results": results}));
+            },
+            "list_cheats" => return Ok(cheats_json(cheats)),
+            "add_cheat" => {
+                let codes = params.get("codes").and_then(|codes| codes.as_str())
+                    .ok_or_else(|| RpcError::invalid_params(String::from("Missing \"codes\"")))?;
+                let name = params.get("name").and_then(|name| name.as_str()).unwrap_or(codes);
+                let cheat = Cheat::parse(name, codes).map_err(RpcError::invalid_params)?;
+                cheat.check_addresses(self.setup.quirks.memory_size).map_err(RpcError::invalid_params)?;
+                let index = cheats.add(cheat);
+                info!("RPC | Added cheat \"{}\"", name);
+                return Ok(json!({"index": index}));
+            },
+            "freeze" => {
+                let address = param_u16(params, "address")?.ok_or_else(|| RpcError::invalid_params(String::from("Missing \"address\"")))?;
+                let value = param_u8(params, "value")?.ok_or_else(|| RpcError::invalid_params(String::from("Missing \"value\"")))?;
+                let cheat = Cheat::freeze(address as usize, value);
+                cheat.check_addresses(self.setup.quirks.memory_size).map_err(RpcError::invalid_params)?;
+                let index = cheats.add(cheat);
+                return Ok(json!({"index": index}));
+            },
+            "remove_cheat" => {
+                cheats.remove(param_u64(params, "index", None)? as usize).map_err(RpcError::invalid_params)?;
+                return Ok(cheats_json(cheats));
+            },
+            "enable_cheat" => {
+                let enabled = params.get("enabled").map_or(Some(true), |enabled| enabled.as_bool())
+                    .ok_or_else(|| RpcError::invalid_params(String::from("\"enabled\" must be a boolean")))?;
+                cheats.set_enabled(param_u64(params, "index", None)? as usize, enabled).map_err(RpcError::invalid_params)?;
+                return Ok(cheats_json(cheats));
+            },
+            "save_cheats" => {
+                let path = cheats.save().map_err(RpcError::emulator)?;
+                info!("RPC | Saved cheats to {}", path.display());
+                return Ok(json!({"path": path.display().to_string()}));
+            },
+            "quit" => {
+                info!("RPC | Got quit request");
+                self.quit_requested = true;
+                return Ok(Value::Null);
+            },
+            _ => return Err(RpcError{code: METHOD_NOT_FOUND, message: format!("Unknown method \"{}\"", method)})
+        }
+    }
+}
+
+impl Drop for RpcServer {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Listener::Unix(_, socket_path) = &self.listener {
+            let _ = std::fs::remove_file(socket_path);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bind(address: &str) -> Result<RpcServer, String> {
+        let config = Config::default();
+        let setup = MachineSetup::from_config(&config).unwrap();
+        return RpcServer::bind(address, &config, setup);
+    }
+
+    #[test]
+    fn tcp_server_only_listens_on_loopback() {
+        assert!(bind("tcp:0.0.0.0:0").is_err());
+        assert!(bind("tcp:[::]:0").is_err());
+        assert!(bind("tcp:127.0.0.1:0").is_ok());
+    }
+}
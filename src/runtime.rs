@@ -0,0 +1,162 @@
+use crate::audio::Beeper;
+use crate::config::{self, Config};
+use crate::consts;
+use crate::cpu::CPU;
+use crate::display::Display;
+use crate::effects::FrameRenderer;
+use crate::errors::Chip8Error;
+use crate::input::InputMapper;
+use crate::keypad::Keypad;
+use crate::memory::Memory;
+use crate::palette::Palette;
+use crate::quirks::Quirks;
+use crate::romdb::{self, RomDatabase};
+use crate::upscale::Upscaler;
+
+use sdl2::event::Event;
+
+use std::process;
+use std::thread;
+use std::time::{Duration, Instant};
+
+// The native code of a recompiled rom, runs the block at the program counter and returns how many instructions it ran.
+// None when there is no block there, like after a computed jump into the middle of one, the interpreter runs it instead
+pub type NativeProgram = fn(&mut CPU, &Keypad) -> Result<Option<u32>, Chip8Error>;
+
+const DEFAULT_HEADLESS_INSTRUCTIONS: u64 = 100_000_000;
+
+// Blocks run whole, so a frame runs at least instructions_per_frame instructions, unless it ends waiting for a key.
+// Returns how many instructions ran
+pub fn run_frame(cpu: &mut CPU, keypad: &Keypad, program: NativeProgram, instructions_per_frame: u32) -> Result<u32, Chip8Error> {
+    let mut executed = 0;
+    while executed < instructions_per_frame {
+        if cpu.is_waiting_for_key() {
+            cpu.step(keypad, 1)?;
+            if cpu.is_waiting_for_key() {
+                break;
+            }
+            executed += 1;
+            continue;
+        }
+
+        executed += match program(cpu, keypad)? {
+            Some(count) => count,
+            None => cpu.step(keypad, 1)?
+        };
+    }
+
+    cpu.tick_timers();
+    return Ok(executed);
+}
+
+fn run_headless(mut cpu: CPU, program: NativeProgram, instructions: u64, instructions_per_frame: u32) -> Result<(), Chip8Error> {
+    let keypad = Keypad::new();
+    let start_time = Instant::now();
+    let mut frames: u64 = 0;
+    let mut executed: u64 = 0;
+    while executed < instructions {
+        executed += run_frame(&mut cpu, &keypad, program, instructions_per_frame)? as u64;
+        frames += 1;
+
+        // No key is ever pressed without a window
+        if cpu.is_waiting_for_key() {
+            println!("Stopped waiting for a key");
+            break;
+        }
+    }
+    let elapsed = start_time.elapsed().as_secs_f64();
+
+    println!("Ran {} instructions in {} frames in {:.3}s, {:.1} frames per second", executed, frames, elapsed, frames as f64 / elapsed);
+    println!("{}", cpu.machine_context(cpu.program_counter()));
+    return Ok(());
+}
+
+fn run_window(title: &str, mut cpu: CPU, program: NativeProgram, config: &Config) -> Result<(), Chip8Error> {
+    let scale_factor = config.display.scale;
+    let sdl_context = sdl2::init().map_err(Chip8Error::FrontendError)?;
+    let video_subsystem = sdl_context.video().map_err(Chip8Error::FrontendError)?;
+    let window = video_subsystem.window(title, consts::DISPLAY_WIDTH as u32 * scale_factor, consts::DISPLAY_HEIGHT as u32 * scale_factor)
+        .resizable()
+        .build()
+        .map_err(|e| Chip8Error::FrontendError(e.to_string()))?;
+    let canvas = window.into_canvas().build().map_err(|e| Chip8Error::FrontendError(e.to_string()))?;
+
+    let texture_creator = canvas.texture_creator();
+    let mut display = Display::new(canvas, &texture_creator, config.display.scaling);
+    let palette = Palette::from_config(&config.palette).map_err(Chip8Error::ConfigError)?;
+    let upscaler = Upscaler::from_name(&config.display.upscaler).map_err(Chip8Error::ConfigError)?;
+    let mut frame_renderer = FrameRenderer::new(palette, config.effects.clone(), upscaler, scale_factor as usize);
+
+    let mut event_pump = sdl_context.event_pump().map_err(Chip8Error::FrontendError)?;
+    let controller_subsystem = sdl_context.game_controller().map_err(Chip8Error::FrontendError)?;
+    let mut input_mapper = InputMapper::new(config, controller_subsystem).map_err(Chip8Error::ConfigError)?;
+    let mut beeper = if config.audio.enabled { Beeper::new(&config.audio).ok() } else { None };
+
+    let frame_duration = Duration::from_secs_f32(consts::TIMER_TICK_MILLIS / 1000.0);
+    'main_loop: loop {
+        let frame_start_time = Instant::now();
+        for event in event_pump.poll_iter() {
+            if let Event::Quit {..} = event {
+                break 'main_loop;
+            }
+            input_mapper.handle_event(&event);
+        }
+
+        let keypad = input_mapper.keypad_state(&event_pump.keyboard_state());
+        run_frame(&mut cpu, &keypad, program, config.cpu.instructions_per_frame)?;
+
+        if let Some(beeper) = beeper.as_mut() {
+            beeper.set_active(cpu.is_sound_active());
+        }
+
+        let layout = display.layout(cpu.framebuffer().width(), cpu.framebuffer().height()).map_err(Chip8Error::FrontendError)?;
+        frame_renderer.set_scale(layout.pixel_scale);
+        let (frame, frame_width, frame_height) = frame_renderer.render(cpu.framebuffer());
+        display.present(&frame, frame_width, frame_height, &layout).map_err(Chip8Error::FrontendError)?;
+
+        let frame_elapsed_time = frame_start_time.elapsed();
+        if frame_elapsed_time < frame_duration {
+            thread::sleep(frame_duration - frame_elapsed_time);
+        }
+    }
+
+    return Ok(());
+}
+
+fn run(title: &str, rom_content: &[u8], quirks: Quirks, program: NativeProgram) -> Result<(), Chip8Error> {
+    // Display, input, audio and speed settings come from the usual config files, the rom database and the rom overrides
+    let rom_sha1 = romdb::rom_sha1(rom_content);
+    let mut rom_layers: Vec<toml::Value> = Vec::new();
+    if let Some(matched_rom) = RomDatabase::load().map_err(Chip8Error::ConfigError)?.lookup(&rom_sha1) {
+        rom_layers.push(matched_rom.config_layer());
+    }
+    if let Some(rom_override) = config::load_rom_override(&rom_sha1).map_err(Chip8Error::ConfigError)? {
+        rom_layers.push(rom_override);
+    }
+    let config = Config::load(None, &rom_layers).map_err(Chip8Error::ConfigError)?;
+    config.validate().map_err(Chip8Error::ConfigError)?;
+
+    let memory = Memory::new_from_rom(rom_content.to_vec(), quirks.memory_size, quirks.address_policy)?;
+    let cpu = CPU::new(memory, quirks);
+
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    match args.first().map(|arg| arg.as_str()) {
+        Some("--headless") => {
+            let instructions = match args.get(1) {
+                Some(count) => count.parse::<u64>().map_err(|_| Chip8Error::InvalidArgument(format!("Invalid instruction count : {}", count)))?,
+                None => DEFAULT_HEADLESS_INSTRUCTIONS
+            };
+            return run_headless(cpu, program, instructions, config.cpu.instructions_per_frame);
+        },
+        Some(arg) => return Err(Chip8Error::InvalidArgument(format!("Unknown argument {}, expected --headless [instructions]", arg))),
+        None => return run_window(title, cpu, program, &config)
+    }
+}
+
+// Entry point of recompiled roms, opens a window or runs headless with --headless [instructions]
+pub fn main(title: &str, rom_content: &[u8], quirks: Quirks, program: NativeProgram) {
+    if let Err(error) = run(title, rom_content, quirks, program) {
+        eprintln!("Stopping due to {}", error);
+        process::exit(error.exit_code());
+    }
+}
@@ -0,0 +1,456 @@
+use crate::cpu::CPU;
+use crate::errors::Chip8Error;
+use crate::keypad::Keypad;
+use crate::memory::{AddressPolicy, Memory};
+use crate::quirks::Quirks;
+use crate::watchpoints::{AccessKind, MemoryEvent, WatchAction, Watchpoint};
+
+use rhai::{Dynamic, Engine, EvalAltResult, FnPtr, FuncArgs, AST};
+
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::rc::Rc;
+
+// A hook running longer than this is stopped, so that a script stuck in a loop doesn't freeze the emulator
+const MAX_HOOK_OPERATIONS: u64 = 1_000_000;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+// A hook and the index of the script that registered it
+#[derive(Clone)]
+struct Hook {
+    id: usize,
+    script: usize,
+    function: FnPtr,
+}
+
+#[derive(Default)]
+struct Hooks {
+    frame: Vec<Hook>,
+    execute: HashMap<u16, Vec<Hook>>,
+    write: Vec<(usize, usize, Hook)>,
+    sound_start: Vec<Hook>,
+    key_wait: Vec<Hook>,
+    // Set while the top level of a script runs, its hooks are registered to it
+    loading_script: usize,
+    registered_count: usize,
+}
+
+impl Hooks {
+    fn new_hook(&mut self, function: FnPtr) -> Hook {
+        self.registered_count += 1;
+        return Hook{id: self.registered_count, script: self.loading_script, function: function};
+    }
+}
+
+// What the scripts see of the emulator, the cpu of the emulator is swapped in while the hooks run
+struct ScriptMachine {
+    cpu: CPU,
+    is_cpu_lent: bool,
+    keypad: Keypad,
+    frame_count: u64,
+    pause_requested: bool,
+    hud: String,
+}
+
+// Rhai scripts hooked to emulator events, see the README for the functions they can call
+pub struct ScriptHost {
+    engine: Engine,
+    scripts: Vec<(String, AST)>,
+    hooks: Rc<RefCell<Hooks>>,
+    machine: Rc<RefCell<ScriptMachine>>,
+    was_sound_active: bool,
+    was_waiting_for_key: bool,
+}
+
+fn to_index(value: i64, limit: usize, name: &str) -> ScriptResult<usize> {
+    if value < 0 || value as usize >= limit {
+        return Err(format!("{} {:#x} is out of range, expected 0 to {:#x}", name, value, limit - 1).into());
+    }
+    return Ok(value as usize);
+}
+
+fn to_byte(value: i64) -> ScriptResult<u8> {
+    return to_index(value, 0x100, "Value").map(|value| value as u8);
+}
+
+fn with_cpu<T>(machine: &Rc<RefCell<ScriptMachine>>, action: impl FnOnce(&mut CPU) -> ScriptResult<T>) -> ScriptResult<T> {
+    let mut machine = machine.borrow_mut();
+    if !machine.is_cpu_lent {
+        return Err("The machine can only be accessed from hooks".into());
+    }
+    return action(&mut machine.cpu);
+}
+
+fn register_hook_functions(engine: &mut Engine, hooks: &Rc<RefCell<Hooks>>) {
+    let registered_hooks = hooks.clone();
+    engine.register_fn("on_frame", move |function: FnPtr| {
+        let mut hooks = registered_hooks.borrow_mut();
+        let hook = hooks.new_hook(function);
+        hooks.frame.push(hook);
+    });
+
+    let registered_hooks = hooks.clone();
+    engine.register_fn("on_exec", move |address: i64, function: FnPtr| -> ScriptResult<()> {
+        let address = to_index(address, 0x10000, "Address")? as u16;
+        let mut hooks = registered_hooks.borrow_mut();
+        let hook = hooks.new_hook(function);
+        hooks.execute.entry(address).or_default().push(hook);
+        return Ok(());
+    });
+
+    let registered_hooks = hooks.clone();
+    let register_write_hook = move |start: i64, end: i64, function: FnPtr| -> ScriptResult<()> {
+        let (start, end) = (to_index(start, 0x10000, "Address")?, to_index(end, 0x10000, "Address")?);
+        if end < start {
+            return Err(format!("Write hook range {:#06x}-{:#06x} ends before it starts", start, end).into());
+        }
+        let mut hooks = registered_hooks.borrow_mut();
+        let hook = hooks.new_hook(function);
+        hooks.write.push((start, end, hook));
+        return Ok(());
+    };
+    let register_single_write_hook = register_write_hook.clone();
+    engine.register_fn("on_write", register_write_hook);
+    engine.register_fn("on_write", move |address: i64, function: FnPtr| register_single_write_hook(address, address, function));
+
+    let registered_hooks = hooks.clone();
+    engine.register_fn("on_sound_start", move |function: FnPtr| {
+        let mut hooks = registered_hooks.borrow_mut();
+        let hook = hooks.new_hook(function);
+        hooks.sound_start.push(hook);
+    });
+
+    let registered_hooks = hooks.clone();
+    engine.register_fn("on_key_wait", move |function: FnPtr| {
+        let mut hooks = registered_hooks.borrow_mut();
+        let hook = hooks.new_hook(function);
+        hooks.key_wait.push(hook);
+    });
+}
+
+fn register_machine_functions(engine: &mut Engine, machine: &Rc<RefCell<ScriptMachine>>) {
+    let shared = machine.clone();
+    engine.register_fn("v", move |register: i64| -> ScriptResult<i64> {
+        let register = to_index(register, 16, "Register")?;
+        return with_cpu(&shared, |cpu| Ok(cpu.v(register) as i64));
+    });
+    let shared = machine.clone();
+    engine.register_fn("set_v", move |register: i64, value: i64| -> ScriptResult<()> {
+        let (register, value) = (to_index(register, 16, "Register")?, to_byte(value)?);
+        return with_cpu(&shared, |cpu| {
+            cpu.set_v(register, value);
+            return Ok(());
+        });
+    });
+
+    let shared = machine.clone();
+    engine.register_fn("i", move || with_cpu(&shared, |cpu| Ok(cpu.index_register() as i64)));
+    let shared = machine.clone();
+    engine.register_fn("set_i", move |value: i64| -> ScriptResult<()> {
+        let value = to_index(value, 0x10000, "Address")? as u16;
+        return with_cpu(&shared, |cpu| {
+            cpu.set_index_register(value);
+            return Ok(());
+        });
+    });
+
+    let shared = machine.clone();
+    engine.register_fn("pc", move || with_cpu(&shared, |cpu| Ok(cpu.program_counter() as i64)));
+    let shared = machine.clone();
+    engine.register_fn("set_pc", move |value: i64| -> ScriptResult<()> {
+        let value = to_index(value, 0x10000, "Address")? as u16;
+        return with_cpu(&shared, |cpu| {
+            cpu.set_program_counter(value);
+            return Ok(());
+        });
+    });
+
+    let shared = machine.clone();
+    engine.register_fn("peek", move |address: i64| -> ScriptResult<i64> {
+        return with_cpu(&shared, |cpu| {
+            let address = to_index(address, cpu.memory_size(), "Address")?;
+            return cpu.peek_memory(address).map(|value| value as i64).map_err(|e| e.to_string().into());
+        });
+    });
+    // Pokes are seen by the watchpoints and the recompiler like the writes of the rom
+    let shared = machine.clone();
+    engine.register_fn("poke", move |address: i64, value: i64| -> ScriptResult<()> {
+        let value = to_byte(value)?;
+        return with_cpu(&shared, |cpu| {
+            let address = to_index(address, cpu.memory_size(), "Address")?;
+            return cpu.write_memory(address, value).map_err(|e| e.to_string().into());
+        });
+    });
+
+    let shared = machine.clone();
+    engine.register_fn("delay_timer", move || with_cpu(&shared, |cpu| Ok(cpu.timers().0 as i64)));
+    let shared = machine.clone();
+    engine.register_fn("set_delay_timer", move |value: i64| -> ScriptResult<()> {
+        let value = to_byte(value)?;
+        return with_cpu(&shared, |cpu| {
+            cpu.set_delay_timer(value);
+            return Ok(());
+        });
+    });
+    let shared = machine.clone();
+    engine.register_fn("sound_timer", move || with_cpu(&shared, |cpu| Ok(cpu.timers().1 as i64)));
+    let shared = machine.clone();
+    engine.register_fn("set_sound_timer", move |value: i64| -> ScriptResult<()> {
+        let value = to_byte(value)?;
+        return with_cpu(&shared, |cpu| {
+            cpu.set_sound_timer(value);
+            return Ok(());
+        });
+    });
+
+    let shared = machine.clone();
+    engine.register_fn("screen_width", move || with_cpu(&shared, |cpu| Ok(cpu.framebuffer().width() as i64)));
+    let shared = machine.clone();
+    engine.register_fn("screen_height", move || with_cpu(&shared, |cpu| Ok(cpu.framebuffer().height() as i64)));
+    let shared = machine.clone();
+    engine.register_fn("pixel", move |x: i64, y: i64| -> ScriptResult<bool> {
+        return with_cpu(&shared, |cpu| {
+            let framebuffer = cpu.framebuffer();
+            let (x, y) = (to_index(x, framebuffer.width(), "X")?, to_index(y, framebuffer.height(), "Y")?);
+            return Ok(framebuffer.get_pixel(x, y));
+        });
+    });
+
+    // Keys pressed by scripts stay pressed until they are released, on top of the keys of the frontend
+    let shared = machine.clone();
+    engine.register_fn("press", move |key: i64| -> ScriptResult<()> {
+        let key = to_index(key, 16, "Key")? as u8;
+        shared.borrow_mut().keypad.set_pressed(key, true);
+        return Ok(());
+    });
+    let shared = machine.clone();
+    engine.register_fn("release", move |key: i64| -> ScriptResult<()> {
+        let key = to_index(key, 16, "Key")? as u8;
+        shared.borrow_mut().keypad.set_pressed(key, false);
+        return Ok(());
+    });
+    let shared = machine.clone();
+    engine.register_fn("is_pressed", move |key: i64| -> ScriptResult<bool> {
+        let key = to_index(key, 16, "Key")? as u8;
+        return Ok(shared.borrow().keypad.is_pressed(key));
+    });
+
+    let shared = machine.clone();
+    engine.register_fn("frame_count", move || shared.borrow().frame_count as i64);
+    let shared = machine.clone();
+    engine.register_fn("pause", move || shared.borrow_mut().pause_requested = true);
+    let shared = machine.clone();
+    engine.register_fn("hud", move |text: &str| shared.borrow_mut().hud = text.to_string());
+}
+
+impl ScriptHost {
+    // Compiles the scripts and runs their top level, which registers their hooks
+    pub fn load(script_paths: &[String]) -> Result<ScriptHost, Chip8Error> {
+        let hooks = Rc::new(RefCell::new(Hooks::default()));
+        // Holds the place of the emulator's cpu between hooks, without memory it costs next to nothing
+        let placeholder_cpu = CPU::new(Memory::new(0, AddressPolicy::Wrap), Quirks::default());
+        let machine = Rc::new(RefCell::new(ScriptMachine{cpu: placeholder_cpu, is_cpu_lent: false, keypad: Keypad::new(), frame_count: 0,
+            pause_requested: false, hud: String::new()}));
+
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_HOOK_OPERATIONS);
+        engine.on_print(|text| info!("SCRIPT | {}", text));
+        engine.on_debug(|text, _, position| debug!("SCRIPT | {} : {}", position, text));
+        register_hook_functions(&mut engine, &hooks);
+        register_machine_functions(&mut engine, &machine);
+
+        let mut scripts = Vec::new();
+        for (index, script_path) in script_paths.iter().enumerate() {
+            let script = std::fs::read_to_string(script_path).map_err(|e| Chip8Error::io_error(script_path, e))?;
+            let mut ast = engine.compile(&script).map_err(|e| Chip8Error::ConfigError(format!("Failed compiling {} : {}", script_path, e)))?;
+            ast.set_source(script_path.as_str());
+
+            hooks.borrow_mut().loading_script = index;
+            engine.run_ast(&ast).map_err(|e| Chip8Error::ConfigError(format!("Failed running {} : {}", script_path, e)))?;
+            info!("SCRIPT | Loaded {}", script_path);
+            scripts.push((script_path.clone(), ast));
+        }
+
+        return Ok(ScriptHost{engine: engine, scripts: scripts, hooks: hooks, machine: machine, was_sound_active: false, was_waiting_for_key: false});
+    }
+
+    // Write hooks are watchpoints, to be added to the memory of the machine they run on
+    pub fn watchpoints(&self) -> Vec<Watchpoint> {
+        return self.hooks.borrow().write.iter()
+            .map(|(start, end, _)| Watchpoint{start: *start, end: *end, read: false, write: true, execute: false, action: WatchAction::Script})
+            .collect();
+    }
+
+    // Execution and write hooks are checked between instructions, the recompiler runs whole blocks instead
+    pub fn needs_interpreter(&self) -> bool {
+        let hooks = self.hooks.borrow();
+        return !hooks.execute.is_empty() || !hooks.write.is_empty();
+    }
+
+    pub fn keypad(&self) -> Keypad {
+        return self.machine.borrow().keypad;
+    }
+
+    pub fn hud(&self) -> String {
+        return self.machine.borrow().hud.clone();
+    }
+
+    pub fn take_pause_request(&mut self) -> bool {
+        return std::mem::take(&mut self.machine.borrow_mut().pause_requested);
+    }
+
+    // Swaps the cpu into the machine of the scripts, and back when they are done with it
+    fn lend_cpu(&mut self, cpu: &mut CPU, is_cpu_lent: bool) {
+        let mut machine = self.machine.borrow_mut();
+        std::mem::swap(&mut machine.cpu, cpu);
+        machine.is_cpu_lent = is_cpu_lent;
+    }
+
+    // The cpu is lent to the hooks while they run.
+    // A failing hook is logged and removed, the emulation continues without it.
+    fn run_hooks(&mut self, cpu: &mut CPU, hooks: Vec<Hook>, args: impl FuncArgs + Clone) -> Vec<Hook> {
+        if hooks.is_empty() {
+            return Vec::new();
+        }
+
+        self.lend_cpu(cpu, true);
+        let mut failed_hooks = Vec::new();
+        for hook in hooks {
+            let (script_path, ast) = &self.scripts[hook.script];
+            if let Err(e) = hook.function.call::<Dynamic>(&self.engine, ast, args.clone()) {
+                error!("SCRIPT | Removing a hook of {} after it failed : {}", script_path, e);
+                failed_hooks.push(hook);
+            }
+        }
+        self.lend_cpu(cpu, false);
+
+        return failed_hooks;
+    }
+
+    fn remove_hooks(&mut self, failed_hooks: Vec<Hook>) {
+        if failed_hooks.is_empty() {
+            return;
+        }
+
+        let is_failed = |hook: &Hook| failed_hooks.iter().any(|failed_hook| failed_hook.id == hook.id);
+        let mut hooks = self.hooks.borrow_mut();
+        hooks.frame.retain(|hook| !is_failed(hook));
+        hooks.execute.values_mut().for_each(|address_hooks| address_hooks.retain(|hook| !is_failed(hook)));
+        hooks.execute.retain(|_, address_hooks| !address_hooks.is_empty());
+        hooks.write.retain(|(_, _, hook)| !is_failed(hook));
+        hooks.sound_start.retain(|hook| !is_failed(hook));
+        hooks.key_wait.retain(|hook| !is_failed(hook));
+    }
+
+    // Runs the hooks of the instruction about to be executed. While FX0A waits the program counter is already past it,
+    // the next instruction only runs once a key is pressed.
+    pub fn before_instruction(&mut self, cpu: &mut CPU, keypad: &Keypad) {
+        if cpu.is_waiting_for_key() && keypad.first_pressed().is_none() {
+            return;
+        }
+
+        let program_counter = cpu.program_counter();
+        let hooks = match self.hooks.borrow().execute.get(&program_counter) {
+            Some(hooks) => hooks.clone(),
+            None => return
+        };
+
+        let failed_hooks = self.run_hooks(cpu, hooks, (program_counter as i64,));
+        self.remove_hooks(failed_hooks);
+    }
+
+    // Runs the hooks of the writes, sound and key waits of the instructions that just ran
+    pub fn after_instructions(&mut self, cpu: &mut CPU, events: &[(u16, MemoryEvent)]) {
+        let mut failed_hooks = Vec::new();
+        for (_, event) in events {
+            if let MemoryEvent::WatchpointHit{watchpoint, access: AccessKind::Write, address, value} = event {
+                if watchpoint.action != WatchAction::Script {
+                    continue;
+                }
+
+                let hooks: Vec<Hook> = self.hooks.borrow().write.iter()
+                    .filter(|(start, end, _)| address >= start && address <= end)
+                    .map(|(_, _, hook)| hook.clone())
+                    .collect();
+                failed_hooks.extend(self.run_hooks(cpu, hooks, (*address as i64, *value as i64)));
+            }
+        }
+
+        let is_sound_active = cpu.is_sound_active();
+        if is_sound_active && !self.was_sound_active {
+            let hooks = self.hooks.borrow().sound_start.clone();
+            failed_hooks.extend(self.run_hooks(cpu, hooks, ()));
+        }
+        self.was_sound_active = is_sound_active;
+
+        let is_waiting_for_key = cpu.is_waiting_for_key();
+        if is_waiting_for_key && !self.was_waiting_for_key {
+            let hooks = self.hooks.borrow().key_wait.clone();
+            failed_hooks.extend(self.run_hooks(cpu, hooks, ()));
+        }
+        self.was_waiting_for_key = is_waiting_for_key;
+
+        self.remove_hooks(failed_hooks);
+    }
+
+    pub fn on_frame(&mut self, cpu: &mut CPU) {
+        self.machine.borrow_mut().frame_count += 1;
+        let hooks = self.hooks.borrow().frame.clone();
+        let failed_hooks = self.run_hooks(cpu, hooks, ());
+        self.remove_hooks(failed_hooks);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::fs;
+
+    fn load_script(name: &str, script: &str) -> ScriptHost {
+        let script_path = std::env::temp_dir().join(format!("chip8_{}_{}.rhai", name, std::process::id()));
+        fs::write(&script_path, script).unwrap();
+        let scripts = ScriptHost::load(&[script_path.to_string_lossy().into_owned()]);
+        fs::remove_file(&script_path).unwrap();
+        return scripts.unwrap();
+    }
+
+    // Steps one instruction at a time like the frontends do with scripts
+    fn run_frame(cpu: &mut CPU, scripts: &mut ScriptHost, keypad: &Keypad) {
+        let mut executed = 0;
+        while executed < 15 {
+            scripts.before_instruction(cpu, keypad);
+            executed += cpu.step(keypad, 15 - executed).unwrap();
+            let memory_events = cpu.take_memory_events();
+            scripts.after_instructions(cpu, &memory_events);
+        }
+        cpu.tick_timers();
+    }
+
+    #[test]
+    fn exec_hooks_wait_with_the_instruction_for_a_key() {
+        let mut scripts = load_script("exec_hooks_wait", "on_exec(0x202, |address| set_v(2, v(2) + 1));");
+        let rom_content = vec![
+            0xF0, 0x0A, // 0x200: wait for a key in V0
+            0x60, 0x01, // 0x202: V0 = 1
+            0x12, 0x04  // 0x204: jump to 0x204
+        ];
+        let quirks = Quirks::default();
+        let memory = Memory::new_from_rom(rom_content, quirks.memory_size, quirks.address_policy).unwrap();
+        let mut cpu = CPU::new(memory, quirks);
+
+        for _ in 0..3 {
+            run_frame(&mut cpu, &mut scripts, &Keypad::new());
+        }
+        assert!(cpu.is_waiting_for_key());
+        assert_eq!(cpu.v(2), 0);
+
+        let mut keypad = Keypad::new();
+        keypad.set_pressed(5, true);
+        run_frame(&mut cpu, &mut scripts, &keypad);
+        assert!(!cpu.is_waiting_for_key());
+        assert_eq!(cpu.v(0), 1);
+        assert_eq!(cpu.v(2), 1);
+    }
+}
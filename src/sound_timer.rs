@@ -1,32 +1,27 @@
-use crate::consts;
-use std::time::{Duration};
-
-use rodio::{OutputStream, Sink};
-use rodio::source::{SineWave, Source};
-
-
-pub struct SoundTimer {}
+pub struct SoundTimer {
+    timer_value: u8
+}
 
 impl SoundTimer {
     pub fn init_timer() -> SoundTimer {
-        return SoundTimer{};
+        return SoundTimer{timer_value: 0};
     }
     
     pub fn set_value(&mut self, value: u8) {
-        info!("Sound timer value set to {}", value);
+        self.timer_value = value;
+        debug!("Sound timer value set to {}", value);
+    }
 
-        let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-        // let sink = Sink::try_new(&stream_handle).unwrap();
+    pub fn get_value(&self) -> u8 {
+        return self.timer_value;
+    }
+
+    pub fn is_active(&self) -> bool {
+        return self.timer_value > 0;
+    }
 
-        // Add a dummy source of the sake of the example.
-        // let source = SineWave::new(440).take_duration(
-        //     Duration::from_millis((consts::TIMER_TICK_MILLIS * value as f32) as u64)).amplify(1.20);
-        let source = SineWave::new(440).take_duration(Duration::from_secs(5)).amplify(1.20);
-        let play_res = stream_handle.play_raw(source);
-        if play_res.is_err() {
-            warn!("Failed playing sound due to : {}", play_res.unwrap_err());
-        } else {
-            info!("No error while trying to play sound");
-        }
+    // Called once per frame, at 60hz
+    pub fn tick(&mut self) {
+        self.timer_value = self.timer_value.saturating_sub(1);
     }
-}
\ No newline at end of file
+}
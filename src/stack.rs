@@ -1,25 +1,42 @@
 use crate::errors::Chip8Error;
 
 pub struct Stack {
-    stack_vec: Vec::<u16>
+    stack_vec: Vec::<u16>,
+    max_depth: usize
 }
 
 impl Stack {
-    pub fn new() -> Stack {
-        Stack{stack_vec: Vec::<u16>::new()}
+    pub fn new(max_depth: usize) -> Stack {
+        Stack{stack_vec: Vec::<u16>::with_capacity(max_depth), max_depth: max_depth}
     }
 
-    pub fn push(&mut self, value: u16) {
+    pub fn push(&mut self, value: u16) -> Result<(), Chip8Error> {
+        if self.stack_vec.len() >= self.max_depth {
+            return Err(Chip8Error::StackOverflow(self.max_depth));
+        }
+
         self.stack_vec.push(value);
+        return Ok(());
     }
 
-    pub fn pop(&mut self) -> Result<u16, Chip8Error> {
-        let value = self.stack_vec.pop();
+    pub fn depth(&self) -> usize {
+        return self.stack_vec.len();
+    }
 
-        if value.is_none() {
-            return Err(Chip8Error::TriedPoppingEmptyStack);
-        } else {
-            return Ok(value.unwrap());
-        }
+    pub fn max_depth(&self) -> usize {
+        return self.max_depth;
+    }
+
+    // Return addresses from the outermost call to the innermost one
+    pub fn frames(&self) -> &[u16] {
+        return &self.stack_vec;
     }
-}
\ No newline at end of file
+
+    pub fn clear(&mut self) {
+        self.stack_vec.clear();
+    }
+
+    pub fn pop(&mut self) -> Result<u16, Chip8Error> {
+        return self.stack_vec.pop().ok_or(Chip8Error::TriedPoppingEmptyStack);
+    }
+}
@@ -0,0 +1,152 @@
+use crate::consts;
+use crate::errors::Chip8Error;
+
+const STATE_MAGIC: &[u8; 4] = b"C8ST";
+const STATE_VERSION: u8 = 1;
+// The framebuffer is always stored at the largest resolution, so that the size of a state doesn't depend on the mode
+const STATE_PIXEL_BYTES: usize = consts::HIRES_DISPLAY_WIDTH * consts::HIRES_DISPLAY_HEIGHT / 8;
+
+// Everything needed to continue running a rom from where it was saved
+#[derive(Debug, Clone, PartialEq)]
+pub struct MachineState {
+    pub program_counter: u16,
+    pub index_register: u16,
+    pub registers: [u8; 16],
+    // Return addresses from the outermost call, and how many the stack holds
+    pub stack_frames: Vec<u16>,
+    pub stack_depth: usize,
+    pub wait_for_key_register: Option<u8>,
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+    pub display_width: usize,
+    pub display_height: usize,
+    // Row major, display_width * display_height pixels
+    pub pixels: Vec<bool>,
+    pub memory: Vec<u8>,
+}
+
+struct StateReader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    fn take(&mut self, length: usize) -> Result<&'a [u8], Chip8Error> {
+        let end = self.position + length;
+        if end > self.bytes.len() {
+            return Err(Chip8Error::InvalidSaveState(format!("Save state ends after {} bytes", self.bytes.len())));
+        }
+
+        let taken = &self.bytes[self.position..end];
+        self.position = end;
+        return Ok(taken);
+    }
+
+    fn u8(&mut self) -> Result<u8, Chip8Error> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    fn u16(&mut self) -> Result<u16, Chip8Error> {
+        let bytes = self.take(2)?;
+        return Ok(u16::from_be_bytes([bytes[0], bytes[1]]));
+    }
+
+    fn u32(&mut self) -> Result<u32, Chip8Error> {
+        let bytes = self.take(4)?;
+        return Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]));
+    }
+}
+
+impl MachineState {
+    // States of the same machine always have the same size, libretro frontends rely on it for rewinding
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(STATE_MAGIC);
+        bytes.push(STATE_VERSION);
+
+        bytes.extend_from_slice(&self.program_counter.to_be_bytes());
+        bytes.extend_from_slice(&self.index_register.to_be_bytes());
+        bytes.extend_from_slice(&self.registers);
+
+        bytes.push(self.stack_depth as u8);
+        bytes.push(self.stack_frames.len() as u8);
+        for slot in 0..self.stack_depth {
+            bytes.extend_from_slice(&self.stack_frames.get(slot).copied().unwrap_or(0).to_be_bytes());
+        }
+
+        bytes.push(self.wait_for_key_register.is_some() as u8);
+        bytes.push(self.wait_for_key_register.unwrap_or(0));
+        bytes.push(self.delay_timer);
+        bytes.push(self.sound_timer);
+
+        bytes.push(self.display_width as u8);
+        bytes.push(self.display_height as u8);
+        let mut pixel_bytes = vec![0u8; STATE_PIXEL_BYTES];
+        for (index, pixel) in self.pixels.iter().enumerate() {
+            if *pixel {
+                pixel_bytes[index / 8] |= 0x80 >> (index % 8);
+            }
+        }
+        bytes.extend_from_slice(&pixel_bytes);
+
+        bytes.extend_from_slice(&(self.memory.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&self.memory);
+
+        return bytes;
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<MachineState, Chip8Error> {
+        let mut reader = StateReader{bytes: bytes, position: 0};
+        if reader.take(STATE_MAGIC.len())? != STATE_MAGIC {
+            return Err(Chip8Error::InvalidSaveState(String::from("Not a chip8 save state")));
+        }
+        let version = reader.u8()?;
+        if version != STATE_VERSION {
+            return Err(Chip8Error::InvalidSaveState(format!("Unsupported save state version {}, expected {}", version, STATE_VERSION)));
+        }
+
+        let program_counter = reader.u16()?;
+        let index_register = reader.u16()?;
+        let mut registers = [0u8; 16];
+        registers.copy_from_slice(reader.take(16)?);
+
+        let stack_depth = reader.u8()? as usize;
+        let stack_length = reader.u8()? as usize;
+        if stack_length > stack_depth {
+            return Err(Chip8Error::InvalidSaveState(format!("Stack of {} entries is deeper than its {} slots", stack_length, stack_depth)));
+        }
+        let mut stack_frames = Vec::new();
+        for slot in 0..stack_depth {
+            let frame = reader.u16()?;
+            if slot < stack_length {
+                stack_frames.push(frame);
+            }
+        }
+
+        let is_waiting_for_key = reader.u8()? != 0;
+        let wait_for_key_register = reader.u8()?;
+        if is_waiting_for_key && wait_for_key_register >= 16 {
+            return Err(Chip8Error::InvalidSaveState(format!("Waiting for a key in register {}, there are only 16", wait_for_key_register)));
+        }
+        let delay_timer = reader.u8()?;
+        let sound_timer = reader.u8()?;
+
+        let display_width = reader.u8()? as usize;
+        let display_height = reader.u8()? as usize;
+        let resolutions = [(consts::DISPLAY_WIDTH, consts::DISPLAY_HEIGHT), (consts::HIRES_DISPLAY_WIDTH, consts::HIRES_DISPLAY_HEIGHT)];
+        if !resolutions.contains(&(display_width, display_height)) {
+            return Err(Chip8Error::InvalidSaveState(format!("Unsupported display resolution {}x{}", display_width, display_height)));
+        }
+        let pixel_bytes = reader.take(STATE_PIXEL_BYTES)?;
+        let pixels = (0..display_width * display_height).map(|index| pixel_bytes[index / 8] & (0x80 >> (index % 8)) != 0).collect();
+
+        let memory_size = reader.u32()? as usize;
+        let memory = reader.take(memory_size)?.to_vec();
+
+        return Ok(MachineState{program_counter: program_counter, index_register: index_register, registers: registers,
+            stack_frames: stack_frames, stack_depth: stack_depth,
+            wait_for_key_register: if is_waiting_for_key { Some(wait_for_key_register) } else { None },
+            delay_timer: delay_timer, sound_timer: sound_timer, display_width: display_width, display_height: display_height,
+            pixels: pixels, memory: memory});
+    }
+}
@@ -0,0 +1,180 @@
+use crate::flow_graph::FlowGraph;
+use crate::instruction::Instruction;
+use crate::quirks::Quirks;
+
+use std::path::Path;
+
+pub struct GeneratedCrate {
+    pub cargo_toml: String,
+    pub main_rs: String,
+}
+
+// Cargo package names are lowercase letters, digits, - and _
+fn package_name(rom_name: &str) -> String {
+    let stem = Path::new(rom_name).file_stem().map(|stem| stem.to_string_lossy().to_string()).unwrap_or_default();
+    let name: String = stem.chars().map(|c| if c.is_ascii_alphanumeric() { c.to_ascii_lowercase() } else { '_' }).collect();
+    if name.is_empty() || name.starts_with(|c: char| c.is_ascii_digit()) {
+        return format!("rom_{}", name);
+    }
+
+    return name;
+}
+
+fn skip_condition(instruction: &Instruction) -> String {
+    match instruction {
+        Instruction::SkipEqualByte{x, value} => format!("cpu.v({:#x}) == {}", x, value),
+        Instruction::SkipNotEqualByte{x, value} => format!("cpu.v({:#x}) != {}", x, value),
+        Instruction::SkipEqualRegister{x, y} => format!("cpu.v({:#x}) == cpu.v({:#x})", x, y),
+        Instruction::SkipNotEqualRegister{x, y} => format!("cpu.v({:#x}) != cpu.v({:#x})", x, y),
+        Instruction::SkipKeyPressed{x} => format!("keypad.is_pressed(cpu.v({:#x}))", x),
+        Instruction::SkipKeyNotPressed{x} => format!("!keypad.is_pressed(cpu.v({:#x}))", x),
+        _ => String::from("false")
+    }
+}
+
+// The Rust code of an instruction, the quirks are decided here instead of at runtime.
+// Instructions that touch memory, the screen, the timers or the stack go through the interpreter of the cpu
+fn native_code(address: u16, instruction: &Instruction, quirks: &Quirks) -> String {
+    let logic_reset = if quirks.logic_resets_vf { " cpu.set_v(0xf, 0);" } else { "" };
+    match instruction {
+        Instruction::System(_) => String::from("// Machine code routine, ignored"),
+        Instruction::Jump(target) => format!("cpu.set_program_counter({:#05x});", target),
+        Instruction::LoadByte{x, value} => format!("cpu.set_v({:#x}, {});", x, value),
+        Instruction::AddByte{x, value} => format!("cpu.set_v({:#x}, cpu.v({:#x}).wrapping_add({}));", x, x, value),
+        Instruction::Assign{x, y} => format!("cpu.set_v({:#x}, cpu.v({:#x}));", x, y),
+        Instruction::Or{x, y} => format!("cpu.set_v({:#x}, cpu.v({:#x}) | cpu.v({:#x}));{}", x, x, y, logic_reset),
+        Instruction::And{x, y} => format!("cpu.set_v({:#x}, cpu.v({:#x}) & cpu.v({:#x}));{}", x, x, y, logic_reset),
+        Instruction::Xor{x, y} => format!("cpu.set_v({:#x}, cpu.v({:#x}) ^ cpu.v({:#x}));{}", x, x, y, logic_reset),
+        Instruction::AddRegister{x, y} => {
+            format!("{{ let (result, carry) = cpu.v({:#x}).overflowing_add(cpu.v({:#x})); cpu.set_v({:#x}, result); cpu.set_v(0xf, carry as u8); }}", x, y, x)
+        },
+        Instruction::Sub{x, y} => {
+            format!("{{ let (x_value, y_value) = (cpu.v({:#x}), cpu.v({:#x})); cpu.set_v({:#x}, x_value.wrapping_sub(y_value)); cpu.set_v(0xf, (x_value > y_value) as u8); }}", x, y, x)
+        },
+        Instruction::SubN{x, y} => {
+            format!("{{ let (x_value, y_value) = (cpu.v({:#x}), cpu.v({:#x})); cpu.set_v({:#x}, y_value.wrapping_sub(x_value)); cpu.set_v(0xf, (y_value > x_value) as u8); }}", x, y, x)
+        },
+        Instruction::ShiftRight{x, y} => {
+            let source = if quirks.shift_uses_vy { y } else { x };
+            format!("{{ let value = cpu.v({:#x}); cpu.set_v({:#x}, value >> 1); cpu.set_v(0xf, value & 1); }}", source, x)
+        },
+        Instruction::ShiftLeft{x, y} => {
+            let source = if quirks.shift_uses_vy { y } else { x };
+            format!("{{ let value = cpu.v({:#x}); cpu.set_v({:#x}, value << 1); cpu.set_v(0xf, value >> 7); }}", source, x)
+        },
+        Instruction::LoadIndex(value) => format!("cpu.set_index_register({:#05x});", value),
+        Instruction::AddIndex{x} => format!("cpu.set_index_register(cpu.index_register().wrapping_add(cpu.v({:#x}) as u16));", x),
+        Instruction::JumpOffset{x, address} => {
+            let register = if quirks.jump_uses_vx { *x } else { 0 };
+            format!("cpu.set_program_counter({:#05x} + cpu.v({:#x}) as u16);", address, register)
+        },
+        instruction if instruction.is_skip() => {
+            format!("cpu.set_program_counter(if {} {{ {:#06x} }} else {{ {:#06x} }});", skip_condition(instruction), address.wrapping_add(4), address.wrapping_add(2))
+        },
+        instruction => format!("cpu.execute_at({:#06x}, Instruction::{:?}, keypad)?;", address, instruction)
+    }
+}
+
+// The match arm running the instructions from start, until one that sets the program counter
+struct Arm {
+    start: u16,
+    code: String,
+    count: u32,
+    next_address: u16,
+    sets_program_counter: bool,
+}
+
+impl Arm {
+    fn new(start: u16) -> Arm {
+        Arm{start: start, code: String::new(), count: 0, next_address: start, sets_program_counter: false}
+    }
+
+    fn push(&mut self, address: u16, instruction: &Instruction, quirks: &Quirks) {
+        self.code.push_str(&format!("            {}\n", native_code(address, instruction, quirks)));
+        self.count += 1;
+        self.next_address = address.wrapping_add(2);
+        self.sets_program_counter = match instruction {
+            Instruction::Jump(_) | Instruction::JumpOffset{..} | Instruction::Call(_) | Instruction::Return | Instruction::WaitForKey{..} => true,
+            instruction => instruction.is_skip()
+        };
+    }
+
+    fn finish(mut self) -> String {
+        if self.count == 0 {
+            return String::new();
+        }
+        if !self.sets_program_counter {
+            self.code.push_str(&format!("            cpu.set_program_counter({:#06x});\n", self.next_address));
+        }
+
+        return format!("        {:#06x} => {{\n{}            return Ok(Some({}));\n        }},\n", self.start, self.code, self.count);
+    }
+}
+
+// Every basic block found from 0x200 becomes a match arm on the program counter, that runs the block and moves to the next one.
+// The generated code assumes the rom doesn't modify itself
+pub fn recompile(rom_content: &[u8], rom_name: &str, quirks: &Quirks, chip8_path: &Path) -> GeneratedCrate {
+    let flow_graph = FlowGraph::analyse(rom_content);
+
+    let mut arms = String::new();
+    for block in flow_graph.blocks.values() {
+        let mut arm = Arm::new(block.start);
+        for decoded in &block.instructions {
+            let instruction = match decoded.instruction {
+                Some(instruction) => instruction,
+                // The interpreter reports the invalid opcode when it gets there
+                None => break
+            };
+
+            arm.push(decoded.address, &instruction, quirks);
+            // The rest of the block runs once a key is pressed, in the next frames
+            if let Instruction::WaitForKey{..} = instruction {
+                arms.push_str(&arm.finish());
+                arm = Arm::new(decoded.address.wrapping_add(2));
+            }
+        }
+
+        arms.push_str(&arm.finish());
+    }
+
+    let mut main_rs = String::new();
+    main_rs.push_str(&format!("// Generated by chip8 recompile from {}\n", rom_name));
+    main_rs.push_str("// Addresses without a block, like the targets of computed jumps, run with the interpreter\n");
+    main_rs.push_str("use chip8::cpu::CPU;\n");
+    main_rs.push_str("use chip8::errors::Chip8Error;\n");
+    main_rs.push_str("#[allow(unused_imports)]\n");
+    main_rs.push_str("use chip8::instruction::Instruction;\n");
+    main_rs.push_str("use chip8::keypad::Keypad;\n");
+    main_rs.push_str("use chip8::memory::AddressPolicy;\n");
+    main_rs.push_str("use chip8::quirks::Quirks;\n");
+    main_rs.push_str("use chip8::runtime;\n\n");
+    main_rs.push_str("const ROM: &[u8] = include_bytes!(\"../rom.ch8\");\n\n");
+    main_rs.push_str("fn main() {\n");
+    main_rs.push_str(&format!("    let quirks = Quirks{{shift_uses_vy: {}, load_store_increments_index: {}, jump_uses_vx: {}, logic_resets_vf: {}, clip_sprites: {},\n",
+        quirks.shift_uses_vy, quirks.load_store_increments_index, quirks.jump_uses_vx, quirks.logic_resets_vf, quirks.clip_sprites));
+    main_rs.push_str(&format!("        stack_depth: {}, memory_stack: {}, memory_size: {}, address_policy: AddressPolicy::{:?}}};\n",
+        quirks.stack_depth, quirks.memory_stack, quirks.memory_size, quirks.address_policy));
+    main_rs.push_str(&format!("    runtime::main({:?}, ROM, quirks, run_block);\n", rom_name));
+    main_rs.push_str("}\n\n");
+    main_rs.push_str("#[allow(unused_variables)]\n");
+    main_rs.push_str("fn run_block(cpu: &mut CPU, keypad: &Keypad) -> Result<Option<u32>, Chip8Error> {\n");
+    main_rs.push_str("    match cpu.program_counter() {\n");
+    main_rs.push_str(&arms);
+    main_rs.push_str("        _ => return Ok(None)\n");
+    main_rs.push_str("    }\n");
+    main_rs.push_str("}\n");
+
+    let mut cargo_toml = String::new();
+    cargo_toml.push_str("[package]\n");
+    cargo_toml.push_str(&format!("name = \"{}\"\n", package_name(rom_name)));
+    cargo_toml.push_str("version = \"0.1.0\"\n");
+    cargo_toml.push_str("edition = \"2021\"\n\n");
+    cargo_toml.push_str("[dependencies]\n");
+    cargo_toml.push_str(&format!("chip8 = {{ path = {} }}\n\n", toml::Value::String(chip8_path.to_string_lossy().to_string())));
+    cargo_toml.push_str("[profile.release]\n");
+    cargo_toml.push_str("lto = true\n\n");
+    // Keeps the crate out of any workspace it is generated in
+    cargo_toml.push_str("[workspace]\n");
+
+    return GeneratedCrate{cargo_toml: cargo_toml, main_rs: main_rs};
+}
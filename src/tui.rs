@@ -0,0 +1,256 @@
+use crate::config::{Config, TerminalCharset};
+use crate::cpu::CPU;
+use crate::framebuffer::Framebuffer;
+use crate::keypad::Keypad;
+
+use crossterm::cursor::{Hide, MoveTo, Show};
+use crossterm::event::{self, Event, KeyCode, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags, PopKeyboardEnhancementFlags, PushKeyboardEnhancementFlags};
+use crossterm::style::Print;
+use crossterm::terminal::{self, Clear, ClearType, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{execute, queue};
+
+use std::collections::HashMap;
+use std::io::{self, Stdout, Write};
+use std::time::{Duration, Instant};
+
+// Braille dot bits by pixel, column then row in the 2x4 cell
+const BRAILLE_DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+const STATUS_LINE: &str = "Esc quit | F5 pause";
+const PANEL_STACK_FRAMES: usize = 4;
+
+pub enum TerminalEvent {
+    Quit,
+    TogglePause,
+}
+
+// Renders the framebuffer and a registers panel with text, and reads the keypad from the terminal input
+pub struct TerminalFrontend {
+    stdout: Stdout,
+    charset: TerminalCharset,
+    key_bindings: HashMap<KeyCode, u8>,
+    // When each chip8 key is released, pressed keys are held until no repeat of them arrives for key_hold
+    key_release_times: [Option<Instant>; 16],
+    // Keys held until the terminal reports their release
+    pressed_keys: Keypad,
+    key_hold: Duration,
+    // Terminals with the kitty keyboard protocol report releases, the others only report presses
+    reports_key_releases: bool,
+    bell: bool,
+    was_sound_active: bool,
+    // Lines drawn on the previous frame, only the lines that changed are written again
+    drawn_lines: Vec<String>,
+    // Text set by the scripts, shown under the registers
+    hud: String,
+}
+
+// The SDL key names of the configuration, as the terminal reports them
+fn parse_key_name(key_name: &str) -> Option<KeyCode> {
+    let mut chars = key_name.chars();
+    if let (Some(key_char), None) = (chars.next(), chars.next()) {
+        return Some(KeyCode::Char(key_char.to_ascii_lowercase()));
+    }
+
+    match key_name.to_lowercase().as_str() {
+        "space" => Some(KeyCode::Char(' ')),
+        "return" => Some(KeyCode::Enter),
+        "tab" => Some(KeyCode::Tab),
+        "backspace" => Some(KeyCode::Backspace),
+        "up" => Some(KeyCode::Up),
+        "down" => Some(KeyCode::Down),
+        "left" => Some(KeyCode::Left),
+        "right" => Some(KeyCode::Right),
+        _ => None
+    }
+}
+
+fn render_half_blocks(framebuffer: &Framebuffer) -> Vec<String> {
+    let mut lines = Vec::new();
+    for row in (0..framebuffer.height()).step_by(2) {
+        let line: String = (0..framebuffer.width()).map(|x| {
+            let top = framebuffer.get_pixel(x, row);
+            let bottom = row + 1 < framebuffer.height() && framebuffer.get_pixel(x, row + 1);
+            match (top, bottom) {
+                (false, false) => ' ',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (true, true) => '█'
+            }
+        }).collect();
+        lines.push(line);
+    }
+
+    return lines;
+}
+
+fn render_braille(framebuffer: &Framebuffer) -> Vec<String> {
+    let mut lines = Vec::new();
+    for cell_y in (0..framebuffer.height()).step_by(4) {
+        let mut line = String::new();
+        for cell_x in (0..framebuffer.width()).step_by(2) {
+            let mut dots = 0;
+            for (column, column_dots) in BRAILLE_DOTS.iter().enumerate() {
+                for (row, dot) in column_dots.iter().enumerate() {
+                    let (x, y) = (cell_x + column, cell_y + row);
+                    if x < framebuffer.width() && y < framebuffer.height() && framebuffer.get_pixel(x, y) {
+                        dots |= dot;
+                    }
+                }
+            }
+            line.push(char::from_u32(0x2800 + dots).unwrap_or(' '));
+        }
+        lines.push(line);
+    }
+
+    return lines;
+}
+
+fn render_panel(cpu: &CPU, paused: bool, hud: &str) -> Vec<String> {
+    let context = cpu.machine_context(cpu.program_counter());
+    let (delay_timer, sound_timer) = cpu.timers();
+
+    let mut panel = Vec::new();
+    panel.push(format!("PC {:#06x}  I {:#06x}", context.program_counter, context.index_register));
+    panel.push(format!("Opcode {:#06x}", context.opcode));
+    for (row, values) in context.registers.chunks(4).enumerate() {
+        let registers: Vec<String> = values.iter().enumerate().map(|(index, value)| format!("V{:X} {:02x}", row * 4 + index, value)).collect();
+        panel.push(registers.join(" "));
+    }
+    panel.push(format!("DT {:3}  ST {:3}", delay_timer, sound_timer));
+
+    let mut stack: Vec<String> = context.stack_frames.iter().rev().take(PANEL_STACK_FRAMES).map(|frame| format!("{:#06x}", frame)).collect();
+    if context.stack_frames.len() > PANEL_STACK_FRAMES {
+        stack.push(String::from(".."));
+    }
+    panel.push(format!("SP {} {}", context.stack_frames.len(), stack.join(" ")));
+    panel.push(String::from(if paused { "PAUSED" } else { "" }));
+    panel.push(hud.chars().take(24).collect());
+
+    return panel;
+}
+
+impl TerminalFrontend {
+    pub fn new(config: &Config) -> Result<TerminalFrontend, String> {
+        let mut key_bindings = HashMap::new();
+        for (chip_key, real_key_name) in config.key_map()? {
+            match parse_key_name(&real_key_name) {
+                Some(key_code) => {
+                    key_bindings.insert(key_code, chip_key);
+                },
+                None => warn!("TERMINAL | Key \"{}\" of chip8 key {:X} can't be read from a terminal, it is not bound", real_key_name, chip_key)
+            }
+        }
+
+        let mut stdout = io::stdout();
+        terminal::enable_raw_mode().map_err(|e| format!("Failed setting up the terminal : {}", e))?;
+        execute!(stdout, EnterAlternateScreen, Hide, Clear(ClearType::All)).map_err(|e| format!("Failed setting up the terminal : {}", e))?;
+
+        let reports_key_releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if reports_key_releases {
+            execute!(stdout, PushKeyboardEnhancementFlags(KeyboardEnhancementFlags::REPORT_EVENT_TYPES)).map_err(|e| e.to_string())?;
+            info!("TERMINAL | The terminal reports key releases");
+        }
+
+        return Ok(TerminalFrontend{stdout: stdout, charset: config.terminal.charset, key_bindings: key_bindings, key_release_times: [None; 16], pressed_keys: Keypad::new(),
+            key_hold: Duration::from_millis(config.terminal.key_hold_millis), reports_key_releases: reports_key_releases,
+            bell: config.terminal.bell && config.audio.enabled, was_sound_active: false, drawn_lines: Vec::new(), hud: String::new()});
+    }
+
+    // Reads the pending terminal input without blocking
+    pub fn poll_events(&mut self) -> Result<Vec<TerminalEvent>, String> {
+        let mut events = Vec::new();
+        while event::poll(Duration::ZERO).map_err(|e| e.to_string())? {
+            match event::read().map_err(|e| e.to_string())? {
+                Event::Key(key_event) => {
+                    let is_release = key_event.kind == KeyEventKind::Release;
+                    match key_event.code {
+                        KeyCode::Esc if !is_release => events.push(TerminalEvent::Quit),
+                        KeyCode::Char('c') if key_event.modifiers.contains(KeyModifiers::CONTROL) => events.push(TerminalEvent::Quit),
+                        KeyCode::F(5) if key_event.kind == KeyEventKind::Press => events.push(TerminalEvent::TogglePause),
+                        key_code => {
+                            if let Some(chip_key) = self.key_bindings.get(&key_code).copied() {
+                                if self.reports_key_releases {
+                                    self.pressed_keys.set_pressed(chip_key, !is_release);
+                                } else {
+                                    self.key_release_times[chip_key as usize] = Some(Instant::now() + self.key_hold);
+                                }
+                            }
+                        }
+                    }
+                },
+                // Everything is drawn again on the new size
+                Event::Resize(..) => {
+                    self.drawn_lines.clear();
+                    queue!(self.stdout, Clear(ClearType::All)).map_err(|e| e.to_string())?;
+                },
+                _ => {}
+            }
+        }
+
+        return Ok(events);
+    }
+
+    pub fn keypad_state(&self) -> Keypad {
+        let now = Instant::now();
+        let mut keypad = self.pressed_keys;
+        for (chip_key, release_time) in self.key_release_times.iter().enumerate() {
+            if release_time.is_some_and(|release_time| now < release_time) {
+                keypad.set_pressed(chip_key as u8, true);
+            }
+        }
+
+        return keypad;
+    }
+
+    pub fn set_hud(&mut self, text: &str) {
+        self.hud = text.to_string();
+    }
+
+    pub fn render(&mut self, cpu: &CPU, paused: bool) -> Result<(), String> {
+        let screen = match self.charset {
+            TerminalCharset::HalfBlock => render_half_blocks(cpu.framebuffer()),
+            TerminalCharset::Braille => render_braille(cpu.framebuffer())
+        };
+        let panel = render_panel(cpu, paused, &self.hud);
+        let screen_width = screen.first().map_or(0, |line| line.chars().count());
+
+        let mut lines = Vec::new();
+        for index in 0..screen.len().max(panel.len()) {
+            let screen_line = screen.get(index).cloned().unwrap_or_else(|| " ".repeat(screen_width));
+            let panel_line = panel.get(index).map(|line| line.as_str()).unwrap_or("");
+            lines.push(format!("{} │ {:<24}", screen_line, panel_line));
+        }
+        lines.push(format!("{:<width$}", STATUS_LINE, width = screen_width + 27));
+
+        // The resolution changed, the old lines may be longer than the new ones
+        if lines.len() != self.drawn_lines.len() || lines.first().map(|line| line.chars().count()) != self.drawn_lines.first().map(|line| line.chars().count()) {
+            self.drawn_lines.clear();
+            queue!(self.stdout, Clear(ClearType::All)).map_err(|e| e.to_string())?;
+        }
+
+        for (row, line) in lines.iter().enumerate() {
+            if self.drawn_lines.get(row) != Some(line) {
+                queue!(self.stdout, MoveTo(0, row as u16), Print(line)).map_err(|e| e.to_string())?;
+            }
+        }
+        self.drawn_lines = lines;
+
+        let is_sound_active = cpu.is_sound_active() && !paused;
+        if self.bell && is_sound_active && !self.was_sound_active {
+            queue!(self.stdout, Print('\x07')).map_err(|e| e.to_string())?;
+        }
+        self.was_sound_active = is_sound_active;
+
+        return self.stdout.flush().map_err(|e| e.to_string());
+    }
+}
+
+// The terminal is restored even when emulation stops with an error
+impl Drop for TerminalFrontend {
+    fn drop(&mut self) {
+        if self.reports_key_releases {
+            let _ = execute!(self.stdout, PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(self.stdout, Show, LeaveAlternateScreen);
+        let _ = terminal::disable_raw_mode();
+    }
+}
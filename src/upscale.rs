@@ -0,0 +1,272 @@
+use crate::palette::Rgb;
+
+pub const UPSCALER_NAMES: [&str; 5] = ["nearest", "scale2x", "scale3x", "scale4x", "hq2x"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Upscaler {
+    Nearest,
+    Scale2x,
+    Scale3x,
+    Scale4x,
+    Hq2x,
+}
+
+impl Upscaler {
+    pub fn from_name(upscaler_name: &str) -> Result<Upscaler, String> {
+        match upscaler_name.to_lowercase().as_str() {
+            "nearest" | "" => Ok(Upscaler::Nearest),
+            "scale2x" | "epx" => Ok(Upscaler::Scale2x),
+            "scale3x" => Ok(Upscaler::Scale3x),
+            "scale4x" => Ok(Upscaler::Scale4x),
+            "hq2x" => Ok(Upscaler::Hq2x),
+            _ => Err(format!("Unknown upscaler \"{}\", expected one of {:?}", upscaler_name, UPSCALER_NAMES))
+        }
+    }
+
+    pub fn factor(&self) -> usize {
+        match self {
+            Upscaler::Nearest => 1,
+            Upscaler::Scale2x | Upscaler::Hq2x => 2,
+            Upscaler::Scale3x => 3,
+            Upscaler::Scale4x => 4,
+        }
+    }
+
+    // Returns the upscaled image, of factor() times the width and height
+    pub fn apply(&self, colors: &[Rgb], width: usize, height: usize) -> Vec<Rgb> {
+        match self {
+            Upscaler::Nearest => colors.to_vec(),
+            Upscaler::Scale2x => scale2x(colors, width, height),
+            Upscaler::Scale3x => scale3x(colors, width, height),
+            Upscaler::Scale4x => scale2x(&scale2x(colors, width, height), width * 2, height * 2),
+            Upscaler::Hq2x => hq2x(colors, width, height),
+        }
+    }
+}
+
+// Neighbour of a pixel, the edge pixels are repeated outside of the image
+fn neighbour(colors: &[Rgb], width: usize, height: usize, x: usize, y: usize, dx: isize, dy: isize) -> Rgb {
+    let nx = (x as isize + dx).clamp(0, width as isize - 1) as usize;
+    let ny = (y as isize + dy).clamp(0, height as isize - 1) as usize;
+    return colors[ny * width + nx];
+}
+
+// Scale2x / EPX, every pixel becomes 2x2 pixels that follow the diagonal edges around it
+pub fn scale2x(colors: &[Rgb], width: usize, height: usize) -> Vec<Rgb> {
+    let out_width = width * 2;
+    let mut scaled = vec![(0, 0, 0); out_width * height * 2];
+
+    for y in 0..height {
+        for x in 0..width {
+            let p = colors[y * width + x];
+            let a = neighbour(colors, width, height, x, y, 0, -1);
+            let b = neighbour(colors, width, height, x, y, 1, 0);
+            let c = neighbour(colors, width, height, x, y, -1, 0);
+            let d = neighbour(colors, width, height, x, y, 0, 1);
+
+            let top_left = if c == a && c != d && a != b { a } else { p };
+            let top_right = if a == b && a != c && b != d { b } else { p };
+            let bottom_left = if d == c && d != b && c != a { c } else { p };
+            let bottom_right = if b == d && b != a && d != c { d } else { p };
+
+            scaled[(y * 2) * out_width + x * 2] = top_left;
+            scaled[(y * 2) * out_width + x * 2 + 1] = top_right;
+            scaled[(y * 2 + 1) * out_width + x * 2] = bottom_left;
+            scaled[(y * 2 + 1) * out_width + x * 2 + 1] = bottom_right;
+        }
+    }
+
+    return scaled;
+}
+
+// Scale3x, every pixel becomes 3x3 pixels that follow the diagonal edges around it
+pub fn scale3x(colors: &[Rgb], width: usize, height: usize) -> Vec<Rgb> {
+    let out_width = width * 3;
+    let mut scaled = vec![(0, 0, 0); out_width * height * 3];
+
+    for y in 0..height {
+        for x in 0..width {
+            // A B C
+            // D E F
+            // G H I
+            let a = neighbour(colors, width, height, x, y, -1, -1);
+            let b = neighbour(colors, width, height, x, y, 0, -1);
+            let c = neighbour(colors, width, height, x, y, 1, -1);
+            let d = neighbour(colors, width, height, x, y, -1, 0);
+            let e = colors[y * width + x];
+            let f = neighbour(colors, width, height, x, y, 1, 0);
+            let g = neighbour(colors, width, height, x, y, -1, 1);
+            let h = neighbour(colors, width, height, x, y, 0, 1);
+            let i = neighbour(colors, width, height, x, y, 1, 1);
+
+            let mut block = [e; 9];
+            if b != h && d != f {
+                block[0] = if d == b { d } else { e };
+                block[1] = if (d == b && e != c) || (b == f && e != a) { b } else { e };
+                block[2] = if b == f { f } else { e };
+                block[3] = if (d == b && e != g) || (d == h && e != a) { d } else { e };
+                block[5] = if (b == f && e != i) || (h == f && e != c) { f } else { e };
+                block[6] = if d == h { d } else { e };
+                block[7] = if (d == h && e != i) || (h == f && e != g) { h } else { e };
+                block[8] = if h == f { f } else { e };
+            }
+
+            for (index, color) in block.iter().enumerate() {
+                scaled[(y * 3 + index / 3) * out_width + x * 3 + index % 3] = *color;
+            }
+        }
+    }
+
+    return scaled;
+}
+
+fn to_yuv(color: Rgb) -> (f32, f32, f32) {
+    let (r, g, b) = (color.0 as f32, color.1 as f32, color.2 as f32);
+    return ((r + g + b) / 3.0, (r - b) / 4.0 + 128.0, (2.0 * g - r - b) / 8.0 + 128.0);
+}
+
+// Colors are alike when they are close in YUV space, like the hqx filters compare them
+fn is_similar(first: Rgb, second: Rgb) -> bool {
+    let (first_y, first_u, first_v) = to_yuv(first);
+    let (second_y, second_u, second_v) = to_yuv(second);
+    return (first_y - second_y).abs() <= 48.0 && (first_u - second_u).abs() <= 7.0 && (first_v - second_v).abs() <= 6.0;
+}
+
+fn blend(main: Rgb, other: Rgb) -> Rgb {
+    let mix = |main: u8, other: u8| ((main as u16 + other as u16 * 3) / 4) as u8;
+    return (mix(main.0, other.0), mix(main.1, other.1), mix(main.2, other.2));
+}
+
+// Scale2x with the hqx color comparison, the corners on a diagonal edge are blended instead of copied
+pub fn hq2x(colors: &[Rgb], width: usize, height: usize) -> Vec<Rgb> {
+    let out_width = width * 2;
+    let mut scaled = vec![(0, 0, 0); out_width * height * 2];
+
+    for y in 0..height {
+        for x in 0..width {
+            let p = colors[y * width + x];
+            let a = neighbour(colors, width, height, x, y, 0, -1);
+            let b = neighbour(colors, width, height, x, y, 1, 0);
+            let c = neighbour(colors, width, height, x, y, -1, 0);
+            let d = neighbour(colors, width, height, x, y, 0, 1);
+
+            let corner = |first: Rgb, second: Rgb, opposite_first: Rgb, opposite_second: Rgb| {
+                if is_similar(first, second) && !is_similar(first, opposite_first) && !is_similar(second, opposite_second) {
+                    blend(p, first)
+                } else {
+                    p
+                }
+            };
+
+            scaled[(y * 2) * out_width + x * 2] = corner(c, a, d, b);
+            scaled[(y * 2) * out_width + x * 2 + 1] = corner(a, b, c, d);
+            scaled[(y * 2 + 1) * out_width + x * 2] = corner(d, c, b, a);
+            scaled[(y * 2 + 1) * out_width + x * 2 + 1] = corner(b, d, a, c);
+        }
+    }
+
+    return scaled;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Rgb = (0, 0, 0);
+    const WHITE: Rgb = (255, 255, 255);
+
+    // '#' is a white pixel and '.' a black one
+    fn image(rows: &[&str]) -> (Vec<Rgb>, usize, usize) {
+        let colors = rows.iter().flat_map(|row| row.chars().map(|pixel| if pixel == '#' { WHITE } else { BLACK })).collect();
+        return (colors, rows[0].len(), rows.len());
+    }
+
+    fn rows(colors: &[Rgb], width: usize) -> Vec<String> {
+        return colors.chunks(width)
+            .map(|row| row.iter().map(|color| if *color == WHITE { '#' } else if *color == BLACK { '.' } else { '?' }).collect())
+            .collect();
+    }
+
+    const SINGLE_PIXEL: [&str; 3] = ["...", ".#.", "..."];
+    const DIAGONAL: [&str; 3] = ["#..", "##.", "###"];
+
+    #[test]
+    fn scale2x_keeps_a_single_pixel_square() {
+        let (colors, width, height) = image(&SINGLE_PIXEL);
+        let scaled = scale2x(&colors, width, height);
+        assert_eq!(rows(&scaled, width * 2), ["......", "......", "..##..", "..##..", "......", "......"]);
+    }
+
+    #[test]
+    fn scale2x_smooths_a_diagonal_edge() {
+        let (colors, width, height) = image(&DIAGONAL);
+        let scaled = scale2x(&colors, width, height);
+        assert_eq!(rows(&scaled, width * 2), ["##....", "###...", "###...", "#####.", "######", "######"]);
+    }
+
+    #[test]
+    fn scale3x_keeps_a_single_pixel_square() {
+        let (colors, width, height) = image(&SINGLE_PIXEL);
+        let scaled = scale3x(&colors, width, height);
+        assert_eq!(rows(&scaled, width * 3), [".........", ".........", ".........", "...###...", "...###...", "...###...",
+            ".........", ".........", "........."]);
+    }
+
+    #[test]
+    fn scale3x_smooths_a_diagonal_edge() {
+        let (colors, width, height) = image(&DIAGONAL);
+        let scaled = scale3x(&colors, width, height);
+        assert_eq!(rows(&scaled, width * 3), ["###......", "####.....", "####.....", "#####....", "######...", "########.",
+            "#########", "#########", "#########"]);
+    }
+
+    #[test]
+    fn scale4x_rounds_a_single_pixel() {
+        // The second scale2x pass cuts the corners of the 2x2 block the first one made
+        let (colors, width, height) = image(&SINGLE_PIXEL);
+        let scaled = Upscaler::Scale4x.apply(&colors, width, height);
+        let mut expected = vec!["............"; 12];
+        expected[4] = ".....##.....";
+        expected[5] = "....####....";
+        expected[6] = "....####....";
+        expected[7] = ".....##.....";
+        assert_eq!(rows(&scaled, width * 4), expected);
+    }
+
+    #[test]
+    fn scale4x_smooths_a_diagonal_edge() {
+        let (colors, width, height) = image(&DIAGONAL);
+        let scaled = Upscaler::Scale4x.apply(&colors, width, height);
+        assert_eq!(rows(&scaled, width * 4), ["####........", "#####.......", "#####.......", "######......", "######......",
+            "#######.....", "#########...", "###########.", "############", "############", "############", "############"]);
+    }
+
+    #[test]
+    fn hq2x_keeps_a_single_pixel_square() {
+        let (colors, width, height) = image(&SINGLE_PIXEL);
+        let scaled = hq2x(&colors, width, height);
+        assert_eq!(rows(&scaled, width * 2), ["......", "......", "..##..", "..##..", "......", "......"]);
+    }
+
+    #[test]
+    fn hq2x_blends_the_corners_of_a_diagonal_edge() {
+        let (colors, width, height) = image(&DIAGONAL);
+        let scaled = hq2x(&colors, width, height);
+        assert_eq!(rows(&scaled, width * 2), ["##....", "##?...", "###?..", "####?.", "######", "######"]);
+
+        // A quarter of the pixel's own color and three quarters of the edge color
+        assert_eq!(scaled[width * 2 + 2], (191, 191, 191));
+        assert_eq!(scaled[3 * width * 2 + 4], (191, 191, 191));
+        assert_eq!(scaled[2 * width * 2 + 3], (63, 63, 63));
+    }
+
+    #[test]
+    fn upscalers_scale_by_their_factor() {
+        let (colors, width, height) = image(&DIAGONAL);
+        for name in UPSCALER_NAMES {
+            let upscaler = Upscaler::from_name(name).unwrap();
+            let scaled = upscaler.apply(&colors, width, height);
+            assert_eq!(scaled.len(), width * height * upscaler.factor() * upscaler.factor(), "{}", name);
+        }
+    }
+}
@@ -0,0 +1,164 @@
+use std::collections::HashSet;
+use std::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AccessKind {
+    Read,
+    Write,
+    Execute,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchAction {
+    Log,
+    Pause,
+    // Runs the write hooks of the scripts, neither logged nor pausing
+    Script,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watchpoint {
+    pub start: usize,
+    pub end: usize,
+    pub read: bool,
+    pub write: bool,
+    pub execute: bool,
+    pub action: WatchAction,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MemoryEvent {
+    WatchpointHit{watchpoint: Watchpoint, access: AccessKind, address: usize, value: u8},
+    // A write into an address that was already executed as an instruction
+    SelfModifyingCode{address: usize, old_value: u8, new_value: u8},
+}
+
+// Watches the memory accesses, the events are collected until they are taken
+pub struct AccessTracker {
+    watchpoints: Vec<Watchpoint>,
+    executed: Vec<bool>,
+    report_self_modifying_code: bool,
+    reported_addresses: HashSet<usize>,
+    events: Vec<MemoryEvent>,
+}
+
+fn parse_address(address: &str) -> Result<usize, String> {
+    let digits = address.trim().trim_start_matches("0x").trim_start_matches("0X");
+    return usize::from_str_radix(digits, 16).map_err(|_| format!("Invalid address \"{}\", expected a hex number", address));
+}
+
+impl Watchpoint {
+    // Parses "<access>:<start>[-<end>][:<action>]", like "w:0x300-0x30F:pause" or "x:0x200"
+    pub fn parse(spec: &str) -> Result<Watchpoint, String> {
+        let parts: Vec<&str> = spec.split(':').collect();
+        if parts.len() < 2 || parts.len() > 3 {
+            return Err(format!("Invalid watchpoint \"{}\", expected <access>:<start>[-<end>][:<action>]", spec));
+        }
+
+        let access = parts[0].to_lowercase();
+        if access.is_empty() || access.chars().any(|c| !"rwx".contains(c)) {
+            return Err(format!("Invalid watchpoint access \"{}\", expected a combination of r, w and x", parts[0]));
+        }
+
+        let (start, end) = match parts[1].split_once('-') {
+            Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+            None => (parse_address(parts[1])?, parse_address(parts[1])?)
+        };
+        if end < start {
+            return Err(format!("Invalid watchpoint range \"{}\", the end is before the start", parts[1]));
+        }
+
+        let action = match parts.get(2).map(|action| action.to_lowercase()).as_deref() {
+            None | Some("log") => WatchAction::Log,
+            Some("pause") => WatchAction::Pause,
+            Some(action) => return Err(format!("Invalid watchpoint action \"{}\", expected log or pause", action))
+        };
+
+        return Ok(Watchpoint{start: start, end: end, read: access.contains('r'), write: access.contains('w'),
+            execute: access.contains('x'), action: action});
+    }
+
+    pub fn matches(&self, access: AccessKind, address: usize) -> bool {
+        let watches_access = match access {
+            AccessKind::Read => self.read,
+            AccessKind::Write => self.write,
+            AccessKind::Execute => self.execute,
+        };
+
+        return watches_access && address >= self.start && address <= self.end;
+    }
+}
+
+impl MemoryEvent {
+    pub fn pauses(&self) -> bool {
+        match self {
+            MemoryEvent::WatchpointHit{watchpoint, ..} => watchpoint.action == WatchAction::Pause,
+            MemoryEvent::SelfModifyingCode{..} => false
+        }
+    }
+
+    pub fn is_script_hook(&self) -> bool {
+        match self {
+            MemoryEvent::WatchpointHit{watchpoint, ..} => watchpoint.action == WatchAction::Script,
+            MemoryEvent::SelfModifyingCode{..} => false
+        }
+    }
+}
+
+impl fmt::Display for MemoryEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MemoryEvent::WatchpointHit{watchpoint, access, address, value} => {
+                write!(f, "{:?} of {:#04x} at {:#06x} hit watchpoint {:#06x}-{:#06x}", access, value, address, watchpoint.start, watchpoint.end)
+            },
+            MemoryEvent::SelfModifyingCode{address, old_value, new_value} => {
+                write!(f, "Self modifying code, executed address {:#06x} changed from {:#04x} to {:#04x}", address, old_value, new_value)
+            }
+        }
+    }
+}
+
+impl AccessTracker {
+    pub fn new(memory_size: usize) -> AccessTracker {
+        AccessTracker{watchpoints: Vec::new(), executed: vec![false; memory_size], report_self_modifying_code: false,
+            reported_addresses: HashSet::new(), events: Vec::new()}
+    }
+
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        self.watchpoints.push(watchpoint);
+    }
+
+    pub fn set_report_self_modifying_code(&mut self, report_self_modifying_code: bool) {
+        self.report_self_modifying_code = report_self_modifying_code;
+    }
+
+    fn check_watchpoints(&mut self, access: AccessKind, address: usize, value: u8) {
+        for watchpoint in &self.watchpoints {
+            if watchpoint.matches(access, address) {
+                self.events.push(MemoryEvent::WatchpointHit{watchpoint: watchpoint.clone(), access: access, address: address, value: value});
+            }
+        }
+    }
+
+    pub fn on_read(&mut self, address: usize, value: u8) {
+        self.check_watchpoints(AccessKind::Read, address, value);
+    }
+
+    pub fn on_write(&mut self, address: usize, old_value: u8, new_value: u8) {
+        self.check_watchpoints(AccessKind::Write, address, new_value);
+
+        // Every modified address is reported once, loops that patch themselves would flood the log otherwise
+        if self.report_self_modifying_code && self.executed[address] && old_value != new_value && self.reported_addresses.insert(address) {
+            self.events.push(MemoryEvent::SelfModifyingCode{address: address, old_value: old_value, new_value: new_value});
+        }
+    }
+
+    pub fn on_execute(&mut self, address: usize, value: u8) {
+        self.executed[address] = true;
+        self.check_watchpoints(AccessKind::Execute, address, value);
+    }
+
+    pub fn take_events(&mut self) -> Vec<MemoryEvent> {
+        return std::mem::take(&mut self.events);
+    }
+}
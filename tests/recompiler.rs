@@ -0,0 +1,190 @@
+// The recompiler has to leave the machine exactly like the interpreter does, every frame of every rom
+use chip8::cpu::CPU;
+use chip8::keypad::Keypad;
+use chip8::memory::Memory;
+use chip8::quirks::{Quirks, QUIRK_PROFILES};
+use chip8::watchpoints::MemoryEvent;
+
+use std::fs;
+
+const FRAMES: usize = 600;
+const INSTRUCTIONS_PER_FRAME: u32 = 15;
+const SEED: u64 = 0xC8;
+
+fn new_cpu(rom_content: &[u8], quirks: Quirks, use_recompiler: bool) -> CPU {
+    let mut memory = Memory::new_from_rom(rom_content.to_vec(), quirks.memory_size, quirks.address_policy).unwrap();
+    memory.set_report_self_modifying_code(true);
+    let mut cpu = CPU::new(memory, quirks);
+    cpu.set_seed(SEED);
+    if use_recompiler {
+        cpu.enable_recompiler();
+    }
+    return cpu;
+}
+
+// Holds a different key every half second, and nothing in between, so that roms waiting for keys keep going
+fn keypad_at(frame: usize) -> Keypad {
+    let mut keypad = Keypad::new();
+    if (frame / 30) % 2 == 1 {
+        keypad.set_pressed(((frame / 60) % 16) as u8, true);
+    }
+    return keypad;
+}
+
+fn run_frame(cpu: &mut CPU, keypad: &Keypad) -> Result<(), String> {
+    let mut executed = 0;
+    while executed < INSTRUCTIONS_PER_FRAME {
+        executed += cpu.step(keypad, INSTRUCTIONS_PER_FRAME - executed).map_err(|e| e.to_string())?;
+    }
+    cpu.tick_timers();
+    return Ok(());
+}
+
+fn compare_engines(rom_name: &str, rom_content: &[u8], profile: &str) {
+    let quirks = Quirks::from_profile(profile).unwrap();
+    let mut interpreter = new_cpu(rom_content, quirks, false);
+    let mut recompiler = new_cpu(rom_content, quirks, true);
+
+    for frame in 0..FRAMES {
+        let keypad = keypad_at(frame);
+        let interpreter_result = run_frame(&mut interpreter, &keypad);
+        let recompiler_result = run_frame(&mut recompiler, &keypad);
+        assert_eq!(interpreter_result, recompiler_result, "{} with {} quirks, frame {}", rom_name, profile, frame);
+        assert!(interpreter.save_state().to_bytes() == recompiler.save_state().to_bytes(),
+            "{} with {} quirks differs after frame {}\ninterpreter {}\nrecompiler  {}", rom_name, profile, frame,
+            interpreter.machine_context(interpreter.program_counter()), recompiler.machine_context(recompiler.program_counter()));
+
+        // Both stopped on the same error
+        if interpreter_result.is_err() {
+            break;
+        }
+    }
+}
+
+fn compare_engines_with_every_profile(rom_name: &str) {
+    let rom_content = fs::read(format!("{}/roms/{}", env!("CARGO_MANIFEST_DIR"), rom_name)).unwrap();
+    for profile in QUIRK_PROFILES {
+        compare_engines(rom_name, &rom_content, profile);
+    }
+}
+
+#[test]
+fn ibm_logo() {
+    compare_engines_with_every_profile("ibm_logo.ch8");
+}
+
+#[test]
+fn test_opcode() {
+    compare_engines_with_every_profile("test_opcode.ch8");
+}
+
+#[test]
+fn keypad_test() {
+    compare_engines_with_every_profile("keypad_test.ch8");
+}
+
+#[test]
+fn pong() {
+    compare_engines_with_every_profile("pong.ch8");
+}
+
+#[test]
+fn breakout() {
+    compare_engines_with_every_profile("breakout.ch8");
+}
+
+#[test]
+fn particle_demo() {
+    compare_engines_with_every_profile("Particle_Demo.ch8");
+}
+
+#[test]
+fn zero_demo() {
+    compare_engines_with_every_profile("zero_demo.ch8");
+}
+
+#[test]
+fn self_modifying_code() {
+    // Overwrites 6111 at 0x20A with 6177 from inside the same block, so V1 has to end up as 0x77
+    let rom_content = [
+        0x60, 0x61, // 0x200: V0 = 0x61
+        0x61, 0x77, // 0x202: V1 = 0x77
+        0xA2, 0x0A, // 0x204: I = 0x20A
+        0xF1, 0x55, // 0x206: store V0-V1 at I
+        0x62, 0x00, // 0x208: V2 = 0
+        0x61, 0x11, // 0x20A: V1 = 0x11
+        0x12, 0x0C  // 0x20C: jump to 0x20C
+    ];
+    for profile in QUIRK_PROFILES {
+        compare_engines("self modifying code", &rom_content, profile);
+        let mut recompiler = new_cpu(&rom_content, Quirks::from_profile(profile).unwrap(), true);
+        run_frame(&mut recompiler, &Keypad::new()).unwrap();
+        assert_eq!(recompiler.v(1), 0x77, "{} quirks", profile);
+    }
+}
+
+#[test]
+fn self_modifying_code_is_reported() {
+    // Writes 6177 over the 6061 that already ran at 0x200
+    let rom_content = [
+        0x60, 0x61, // 0x200: V0 = 0x61
+        0x61, 0x77, // 0x202: V1 = 0x77
+        0xA2, 0x00, // 0x204: I = 0x200
+        0xF1, 0x55, // 0x206: store V0-V1 at I
+        0x12, 0x08  // 0x208: jump to 0x208
+    ];
+    let expected_events = vec![
+        (0x206, MemoryEvent::SelfModifyingCode{address: 0x200, old_value: 0x60, new_value: 0x61}),
+        (0x206, MemoryEvent::SelfModifyingCode{address: 0x201, old_value: 0x61, new_value: 0x77})
+    ];
+    for use_recompiler in [false, true] {
+        let mut cpu = new_cpu(&rom_content, Quirks::from_profile("chip8").unwrap(), use_recompiler);
+        run_frame(&mut cpu, &Keypad::new()).unwrap();
+        assert_eq!(cpu.take_memory_events(), expected_events, "recompiler {}", use_recompiler);
+    }
+}
+
+#[test]
+fn overlapping_blocks_are_dropped() {
+    // The blocks at 0x200 and 0x204 both hold 0x204, which is overwritten with 7205 before jumping back to 0x200
+    let rom_content = [
+        0x6E, 0x00, // 0x200: VE = 0
+        0x71, 0x01, // 0x202: V1 += 1
+        0x72, 0x01, // 0x204: V2 += 1
+        0x32, 0x02, // 0x206: skip if V2 == 2
+        0x12, 0x04, // 0x208: jump to 0x204
+        0x3E, 0x00, // 0x20A: skip if VE == 0
+        0x12, 0x1A, // 0x20C: jump to 0x21A
+        0x6E, 0x01, // 0x20E: VE = 1
+        0xA2, 0x04, // 0x210: I = 0x204
+        0x60, 0x72, // 0x212: V0 = 0x72
+        0x61, 0x05, // 0x214: V1 = 0x05
+        0xF1, 0x55, // 0x216: store V0-V1 at I
+        0x12, 0x00, // 0x218: jump to 0x200
+        0x12, 0x1A  // 0x21A: jump to 0x21A
+    ];
+    for profile in QUIRK_PROFILES {
+        compare_engines("overlapping blocks", &rom_content, profile);
+        let mut recompiler = new_cpu(&rom_content, Quirks::from_profile(profile).unwrap(), true);
+        // Up to the modified instruction at 0x204, run the second time through 0x200
+        let mut executed = 0;
+        while executed < 17 {
+            executed += recompiler.step(&Keypad::new(), 17 - executed).unwrap();
+        }
+        assert_eq!(recompiler.v(2), 7, "{} quirks", profile);
+    }
+}
+
+#[test]
+fn recompiled_blocks_are_profiled() {
+    let rom_content = fs::read(format!("{}/roms/pong.ch8", env!("CARGO_MANIFEST_DIR"))).unwrap();
+    let mut cpu = new_cpu(&rom_content, Quirks::default(), true);
+    cpu.enable_profiler();
+    for frame in 0..60 {
+        run_frame(&mut cpu, &keypad_at(frame)).unwrap();
+    }
+
+    let report = cpu.profiler().unwrap().report();
+    assert_eq!(report.instructions, 60 * INSTRUCTIONS_PER_FRAME as u64);
+    assert!(report.other_instructions_seconds > 0.0);
+}